@@ -0,0 +1,69 @@
+//! Assembles the firmware in `src/hardware/firmware` into `$OUT_DIR/firmware`, so mistakes
+//! in it fail the build instead of panicking once the game is running.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process;
+
+#[allow(dead_code)]
+#[path = "src/hardware/dcpu/mod.rs"]
+mod dcpu;
+
+use dcpu::assembler::AssemblerOptions;
+use dcpu::assembler::embed;
+
+const FIRMWARE_DIR: &str = "src/hardware/firmware";
+
+
+fn main() {
+    let out_dir = Path::new(&env::var("OUT_DIR").unwrap()).join("firmware");
+    fs::create_dir_all(&out_dir).unwrap();
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let firmware_dir = Path::new(&manifest_dir).join(FIRMWARE_DIR);
+
+    // The output depends on the assembler as much as the source.
+    println!("cargo:rerun-if-changed={}", firmware_dir.display());
+    println!("cargo:rerun-if-changed={}", Path::new(&manifest_dir).join("src/hardware/dcpu")
+             .display());
+
+    let mut failed = false;
+    for entry in fs::read_dir(&firmware_dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(true, |e| e != "dasm") {
+            continue;
+        }
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let mut source = String::new();
+        File::open(&path).and_then(|mut f| f.read_to_string(&mut source)).unwrap();
+        let options = AssemblerOptions {
+            file_name: format!("{}/{}", FIRMWARE_DIR,
+                               path.file_name().unwrap().to_string_lossy()),
+            optimize: true,
+            ..AssemblerOptions::default()
+        };
+        match embed::embed(&source, &options) {
+            Ok(embedded) => {
+                for warning in &embedded.warnings {
+                    // Cargo reads one line at a time.
+                    for line in warning.lines() {
+                        println!("cargo:warning={}", line);
+                    }
+                }
+                let out_path = out_dir.join(path.with_extension("rs").file_name().unwrap());
+                File::create(&out_path)
+                    .and_then(|mut f| f.write_all(embedded.rust.as_bytes()))
+                    .unwrap();
+            },
+            Err(diagnostics) => {
+                eprintln!("{}", diagnostics);
+                failed = true;
+            },
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,13 @@
+#version 330 core
+
+uniform float brightness;
+
+in vec3 frag_color_in;
+
+out vec4 frag_color;
+
+void main() {
+    // Lines give off their own light, so they aren't shaded.  They're added to what's
+    // behind them, so alpha doesn't matter.
+    frag_color = vec4(frag_color_in * brightness, 1.0);
+}
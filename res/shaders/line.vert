@@ -0,0 +1,15 @@
+#version 330 core
+
+uniform mat4 projection_matrix;
+uniform mat4 view_matrix;
+uniform mat4 model_matrix;
+
+in vec3 position;
+in vec3 color;
+
+out vec3 frag_color_in;
+
+void main() {
+    frag_color_in = color;
+    gl_Position = projection_matrix * view_matrix * model_matrix * vec4(position, 1.0);
+}
@@ -0,0 +1,566 @@
+//! Language server for DCPU-16 assembly, speaking LSP over stdin and stdout.
+//!
+//! Documents are kept in full sync, and reassembled on every change to publish
+//! diagnostics.  Label and define lookups go through `assembler::index`, and hovering
+//! over an instruction only assembles its own line, so they keep working while the
+//! document has errors in it.
+
+#[macro_use]
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use serde_json::Value;
+
+#[allow(dead_code)]
+#[path = "../hardware/dcpu/mod.rs"]
+mod dcpu;
+
+use dcpu::assembler::{self, AssemblerOptions, OPERAND_KEYWORDS, PSEUDO_OP_NAMES};
+use dcpu::assembler::diagnostic::{AssemblerDiagnostic, Severity};
+use dcpu::assembler::index::{self, SourceIndex, SymbolKind};
+use dcpu::instruction::Instruction;
+use dcpu::op::{BASIC_OP_NAMES, SPECIAL_OP_NAMES};
+use dcpu::register;
+
+// Error codes from the spec.
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// `CompletionItemKind`s from the spec.
+const COMPLETION_KEYWORD: u32 = 14;
+const COMPLETION_VARIABLE: u32 = 6;
+const COMPLETION_REFERENCE: u32 = 18;
+
+
+struct Server {
+    /// The text of every open document, by URI.
+    documents: HashMap<String, String>,
+    shutting_down: bool,
+    /// What to exit with, once the client has said to.
+    exit_code: Option<i32>,
+}
+
+fn main() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut server = Server::new();
+    while let Some(message) = read_message(&mut input) {
+        for reply in server.handle(message) {
+            write_message(&reply);
+        }
+        if let Some(code) = server.exit_code {
+            process::exit(code);
+        }
+    }
+}
+
+impl Server {
+    fn new() -> Server {
+        Server {
+            documents: HashMap::new(),
+            shutting_down: false,
+            exit_code: None,
+        }
+    }
+
+    /// Handles a message from the client, and returns the messages to send back.
+    fn handle(&mut self, message: Value) -> Vec<Value> {
+        let method = match message["method"].as_str() {
+            Some(m) => String::from(m),
+            // Responses to requests we never make.
+            None => return Vec::new(),
+        };
+        let params = &message["params"];
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.handle_notification(&method, params),
+        };
+
+        let result = match method.as_str() {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "dasm-lsp" },
+            })),
+            "shutdown" => {
+                self.shutting_down = true;
+                Ok(Value::Null)
+            },
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method \"{}\"", method))),
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        };
+        vec![response]
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = match params["textDocument"]["uri"].as_str() {
+            Some(uri) => String::from(uri),
+            None => {
+                if method == "exit" {
+                    self.exit_code = Some(if self.shutting_down { 0 } else { 1 });
+                }
+                return Vec::new();
+            },
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.documents.insert(uri.clone(), String::from(text));
+                vec![self.diagnostics(&uri)]
+            },
+            "textDocument/didChange" => {
+                // With full sync, the last change holds the whole document.
+                let changes = params["contentChanges"].as_array();
+                match changes.and_then(|c| c.last()) {
+                    Some(change) => {
+                        let text = change["text"].as_str().unwrap_or("");
+                        self.documents.insert(uri.clone(), String::from(text));
+                        vec![self.diagnostics(&uri)]
+                    },
+                    None => Vec::new(),
+                }
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                // Clear out the diagnostics for the closed document.
+                vec![diagnostics_message(&uri, Vec::new())]
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Reassembles a document, and returns the notification with its diagnostics.
+    fn diagnostics(&self, uri: &str) -> Value {
+        let text = &self.documents[uri];
+        let assembly = assembler::assemble_with_options(text, &options_for(uri));
+        let diagnostics: Vec<Value> = assembly.diagnostics.iter()
+            .map(|d| to_lsp_diagnostic(d, text))
+            .collect();
+        diagnostics_message(uri, diagnostics)
+    }
+
+    /// Returns the URI, text, one-based line number and byte offset that a
+    /// `TextDocumentPositionParams` points at.
+    fn position<'a>(&'a self, params: &'a Value)
+        -> Result<(&'a str, &'a str, usize, usize), (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str()
+            .ok_or((INVALID_PARAMS, String::from("Missing document URI")))?;
+        let text = self.documents.get(uri)
+            .ok_or((INVALID_PARAMS, format!("Document \"{}\" isn't open", uri)))?;
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        let character = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+        let col = from_lsp_col(line_text(text, line + 1), character);
+        Ok((uri, text, line + 1, col))
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, text, line, col) = self.position(params)?;
+        let index = index::index(text);
+        let definition = index.occurrence_at(line, col)
+            .and_then(|o| index.definition(&o.name));
+        Ok(match definition {
+            Some(d) => location(uri, text, d.line, d.span.start, d.span.end),
+            None => Value::Null,
+        })
+    }
+
+    fn references(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, text, line, col) = self.position(params)?;
+        let include_declaration = params["context"]["includeDeclaration"].as_bool()
+            .unwrap_or(true);
+        let index = index::index(text);
+        let name = match index.occurrence_at(line, col) {
+            Some(o) => o.name.clone(),
+            None => return Ok(Value::Null),
+        };
+        let locations: Vec<Value> = index.occurrences_of(&name).iter()
+            .filter(|o| include_declaration || !o.is_definition)
+            .map(|o| location(uri, text, o.line, o.span.start, o.span.end))
+            .collect();
+        Ok(Value::Array(locations))
+    }
+
+    fn hover(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, text, line, col) = self.position(params)?;
+        let index = index::index(text);
+        // Addresses are only known once the whole document assembles.
+        let program = assembler::assemble_with_options(text, &options_for(uri)).program;
+
+        // Symbols get described, and anything else on an instruction line shows the
+        // instruction's encoding.
+        let contents = if let Some(occurrence) = index.occurrence_at(line, col) {
+            let definition = match index.definition(&occurrence.name) {
+                Some(d) => d,
+                None => return Ok(Value::Null),
+            };
+            let kind = match definition.kind {
+                SymbolKind::CodeLabel | SymbolKind::DataLabel => "label",
+                SymbolKind::Define => "define",
+            };
+            let mut contents = format!("{} `{}`, defined on line {}", kind, definition.name,
+                                       definition.line);
+            let symbol = program.as_ref().and_then(|p| p.symbol(&definition.name));
+            if let Some(symbol) = symbol {
+                contents.push_str(&format!("\n\nAddress: `{:#06x}`", symbol.address));
+            }
+            contents
+        } else if index.is_instruction_line(line) {
+            let mapping = program.as_ref()
+                .and_then(|p| p.line_map.iter().find(|m| m.line == line).map(|m| (p, m)));
+            let (address, words) = match mapping {
+                Some((program, mapping)) => {
+                    let start = mapping.address as usize;
+                    let words = &program.words[start..start + mapping.num_words as usize];
+                    (Some(mapping.address), words.iter().map(|&w| Some(w)).collect())
+                },
+                None => match encode_line(uri, text, &index, line) {
+                    Some(words) => (None, words),
+                    None => return Ok(Value::Null),
+                },
+            };
+            let instruction = Instruction::new(words[0].unwrap_or(0));
+            let mut contents = format!("```\n{}\n```", instruction);
+            if let Some(address) = address {
+                contents.push_str(&format!("\n\nAddress: `{:#06x}`", address));
+            }
+            // Words that hold label addresses aren't known without the address.
+            let hex: Vec<String> = words.iter()
+                .map(|w| w.map(|w| format!("{:#06x}", w)).unwrap_or(String::from("????")))
+                .collect();
+            contents.push_str(&format!("\n\nEncoding: `{}`\n\nCycles: {}", hex.join(" "),
+                                       instruction.num_cycles()));
+            if words.contains(&None) {
+                contents.push_str("\n\nLabels take a word of their own until the file \
+                                   assembles, since small addresses can fit in the \
+                                   instruction.");
+            }
+            contents
+        } else {
+            return Ok(Value::Null);
+        };
+        Ok(json!({ "contents": { "kind": "markdown", "value": contents } }))
+    }
+
+    fn completion(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (_, text, _, _) = self.position(params)?;
+        let mut items = Vec::new();
+        let mnemonics = BASIC_OP_NAMES.iter().chain(SPECIAL_OP_NAMES).chain(PSEUDO_OP_NAMES);
+        for name in mnemonics {
+            items.push(json!({ "label": name, "kind": COMPLETION_KEYWORD }));
+        }
+        for name in register::NAMES.iter().chain(OPERAND_KEYWORDS) {
+            items.push(json!({ "label": name, "kind": COMPLETION_VARIABLE }));
+        }
+        for definition in index::index(text).definitions {
+            items.push(json!({ "label": definition.name, "kind": COMPLETION_REFERENCE }));
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn document_symbols(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str()
+            .ok_or((INVALID_PARAMS, String::from("Missing document URI")))?;
+        let text = self.documents.get(uri)
+            .ok_or((INVALID_PARAMS, format!("Document \"{}\" isn't open", uri)))?;
+        let symbols: Vec<Value> = index::index(text).definitions.iter()
+            .map(|d| {
+                // `SymbolKind`s from the spec: function, variable and constant.
+                let kind = match d.kind {
+                    SymbolKind::CodeLabel => 12,
+                    SymbolKind::DataLabel => 13,
+                    SymbolKind::Define => 14,
+                };
+                json!({
+                    "name": d.name,
+                    "kind": kind,
+                    "location": location(uri, text, d.line, d.span.start, d.span.end),
+                })
+            })
+            .collect();
+        Ok(Value::Array(symbols))
+    }
+}
+
+/// Assembles the instruction on `line` by itself, along with the file's defines, and
+/// returns its words.  Words that hold the addresses of labels are `None`.
+fn encode_line(uri: &str, text: &str, index: &SourceIndex, line: usize)
+    -> Option<Vec<Option<u16>>> {
+    let mut source = String::new();
+    let mut labels = Vec::new();
+    for definition in &index.definitions {
+        if definition.kind == SymbolKind::Define {
+            if definition.line != line {
+                source.push_str(line_text(text, definition.line));
+                source.push('\n');
+            }
+        } else if definition.line != line && !labels.contains(&definition.name) {
+            labels.push(definition.name.clone());
+        }
+    }
+    // Labels from the rest of the file are imported, so they're encoded the way they are
+    // in the whole program, and relocated instead of needing addresses.
+    for label in &labels {
+        source.push_str(&format!(".extern {}\n", label));
+    }
+    source.push_str(line_text(text, line));
+
+    let object = assembler::assemble_object(&source, &options_for(uri)).object?;
+    let section = object.sections.first()?;
+    if section.words.len() == 0 {
+        return None;
+    }
+    Some(section.words.iter().enumerate()
+        .map(|(i, &word)| {
+            let relocated = section.relocations.iter().any(|r| r.offset as usize == i);
+            if relocated { None } else { Some(word) }
+        })
+        .collect())
+}
+
+fn options_for(uri: &str) -> AssemblerOptions {
+    let mut options = AssemblerOptions::default();
+    options.file_name = String::from(uri);
+    options
+}
+
+fn to_lsp_diagnostic(diagnostic: &AssemblerDiagnostic, text: &str) -> Value {
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+        Severity::Note => 3,
+    };
+    // Whole-file diagnostics go on the first line.
+    let line = diagnostic.line.max(1);
+    let range = if diagnostic.line == 0 {
+        range(text, 1, 0, 0)
+    } else {
+        range(text, line, diagnostic.span.start, diagnostic.span.end)
+    };
+    json!({
+        "range": range,
+        "severity": severity,
+        "code": diagnostic.code.code(),
+        "source": "dasm",
+        "message": diagnostic.message,
+    })
+}
+
+fn diagnostics_message(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn location(uri: &str, text: &str, line: usize, start: usize, end: usize) -> Value {
+    json!({ "uri": uri, "range": range(text, line, start, end) })
+}
+
+/// Makes an LSP range from a one-based line and byte offsets.
+fn range(text: &str, line: usize, start: usize, end: usize) -> Value {
+    let line_text = line_text(text, line);
+    json!({
+        "start": { "line": line - 1, "character": to_lsp_col(line_text, start) },
+        "end": { "line": line - 1, "character": to_lsp_col(line_text, end) },
+    })
+}
+
+fn line_text(text: &str, line: usize) -> &str {
+    text.split('\n').nth(line - 1).unwrap_or("").trim_end_matches('\r')
+}
+
+/// Converts a byte offset into a count of UTF-16 code units, which is what LSP
+/// positions use.
+fn to_lsp_col(line: &str, byte: usize) -> usize {
+    line.char_indices()
+        .take_while(|&(i, _)| i < byte)
+        .map(|(_, c)| c.len_utf16())
+        .sum()
+}
+
+fn from_lsp_col(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+/// Reads a message, returning `None` once the client hangs up.
+fn read_message<R: BufRead>(input: &mut R) -> Option<Value> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim_end();
+        if header.len() == 0 {
+            break;
+        }
+        let mut parts = header.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = parts.next().and_then(|v| v.trim().parse::<usize>().ok());
+        }
+    }
+
+    let mut body = vec![0; content_length?];
+    input.read_exact(&mut body).ok()?;
+    match serde_json::from_slice(&body) {
+        Ok(message) => Some(message),
+        Err(e) => {
+            eprintln!("dasm-lsp: ignoring malformed message: {}", e);
+            Some(Value::Null)
+        },
+    }
+}
+
+fn write_message(message: &Value) {
+    let body = message.to_string();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let _ = write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = output.flush();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///test.dasm";
+
+    fn open(text: &str) -> (Server, Vec<Value>) {
+        let mut server = Server::new();
+        let replies = server.handle(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "text": text } },
+        }));
+        (server, replies)
+    }
+
+    /// Sends a request about a zero-based line and character, and returns its result.
+    fn request(server: &mut Server, method: &str, line: usize, character: usize) -> Value {
+        let replies = server.handle(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": true },
+            },
+        }));
+        assert_eq!(replies.len(), 1);
+        replies[0]["result"].clone()
+    }
+
+    fn hover_text(server: &mut Server, line: usize, character: usize) -> String {
+        let hover = request(server, "textDocument/hover", line, character);
+        String::from(hover["contents"]["value"].as_str().unwrap())
+    }
+
+    fn range(line: usize, start: usize, end: usize) -> Value {
+        json!({
+            "start": { "line": line, "character": start },
+            "end": { "line": line, "character": end },
+        })
+    }
+
+    const SOURCE: &str = "\
+  set A, 1
+:loop
+  set PC, loop
+";
+
+    #[test]
+    fn diagnostics() {
+        let (mut server, replies) = open("  set A, 1\n  bogus A\n");
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["method"], "textDocument/publishDiagnostics");
+        let diagnostics = replies[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["range"], range(1, 2, 7));
+        assert_eq!(diagnostics[0]["severity"], 1);
+
+        let replies = server.handle(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": URI },
+                "contentChanges": [{ "text": SOURCE }],
+            },
+        }));
+        assert_eq!(replies[0]["params"]["diagnostics"], json!([]));
+
+        let replies = server.handle(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didClose",
+            "params": { "textDocument": { "uri": URI } },
+        }));
+        assert_eq!(replies[0]["params"]["diagnostics"], json!([]));
+        assert!(server.documents.is_empty());
+    }
+
+    #[test]
+    fn definition_and_references() {
+        let (mut server, _) = open(SOURCE);
+        let definition = request(&mut server, "textDocument/definition", 2, 11);
+        assert_eq!(definition, json!({ "uri": URI, "range": range(1, 1, 5) }));
+        // Registers aren't symbols.
+        let definition = request(&mut server, "textDocument/definition", 0, 6);
+        assert_eq!(definition, Value::Null);
+
+        let references = request(&mut server, "textDocument/references", 1, 2);
+        assert_eq!(references, json!([
+            { "uri": URI, "range": range(1, 1, 5) },
+            { "uri": URI, "range": range(2, 10, 14) },
+        ]));
+    }
+
+    #[test]
+    fn hover() {
+        let (mut server, _) = open(SOURCE);
+        assert_eq!(hover_text(&mut server, 2, 11),
+                   "label `loop`, defined on line 2\n\nAddress: `0x0001`");
+        assert_eq!(hover_text(&mut server, 2, 2),
+                   "```\nSET PC, 0x1\n```\n\nAddress: `0x0001`\n\n\
+                    Encoding: `0x8b81`\n\nCycles: 1");
+
+        // Errors elsewhere in the file only hide what depends on the whole program.
+        let (mut server, _) = open(&format!("{}  bogus A\n", SOURCE));
+        let jump = hover_text(&mut server, 2, 2);
+        assert!(jump.starts_with("```\nSET PC, <next word>\n```\n\n\
+                                  Encoding: `0x7f81 ????`\n\nCycles: 2\n\n"), "{}", jump);
+        assert_eq!(hover_text(&mut server, 0, 2),
+                   "```\nSET A, 0x1\n```\n\nEncoding: `0x8801`\n\nCycles: 1");
+        assert_eq!(request(&mut server, "textDocument/hover", 3, 2), Value::Null);
+    }
+}
@@ -0,0 +1,142 @@
+//! Command-line DCPU-16 assembler.
+//!
+//! Usage: `dasm [-o OUT] [--listing FILE] [-O [--relative-jumps]] [-DNAME[=VALUE]]... SOURCE`
+//!
+//! The program is written as little-endian words, to `SOURCE` with a `.bin` extension if
+//! no output file is given.  Defines without a value are set to 1.  `-O` runs the
+//! peephole optimizer, and reports unused labels and unreachable code.  With
+//! `--relative-jumps`, it also shortens jumps into `add PC, n` or `sub PC, n`, which
+//! overwrite EX.
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process;
+
+#[allow(dead_code)]
+#[path = "../hardware/dcpu/mod.rs"]
+mod dcpu;
+
+use dcpu::assembler::{self, AssemblerOptions};
+use dcpu::literal;
+
+
+struct Args {
+    source: String,
+    output: Option<String>,
+    listing: Option<String>,
+    options: AssemblerOptions,
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            eprintln!("usage: dasm [-o OUT] [--listing FILE] [-O [--relative-jumps]] \
+                       [-DNAME[=VALUE]]... SOURCE");
+            process::exit(2);
+        },
+    };
+
+    let mut source = String::new();
+    if let Err(e) = File::open(&args.source).and_then(|mut f| f.read_to_string(&mut source)) {
+        eprintln!("error: couldn't read {}: {}", args.source, e);
+        process::exit(1);
+    }
+
+    let assembly = assembler::assemble_with_options(&source, &args.options);
+    if assembly.diagnostics.len() > 0 {
+        eprintln!("{}", assembler::diagnostic::render_all(&assembly.diagnostics, &source));
+    }
+    let program = match assembly.program {
+        Some(p) => p,
+        None => process::exit(1),
+    };
+
+    let source_path = args.source;
+    let output = args.output.unwrap_or_else(|| {
+        Path::new(&source_path).with_extension("bin").to_string_lossy().into_owned()
+    });
+    let mut bytes = Vec::with_capacity(program.words.len() * 2);
+    for word in &program.words {
+        bytes.push(*word as u8);
+        bytes.push((*word >> 8) as u8);
+    }
+    write_file(&output, &bytes);
+    if let Some(listing) = args.listing {
+        write_file(&listing, program.listing.as_bytes());
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Args, String> {
+    let mut options = AssemblerOptions::default();
+    let mut source = None;
+    let mut output = None;
+    let mut listing = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-o" || arg == "--listing" {
+            let value = args.next().ok_or(format!("{} needs a file name", arg))?;
+            if arg == "-o" {
+                output = Some(value);
+            } else {
+                listing = Some(value);
+            }
+        } else if arg == "-O" {
+            options.optimize = true;
+        } else if arg == "--relative-jumps" {
+            options.relative_jumps = true;
+        } else if arg.starts_with("-D") {
+            // Both "-DNAME" and "-D NAME" work.
+            let define = if arg.len() > 2 {
+                String::from(&arg[2..])
+            } else {
+                args.next().ok_or(String::from("-D needs a name"))?
+            };
+            let (name, value) = parse_define(&define)?;
+            options.defines.insert(name, value);
+        } else if arg.starts_with("-") {
+            return Err(format!("Unknown option \"{}\"", arg));
+        } else if source.is_some() {
+            return Err(String::from("Only one source file can be assembled at a time"));
+        } else {
+            source = Some(arg);
+        }
+    }
+
+    let source = source.ok_or(String::from("No source file given"))?;
+    if options.relative_jumps && !options.optimize {
+        return Err(String::from("--relative-jumps only works with -O"));
+    }
+    options.file_name = source.clone();
+    Ok(Args {
+        source,
+        output,
+        listing,
+        options,
+    })
+}
+
+/// Parses "NAME" or "NAME=VALUE".
+fn parse_define(define: &str) -> Result<(String, u16), String> {
+    let mut parts = define.splitn(2, '=');
+    let name = parts.next().unwrap();
+    if name.len() == 0 {
+        return Err(format!("Invalid define \"{}\"", define));
+    }
+    let value = match parts.next() {
+        Some(v) => literal::parse(v)? as u16,
+        None => 1,
+    };
+    Ok((String::from(name), value))
+}
+
+fn write_file(path: &str, bytes: &[u8]) {
+    if let Err(e) = File::create(path).and_then(|mut f| f.write_all(bytes)) {
+        eprintln!("error: couldn't write {}: {}", path, e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,108 @@
+//! Formats DCPU-16 assembly in the canonical style.
+//!
+//! Usage: `dasmfmt [--check] [FILE]...`
+//!
+//! Files are formatted in place.  With no files, source is read from stdin and written
+//! to stdout.  With `--check`, nothing gets written, and the exit code says whether
+//! every file was already formatted.
+//!
+//! As a safety net, the formatted source is reassembled, and files whose words would
+//! change are left alone.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+#[allow(dead_code)]
+#[path = "../hardware/dcpu/mod.rs"]
+mod dcpu;
+
+use dcpu::assembler;
+use dcpu::assembler::format::format;
+
+
+fn main() {
+    let mut check = false;
+    let mut files = Vec::new();
+    for arg in env::args().skip(1) {
+        if arg == "--check" {
+            check = true;
+        } else if arg.starts_with("-") {
+            eprintln!("error: Unknown option \"{}\"", arg);
+            eprintln!("usage: dasmfmt [--check] [FILE]...");
+            process::exit(2);
+        } else {
+            files.push(arg);
+        }
+    }
+
+    if files.len() == 0 {
+        let mut source = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut source) {
+            eprintln!("error: couldn't read stdin: {}", e);
+            process::exit(1);
+        }
+        let formatted = match format_checked(&source) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            },
+        };
+        if check {
+            process::exit(if formatted == source { 0 } else { 1 });
+        }
+        print!("{}", formatted);
+        return;
+    }
+
+    let mut ok = true;
+    for file in &files {
+        let mut source = String::new();
+        if let Err(e) = File::open(file).and_then(|mut f| f.read_to_string(&mut source)) {
+            eprintln!("error: couldn't read {}: {}", file, e);
+            ok = false;
+            continue;
+        }
+        let formatted = match format_checked(&source) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("error: {}: {}", file, e);
+                ok = false;
+                continue;
+            },
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} isn't formatted", file);
+            ok = false;
+        } else if let Err(e) = File::create(file)
+                .and_then(|mut f| f.write_all(formatted.as_bytes())) {
+            eprintln!("error: couldn't write {}: {}", file, e);
+            ok = false;
+        }
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
+
+/// Formats `source`, making sure it still assembles to the same words.  Sources that
+/// don't assemble are still formatted, since there's nothing to compare against.
+fn format_checked(source: &str) -> Result<String, String> {
+    let formatted = format(source);
+    if let Ok(before) = assembler::assemble(source) {
+        let same = match assembler::assemble(&formatted) {
+            Ok(after) => after.words == before.words,
+            Err(_) => false,
+        };
+        if !same {
+            return Err(String::from("Formatting would change the assembled program; \
+                                     please report this as a bug"));
+        }
+    }
+    Ok(formatted)
+}
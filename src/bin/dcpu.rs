@@ -0,0 +1,163 @@
+//! Runs a DCPU-16 program without the game.
+//!
+//! Usage: `dcpu [--cycles N] [--seed N] PROGRAM`
+//!
+//! The program is read as little-endian words, like `dasm` writes them.  It runs in real
+//! time, with a Generic Clock, a random number generator and a serial port plugged into
+//! stdin and stdout, so it can be driven by scripts and have its output piped into other
+//! tools.  It runs until `--cycles` cycles have gone by, or for as long as it takes
+//! otherwise, and stops with an error if the DCPU catches fire.  With `--seed`, the
+//! random number generator gives the same words every run.
+
+#[cfg(unix)]
+extern crate libc;
+extern crate rand;
+
+use std::cell::RefCell;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[allow(dead_code)]
+#[path = "../hardware/clock/mod.rs"]
+pub mod clock;
+#[allow(dead_code)]
+#[path = "../hardware/dcpu/mod.rs"]
+pub mod dcpu;
+#[allow(dead_code)]
+#[path = "../hardware/rng/mod.rs"]
+pub mod rng;
+#[allow(dead_code)]
+#[path = "../hardware/serial/mod.rs"]
+pub mod serial;
+
+// Devices find the DCPU under `hardware`, like they do in the game.
+mod hardware {
+    pub use super::dcpu;
+}
+
+use clock::GenericClock;
+use dcpu::Dcpu;
+use rand::{IsaacRng, Rng, SeedableRng};
+use rng::HardwareRng;
+use serial::Serial;
+use serial::link::Link;
+
+/// How often the DCPU catches up with the time on the wall.
+const SLICES_PER_SECOND: u64 = 60;
+
+
+struct Args {
+    program: String,
+    cycles: Option<u64>,
+    seed: Option<u32>,
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            eprintln!("usage: dcpu [--cycles N] [--seed N] PROGRAM");
+            process::exit(2);
+        },
+    };
+
+    let mut bytes = Vec::new();
+    let read = File::open(&args.program).and_then(|mut f| f.read_to_end(&mut bytes));
+    if let Err(e) = read {
+        eprintln!("error: couldn't read {}: {}", args.program, e);
+        process::exit(1);
+    }
+    if bytes.len() % 2 != 0 || bytes.len() > 2 * 0x10000 {
+        eprintln!("error: {} isn't a DCPU program", args.program);
+        process::exit(1);
+    }
+    let program: Vec<u16> = bytes.chunks(2)
+        .map(|pair| pair[0] as u16 | (pair[1] as u16) << 8)
+        .collect();
+
+    let serial = Rc::new(RefCell::new(Serial::new()));
+    serial.borrow_mut().connect(Link::stdio());
+    let rng: Rc<RefCell<Rng>> = match args.seed {
+        Some(seed) => Rc::new(RefCell::new(IsaacRng::from_seed(&[seed][..]))),
+        None => Rc::new(RefCell::new(rand::thread_rng())),
+    };
+    let mut dcpu = Dcpu::new();
+    dcpu.attach(Rc::new(RefCell::new(GenericClock::new())));
+    dcpu.attach(Rc::new(RefCell::new(HardwareRng::new(rng))));
+    dcpu.attach(serial.clone());
+    dcpu.load_program(&program);
+
+    let start = Instant::now();
+    let mut slices = 0;
+    loop {
+        // Counting slices, rather than adding a slice's worth of cycles each time, keeps
+        // the remainders from adding up.
+        slices += 1;
+        let mut end_cycle = slices * dcpu::CLOCK_SPEED / SLICES_PER_SECOND;
+        if let Some(cycles) = args.cycles {
+            end_cycle = end_cycle.min(cycles);
+        }
+        while dcpu.cycles() < end_cycle && !dcpu.on_fire() {
+            dcpu.tick();
+        }
+        if dcpu.on_fire() {
+            eprintln!("error: the DCPU caught fire after {} cycles", dcpu.cycles());
+            process::exit(1);
+        }
+        if args.cycles.map_or(false, |cycles| dcpu.cycles() >= cycles) {
+            break;
+        }
+
+        let slice_end = start + Duration::from_millis(slices * 1000 / SLICES_PER_SECOND);
+        let now = Instant::now();
+        if slice_end > now {
+            thread::sleep(slice_end - now);
+        }
+    }
+
+    // Waits for what's been sent to be written.
+    let link = serial.borrow_mut().disconnect();
+    if let Some(link) = link {
+        link.close();
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Args, String> {
+    let mut program = None;
+    let mut cycles = None;
+    let mut seed = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--cycles" {
+            let value = args.next().ok_or(String::from("--cycles needs a number"))?;
+            let parsed = value.parse()
+                .map_err(|_| format!("Invalid cycle count \"{}\"", value))?;
+            cycles = Some(parsed);
+        } else if arg == "--seed" {
+            let value = args.next().ok_or(String::from("--seed needs a number"))?;
+            let parsed = value.parse()
+                .map_err(|_| format!("Invalid seed \"{}\"", value))?;
+            seed = Some(parsed);
+        } else if arg.starts_with("-") {
+            return Err(format!("Unknown option \"{}\"", arg));
+        } else if program.is_some() {
+            return Err(String::from("Only one program can be run at a time"));
+        } else {
+            program = Some(arg);
+        }
+    }
+
+    let program = program.ok_or(String::from("No program given"))?;
+    Ok(Args {
+        program,
+        cycles,
+        seed,
+    })
+}
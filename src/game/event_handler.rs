@@ -2,6 +2,16 @@ use glutin;
 use glutin::{EventsLoop, VirtualKeyCode};
 use std::collections::HashSet;
 
+/// Something that happened on the keyboard, in the order it happened.
+#[derive(Clone, Copy, Debug)]
+pub enum KeyEvent {
+    /// A key went down, or was held long enough to repeat.
+    Pressed(VirtualKeyCode),
+    Released(VirtualKeyCode),
+    /// A character was typed, with whatever layout and modifiers were in use.
+    Typed(char),
+}
+
 pub struct EventHandler {
     events_loop: glutin::EventsLoop,
     close_requested: bool,
@@ -10,6 +20,8 @@ pub struct EventHandler {
     // Store the currently-pressed keys and the keys that were pressed on the last tick.
     last_pressed_keys: HashSet<VirtualKeyCode>,
     pressed_keys: HashSet<VirtualKeyCode>,
+    // Keyboard events since the last tick.
+    key_events: Vec<KeyEvent>,
 }
 
 impl EventHandler {
@@ -21,6 +33,7 @@ impl EventHandler {
             pressed_mouse_buttons: HashSet::new(),
             last_pressed_keys: HashSet::new(),
             pressed_keys: HashSet::new(),
+            key_events: Vec::new(),
         }
     }
 
@@ -31,8 +44,10 @@ impl EventHandler {
         // TODO: Make more efficient.
         self.last_pressed_keys = self.pressed_keys.clone();
         let pressed_keys = &mut self.pressed_keys;
+        let key_events = &mut self.key_events;
 
         *mouse_delta = (0.0, 0.0);
+        key_events.clear();
 
         self.events_loop.poll_events(|event| {
             use glutin::Event::{Awakened, DeviceEvent, WindowEvent};
@@ -40,8 +55,14 @@ impl EventHandler {
             match event {
                 WindowEvent { event, .. } => match event {
                     glutin::WindowEvent::Closed => *close_requested = true,
-                    glutin::WindowEvent::KeyboardInput { input, .. } =>
-                        Self::handle_key_event(input, pressed_keys, close_requested),
+                    glutin::WindowEvent::KeyboardInput { input, .. } => {
+                        // Only the window's keyboard events count as typing, so keys
+                        // aren't seen twice, and typing stops when it loses focus.
+                        Self::record_key_event(input, key_events);
+                        Self::handle_key_event(input, pressed_keys, close_requested);
+                    },
+                    glutin::WindowEvent::ReceivedCharacter(c) =>
+                        key_events.push(KeyEvent::Typed(c)),
                     _ => (),
                 },
                 DeviceEvent { event, .. } => {
@@ -67,9 +88,8 @@ impl EventHandler {
                             }
                         },
                         Key(input) => Self::handle_key_event(input, pressed_keys, close_requested),
-                        Text { codepoint } => {
-                            println!("Codepoint: {}", codepoint);
-                        },
+                        // The same text comes through the window as `ReceivedCharacter`.
+                        Text { .. } => (),
                     }
                 },
                 Awakened => (),
@@ -77,6 +97,15 @@ impl EventHandler {
         });
     }
 
+    fn record_key_event(input: glutin::KeyboardInput, key_events: &mut Vec<KeyEvent>) {
+        if let Some(key) = input.virtual_keycode {
+            key_events.push(match input.state {
+                glutin::ElementState::Pressed => KeyEvent::Pressed(key),
+                glutin::ElementState::Released => KeyEvent::Released(key),
+            });
+        }
+    }
+
     fn handle_key_event(input: glutin::KeyboardInput,
                         pressed_keys: &mut HashSet<VirtualKeyCode>,
                         close_requested: &mut bool) {
@@ -122,4 +151,10 @@ impl EventHandler {
     pub fn is_key_pressed(&self, key: glutin::VirtualKeyCode) -> bool {
         return self.pressed_keys.contains(&key) && !self.last_pressed_keys.contains(&key);
     }
+
+    /// Returns every keyboard event since the last tick, including repeats and typed
+    /// characters, in order.
+    pub fn key_events(&self) -> &[KeyEvent] {
+        &self.key_events
+    }
 }
@@ -19,7 +19,8 @@ pub struct Game {
 }
 
 impl Game {
-    pub fn new() -> Game {
+    /// Returns a new game, with its world set up by `options`.
+    pub fn new(options: world::WorldOptions) -> Game {
         let events_loop = glutin::EventsLoop::new();
         let window = glutin::WindowBuilder::new()
             .with_dimensions(WINDOW_DIMENSIONS.0, WINDOW_DIMENSIONS.1)
@@ -36,7 +37,7 @@ impl Game {
         }
 
         Game {
-            current_state: Box::new(MainGameState::new(display)),
+            current_state: Box::new(MainGameState::new(display, options)),
             event_handler: event_handler::EventHandler::new(events_loop),
         }
     }
@@ -84,10 +85,10 @@ struct MainGameState {
 }
 
 impl MainGameState {
-    pub fn new(display: Display) -> MainGameState {
+    pub fn new(display: Display, options: world::WorldOptions) -> MainGameState {
         let camera = camera::Camera::new(WINDOW_DIMENSIONS.0, WINDOW_DIMENSIONS.1);
         let player = entity::player::Player::new();
-        let world = world::World::new(player, display);
+        let world = world::World::new(player, display, options);
 
         MainGameState {
             camera,
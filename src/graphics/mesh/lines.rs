@@ -0,0 +1,129 @@
+use gl;
+use gl::types::*;
+use std::mem;
+use std::ptr;
+
+use graphics::Render;
+use graphics::mesh::AttribIndices;
+use util::math::Transformation;
+use world::RenderConfig;
+
+
+/// How wide the faint pass under each line is, in pixels.
+const GLOW_WIDTH: GLfloat = 6.0;
+/// How bright the faint pass is, next to the line itself.
+const GLOW_BRIGHTNESS: GLfloat = 0.3;
+
+
+/// Colored lines that glow, and can be changed every frame, drawn with whatever shader is
+/// bound.  Shaders get each vertex's color as `color`, and how much to scale it by as the
+/// `brightness` uniform.
+///
+/// Each line is drawn twice, wide and faint and then thin and bright, and added to what's
+/// behind it, so lines light up where they cross and fade out at their edges.
+pub struct Lines {
+    vao_id: GLuint,
+    positions_vbo_id: GLuint,
+    colors_vbo_id: GLuint,
+    num_vertices: usize,
+    transformation: Transformation,
+}
+
+impl Lines {
+    /// Returns a new `Lines`, with no lines in it.
+    pub fn new() -> Lines {
+        let mut vao_id = 0;
+        let positions_vbo_id;
+        let colors_vbo_id;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao_id);
+            gl::BindVertexArray(vao_id);
+            positions_vbo_id = Self::gen_vbo(AttribIndices::Positions as GLuint);
+            colors_vbo_id = Self::gen_vbo(AttribIndices::Colors as GLuint);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+
+        Lines {
+            vao_id,
+            positions_vbo_id,
+            colors_vbo_id,
+            num_vertices: 0,
+            transformation: Transformation::new(),
+        }
+    }
+
+    unsafe fn gen_vbo(attrib_index: GLuint) -> GLuint {
+        let mut vbo_id = 0;
+        gl::GenBuffers(1, &mut vbo_id);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo_id);
+        gl::VertexAttribPointer(attrib_index, 3, gl::FLOAT, gl::FALSE, 0, ptr::null());
+        vbo_id
+    }
+
+    /// Replaces the lines with new ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `positions` - Pairs of points, with a line between the points in each pair.
+    /// * `colors` - An RGB color for each point.
+    pub fn update(&mut self, positions: &[GLfloat], colors: &[GLfloat]) {
+        assert_eq!(positions.len(), colors.len());
+        assert_eq!(positions.len() % 6, 0);
+        unsafe {
+            Self::buffer_data(self.positions_vbo_id, positions);
+            Self::buffer_data(self.colors_vbo_id, colors);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        self.num_vertices = positions.len() / 3;
+    }
+
+    unsafe fn buffer_data(vbo_id: GLuint, data: &[GLfloat]) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo_id);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            (data.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+            data.as_ptr() as *const GLvoid,
+            gl::DYNAMIC_DRAW,
+        );
+    }
+
+    /// Use this to manipulate the lines' translation, rotation, and scaling.
+    pub fn transformation(&mut self) -> &mut Transformation {
+        &mut self.transformation
+    }
+}
+
+impl Render for Lines {
+    fn render(&mut self, config: &mut RenderConfig) {
+        if self.num_vertices == 0 {
+            return;
+        }
+
+        let uniforms = config.render_context.curr_shader().uniforms();
+        uniforms.send_matrix_4fv("model_matrix", self.transformation.to_model_matrix());
+
+        unsafe {
+            gl::BindVertexArray(self.vao_id);
+            gl::EnableVertexAttribArray(AttribIndices::Positions as GLuint);
+            gl::EnableVertexAttribArray(AttribIndices::Colors as GLuint);
+            // Light only adds to what's behind it, and doesn't hide lines drawn later.
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ONE, gl::ONE);
+            gl::DepthMask(gl::FALSE);
+
+            for &(width, brightness) in &[(GLOW_WIDTH, GLOW_BRIGHTNESS), (1.0, 1.0)] {
+                uniforms.send_1f("brightness", brightness);
+                gl::LineWidth(width);
+                gl::DrawArrays(gl::LINES, 0, self.num_vertices as GLsizei);
+            }
+
+            gl::LineWidth(1.0);
+            gl::DepthMask(gl::TRUE);
+            gl::Disable(gl::BLEND);
+            gl::DisableVertexAttribArray(AttribIndices::Positions as GLuint);
+            gl::DisableVertexAttribArray(AttribIndices::Colors as GLuint);
+            gl::BindVertexArray(0);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod cube;
+pub mod lines;
 pub mod obj;
 pub mod rect;
 pub mod tetrahedron;
@@ -32,6 +33,7 @@ pub enum AttribIndices {
     Positions = 0,
     Normals = 1,
     TexCoords = 2,
+    Colors = 3,
 }
 
 
@@ -84,6 +84,10 @@ impl<'a> RenderingContext<'a> {
         self.state = state;
     }
 
+    pub fn state(&self) -> &ContextState {
+        &self.state
+    }
+
     pub fn curr_shader(&mut self) -> &mut ShaderProgram {
         match self.curr_shader_name {
             Some(ref mut pn) => self.shaders.get_mut(pn).unwrap(),
@@ -116,6 +120,11 @@ impl Renderer {
                            name: String::from("unlit"),
                            shader_type: VertFrag,
                        })));
+        shaders.insert(String::from("line"),
+                       ShaderProgram::new(ShaderSource::from(ShaderConfig {
+                           name: String::from("line"),
+                           shader_type: VertFrag,
+                       })));
         shaders.insert(String::from("depth"),
                        ShaderProgram::new(ShaderSource::from(ShaderConfig {
                            name: String::from("depth"),
@@ -315,6 +315,8 @@ impl ShaderProgram {
                                    CString::new("normal").unwrap().as_ptr());
             gl::BindAttribLocation(program, AttribIndices::TexCoords as u32,
                                    CString::new("tex_coord").unwrap().as_ptr());
+            gl::BindAttribLocation(program, AttribIndices::Colors as u32,
+                                   CString::new("color").unwrap().as_ptr());
         }
     }
 }
@@ -0,0 +1,145 @@
+//! The Generic Clock, which ticks at a rate programs pick, and can send an interrupt on
+//! every tick.
+//!
+//! It goes by the DCPU's cycle count rather than the time on the wall, so programs that
+//! use it run the same way every time, however fast the game runs.
+//!
+//! Interrupts, picked by `A`:
+//!
+//! * 0 - Ticks 60 / `B` times a second, starting from now, or stops if `B` is 0.
+//! * 1 - Sets `C` to the number of ticks since the clock was last started.
+//! * 2 - Turns on interrupts with message `B`, which are sent on every tick, or turns them
+//!   off if `B` is 0.
+
+use hardware::dcpu::{self, Dcpu};
+use hardware::dcpu::device::Device;
+use hardware::dcpu::register::Register;
+
+const ID: u32 = 0x12d0b402;
+const VERSION: u16 = 1;
+const MANUFACTURER: u32 = 0;
+
+const SET_RATE: u16 = 0;
+const GET_TICKS: u16 = 1;
+const SET_INTERRUPT_MESSAGE: u16 = 2;
+
+/// How many times a second the clock ticks when `B` is 1.
+const BASE_TICKS_PER_SECOND: u64 = 60;
+
+
+pub struct GenericClock {
+    /// The clock ticks once every `divider` 60ths of a second, or not at all if it's 0.
+    divider: u16,
+    /// When the clock was started, in DCPU cycles.
+    start_cycle: u64,
+    /// Ticks since the clock was started that have been sent as interrupts, or would have
+    /// been if interrupts were on.
+    ticks_seen: u64,
+    /// Sent on every tick, or 0 if interrupts are off.
+    interrupt_message: u16,
+}
+
+impl GenericClock {
+    pub fn new() -> GenericClock {
+        GenericClock {
+            divider: 0,
+            start_cycle: 0,
+            ticks_seen: 0,
+            interrupt_message: 0,
+        }
+    }
+
+    /// Returns how many times the clock has ticked since it was started.
+    fn ticks(&self, dcpu: &Dcpu) -> u64 {
+        if self.divider == 0 {
+            return 0;
+        }
+        let elapsed = dcpu.cycles() - self.start_cycle;
+        elapsed * BASE_TICKS_PER_SECOND / (dcpu::CLOCK_SPEED * self.divider as u64)
+    }
+}
+
+impl Device for GenericClock {
+    fn id(&self) -> u32 {
+        ID
+    }
+
+    fn version(&self) -> u16 {
+        VERSION
+    }
+
+    fn manufacturer(&self) -> u32 {
+        MANUFACTURER
+    }
+
+    fn interrupt(&mut self, dcpu: &mut Dcpu) {
+        let b = dcpu.reg(Register::B as u16);
+        match dcpu.reg(Register::A as u16) {
+            SET_RATE => {
+                self.divider = b;
+                self.start_cycle = dcpu.cycles();
+                self.ticks_seen = 0;
+            },
+            GET_TICKS => {
+                let ticks = self.ticks(dcpu);
+                dcpu.set_reg(Register::C as u16, ticks as u16);
+            },
+            SET_INTERRUPT_MESSAGE => self.interrupt_message = b,
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, dcpu: &mut Dcpu) {
+        let ticks = self.ticks(dcpu);
+        while self.ticks_seen < ticks {
+            self.ticks_seen += 1;
+            if self.interrupt_message != 0 {
+                dcpu.interrupt(self.interrupt_message);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use hardware::dcpu::with_device;
+
+    #[test]
+    fn ticks() {
+        // Ticks 30 times a second, counting interrupts in X.
+        let mut dcpu = with_device(Rc::new(RefCell::new(GenericClock::new())), "\
+ias on_tick
+set A, 2
+set B, 0x77
+hwi 0
+set A, 0
+set B, 2
+hwi 0
+:wait set PC, wait
+
+:on_tick
+add X, 1
+rfi 0
+");
+
+        // Run for a second.
+        while dcpu.cycles() < dcpu::CLOCK_SPEED {
+            dcpu.tick();
+        }
+        assert_eq!(dcpu.reg(Register::X as u16), 29);
+        while dcpu.cycles() < dcpu::CLOCK_SPEED + 100 {
+            dcpu.tick();
+        }
+        assert_eq!(dcpu.reg(Register::X as u16), 30);
+
+        // Asking how many ticks there have been doesn't reset them.
+        dcpu.set_reg(Register::A as u16, 1);
+        dcpu.device(0).unwrap().borrow_mut().interrupt(&mut dcpu);
+        assert_eq!(dcpu.reg(Register::C as u16), 30);
+    }
+}
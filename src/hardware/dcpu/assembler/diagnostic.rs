@@ -0,0 +1,217 @@
+use std::fmt;
+
+
+/// How serious a diagnostic is.  Only errors prevent a program from being assembled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/// Identifies the kind of problem a diagnostic reports, so tools can filter diagnostics
+/// without matching on their messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiagnosticCode {
+    UnknownOp,
+    InvalidOperand,
+    ExpectedComma,
+    ExpectedLineEnd,
+    IncompleteLine,
+    DuplicateLabel,
+    UndefinedLabel,
+    InvalidData,
+    InvalidStringByte,
+    InvalidLiteral,
+    UnknownDirective,
+    UnbalancedConditional,
+    InvalidExpression,
+    LabelAsLvalue,
+    LiteralTruncated,
+    UnreachableCode,
+    RelaxationSavings,
+    UnusedLabel,
+    OptimizationSavings,
+}
+
+/// A range of columns on a single line, as byte offsets from the start of the line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct AssemblerDiagnostic {
+    pub file: String,
+    /// One-based line number, or 0 if the diagnostic is about the whole file.
+    pub line: usize,
+    pub span: Span,
+    pub severity: Severity,
+    pub code: DiagnosticCode,
+    pub message: String,
+}
+
+impl DiagnosticCode {
+    pub fn severity(&self) -> Severity {
+        use self::DiagnosticCode::*;
+        match *self {
+            LabelAsLvalue | LiteralTruncated | UnreachableCode => Severity::Warning,
+            RelaxationSavings | UnusedLabel | OptimizationSavings => Severity::Note,
+            _ => Severity::Error,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        use self::DiagnosticCode::*;
+        match *self {
+            UnknownOp => "E0001",
+            InvalidOperand => "E0002",
+            ExpectedComma => "E0003",
+            ExpectedLineEnd => "E0004",
+            IncompleteLine => "E0005",
+            DuplicateLabel => "E0006",
+            UndefinedLabel => "E0007",
+            InvalidData => "E0008",
+            InvalidStringByte => "E0009",
+            InvalidLiteral => "E0010",
+            UnknownDirective => "E0011",
+            UnbalancedConditional => "E0012",
+            InvalidExpression => "E0013",
+            LabelAsLvalue => "W0001",
+            LiteralTruncated => "W0002",
+            UnreachableCode => "W0003",
+            RelaxationSavings => "N0001",
+            UnusedLabel => "N0002",
+            OptimizationSavings => "N0003",
+        }
+    }
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+impl AssemblerDiagnostic {
+    pub fn new(file: &str, line: usize, span: Span, code: DiagnosticCode, message: String)
+        -> AssemblerDiagnostic {
+        AssemblerDiagnostic {
+            file: String::from(file),
+            line,
+            span,
+            severity: code.severity(),
+            code,
+            message,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Renders the diagnostic in the style of rustc, quoting the offending line from
+    /// `source` with carets under the offending token.
+    pub fn render(&self, source: &str) -> String {
+        let line_num = self.line.to_string();
+        let gutter: String = line_num.chars().map(|_| ' ').collect();
+        let mut result = format!("{}[{}]: {}\n", self.severity, self.code.code(),
+                                 self.message);
+        if self.line == 0 {
+            result.push_str(&format!("{}--> {}\n", gutter, self.file));
+            return result;
+        }
+        result.push_str(&format!("{}--> {}:{}:{}\n", gutter, self.file, self.line,
+                                 self.span.start + 1));
+
+        let source_line = match source.split('\n').nth(self.line - 1) {
+            Some(l) => l.trim_end_matches('\r'),
+            None => return result,
+        };
+        // Spans can point one past the end of the line (e.g., for a missing operand), so
+        // clamp them before slicing.
+        let start = clamp_to_char_boundary(source_line, self.span.start);
+        let end = clamp_to_char_boundary(source_line, self.span.end.max(start));
+
+        // Copy tabs from the source line, so the carets line up no matter how wide the
+        // reader's tabs are.
+        let padding: String = source_line[..start].chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let num_carets = source_line[start..end].chars().count().max(1);
+        let carets: String = (0..num_carets).map(|_| '^').collect();
+
+        result.push_str(&format!("{} |\n", gutter));
+        result.push_str(&format!("{} | {}\n", line_num, source_line));
+        result.push_str(&format!("{} | {}{}\n", gutter, padding, carets));
+        result
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+impl fmt::Display for AssemblerDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}[{}]: {}", self.file, self.severity, self.code.code(),
+                   self.message)
+        } else {
+            write!(f, "{}:{}:{}: {}[{}]: {}", self.file, self.line, self.span.start + 1,
+                   self.severity, self.code.code(), self.message)
+        }
+    }
+}
+
+/// Renders every diagnostic in `diagnostics` against `source`, separated by blank lines.
+pub fn render_all(diagnostics: &[AssemblerDiagnostic], source: &str) -> String {
+    let rendered: Vec<String> = diagnostics.iter().map(|d| d.render(source)).collect();
+    rendered.join("\n")
+}
+
+fn clamp_to_char_boundary(s: &str, idx: usize) -> usize {
+    let mut idx = idx.min(s.len());
+    while !s.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_points_at_token() {
+        let source = "set A, 1\nset PC, nowhere\n";
+        let diagnostic = AssemblerDiagnostic::new(
+            "test.dasm", 2, Span::new(8, 15), DiagnosticCode::UndefinedLabel,
+            String::from("No label \"nowhere\" found"));
+        assert_eq!(diagnostic.render(source), "\
+error[E0007]: No label \"nowhere\" found
+ --> test.dasm:2:9
+  |
+2 | set PC, nowhere
+  |         ^^^^^^^
+");
+    }
+
+    #[test]
+    fn render_past_end_of_line() {
+        let source = "set A,";
+        let diagnostic = AssemblerDiagnostic::new(
+            "test.dasm", 1, Span::new(6, 7), DiagnosticCode::IncompleteLine,
+            String::from("Incomplete line"));
+        let rendered = diagnostic.render(source);
+        assert!(rendered.ends_with("1 | set A,\n  |       ^\n"));
+    }
+}
@@ -0,0 +1,79 @@
+//! Assembles programs into Rust source, so build scripts can assemble firmware when the
+//! game is built, rather than when it runs.
+//!
+//! `build.rs` assembles every `.dasm` file in `src/hardware/firmware` into
+//! `$OUT_DIR/firmware/<name>.rs`, which holds a `&'static [u16]` expression, and
+//! `firmware_image!` includes it.  Errors in the source fail the build, and warnings show
+//! up as cargo warnings.
+
+use std::fmt::Write;
+
+use super::{AssemblerOptions, assemble_with_options};
+use super::diagnostic::{self, AssemblerDiagnostic, Severity};
+
+/// Number of words written on each line of generated source.
+const WORDS_PER_LINE: usize = 8;
+
+
+/// A program that assembled, ready to be written out for `include!`.
+pub struct EmbeddedProgram {
+    /// A Rust expression for the program's words, as a `&'static [u16]`.
+    pub rust: String,
+    /// Warnings, rendered one per element.
+    pub warnings: Vec<String>,
+}
+
+/// Assembles `source`, returning Rust source for it, or its errors and warnings, rendered,
+/// if it had any errors.  Notes are left out either way.
+pub fn embed(source: &str, options: &AssemblerOptions) -> Result<EmbeddedProgram, String> {
+    let assembly = assemble_with_options(source, options);
+    let problems = assembly.diagnostics.into_iter()
+        .filter(|d| d.severity != Severity::Note);
+    match assembly.program {
+        Some(program) => {
+            Ok(EmbeddedProgram {
+                rust: to_rust(&program.words),
+                warnings: problems.map(|d| d.render(source)).collect(),
+            })
+        },
+        None => {
+            let problems: Vec<AssemblerDiagnostic> = problems.collect();
+            Err(diagnostic::render_all(&problems, source))
+        },
+    }
+}
+
+/// Formats `words` as a Rust expression of type `&'static [u16]`.
+pub fn to_rust(words: &[u16]) -> String {
+    let mut rust = String::from("&[\n");
+    for line in words.chunks(WORDS_PER_LINE) {
+        rust.push_str("   ");
+        for word in line {
+            write!(rust, " {:#06x},", word).unwrap();
+        }
+        rust.push('\n');
+    }
+    rust.push_str("]\n");
+    rust
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedding() {
+        let mut options = AssemblerOptions::default();
+        options.file_name = String::from("test.dasm");
+        let embedded = embed(":loop set A, 0x1234\nset 5, A\nset PC, loop\n", &options)
+            .ok().unwrap();
+        assert_eq!(embedded.rust, "&[\n    0x7c01, 0x1234, 0x03e1, 0x0005, 0x8781,\n]\n");
+        assert_eq!(embedded.warnings.len(), 1);
+        assert!(embedded.warnings[0].starts_with("warning[W0001]"));
+
+        let errors = embed("frob A\n", &options).err().unwrap();
+        assert!(errors.starts_with("error[E0001]: Expected an op name"));
+        assert!(errors.contains("test.dasm:1:1"));
+    }
+}
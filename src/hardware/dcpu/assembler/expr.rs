@@ -0,0 +1,229 @@
+//! Constant expressions, as used by `.if`, `.elif` and `.define`.
+//!
+//! Expressions work like they do in C, with 64-bit signed arithmetic.  Names refer to
+//! defines, and `defined(NAME)` tells whether a name is defined at all.
+
+use std::collections::HashMap;
+
+/// Binary operators, from lowest to highest precedence.
+const PRECEDENCE_LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Every operator, with longer ones first, so "<<" isn't read as two "<"s.
+const OPERATORS: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+    "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!", "~", "(", ")",
+];
+
+
+#[derive(Clone, Debug, PartialEq)]
+enum ExprToken {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+struct Parser<'a> {
+    tokens: Vec<ExprToken>,
+    pos: usize,
+    defines: &'a HashMap<String, u16>,
+}
+
+/// Evaluates `expr`, looking up names in `defines`.
+pub fn evaluate(expr: &str, defines: &HashMap<String, u16>) -> Result<i64, String> {
+    let mut parser = Parser {
+        tokens: lex(expr)?,
+        pos: 0,
+        defines,
+    };
+    if parser.tokens.len() == 0 {
+        return Err(String::from("Expected an expression"));
+    }
+    let result = parser.binary(0)?;
+    match parser.peek() {
+        None => Ok(result),
+        Some(t) => Err(format!("Unexpected {} in expression", describe(&t))),
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<ExprToken> {
+        self.tokens.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<ExprToken> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(ExprToken::Op(o)) if o == op => Ok(()),
+            Some(t) => Err(format!("Expected \"{}\"; got {}", op, describe(&t))),
+            None => Err(format!("Expected \"{}\" before the end of the expression", op)),
+        }
+    }
+
+    /// Parses operators at precedence `level` and above.
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == PRECEDENCE_LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(ExprToken::Op(op)) if PRECEDENCE_LEVELS[level].contains(&op) => op,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = apply(op, lhs, rhs)?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.next() {
+            Some(ExprToken::Op("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(ExprToken::Op("!")) => Ok((self.unary()? == 0) as i64),
+            Some(ExprToken::Op("~")) => Ok(!self.unary()?),
+            Some(ExprToken::Op("(")) => {
+                let result = self.binary(0)?;
+                self.expect(")")?;
+                Ok(result)
+            },
+            Some(ExprToken::Number(v)) => Ok(v),
+            Some(ExprToken::Name(ref name)) if name == "defined" => {
+                // Both "defined(NAME)" and "defined NAME" work.
+                let parenthesized = self.peek() == Some(ExprToken::Op("("));
+                if parenthesized {
+                    self.pos += 1;
+                }
+                let result = match self.next() {
+                    Some(ExprToken::Name(name)) => self.defines.contains_key(&name) as i64,
+                    _ => return Err(String::from("Expected a name after \"defined\"")),
+                };
+                if parenthesized {
+                    self.expect(")")?;
+                }
+                Ok(result)
+            },
+            Some(ExprToken::Name(name)) => match self.defines.get(&name) {
+                Some(&v) => Ok(v as i64),
+                None => Err(format!("\"{}\" isn't defined", name)),
+            },
+            Some(t) => Err(format!("Unexpected {} in expression", describe(&t))),
+            None => Err(String::from("Expression ends unexpectedly")),
+        }
+    }
+}
+
+fn apply(op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+    let result = match op {
+        "||" => (lhs != 0 || rhs != 0) as i64,
+        "&&" => (lhs != 0 && rhs != 0) as i64,
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "&" => lhs & rhs,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "<=" => (lhs <= rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        "<" => (lhs < rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" if rhs == 0 => return Err(String::from("Division by zero")),
+        "/" => lhs.wrapping_div(rhs),
+        "%" => lhs.wrapping_rem(rhs),
+        _ => unreachable!(),
+    };
+    Ok(result)
+}
+
+fn lex(expr: &str) -> Result<Vec<ExprToken>, String> {
+    use super::super::literal;
+
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while rest.len() > 0 {
+        let len = if rest.starts_with('\'') {
+            // Character literal, which might have an escaped quote in it.
+            let mut escaped = false;
+            let end = rest.char_indices().skip(1).find(|&(_, c)| {
+                let closes = c == '\'' && !escaped;
+                escaped = c == '\\' && !escaped;
+                closes
+            });
+            match end {
+                Some((i, _)) => {
+                    tokens.push(ExprToken::Number(literal::parse(&rest[..i + 1])?));
+                    i + 1
+                },
+                None => return Err(String::from("Unterminated character literal")),
+            }
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(ExprToken::Op(*op));
+            op.len()
+        } else {
+            let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(format!("Unexpected character '{}' in expression",
+                                   rest.chars().next().unwrap()));
+            }
+            let word = &rest[..len];
+            if literal::is_literal(word) {
+                tokens.push(ExprToken::Number(literal::parse(word)?));
+            } else {
+                tokens.push(ExprToken::Name(String::from(word)));
+            }
+            len
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &ExprToken) -> String {
+    match *token {
+        ExprToken::Number(v) => format!("number {}", v),
+        ExprToken::Name(ref name) => format!("name \"{}\"", name),
+        ExprToken::Op(op) => format!("\"{}\"", op),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence_and_names() {
+        let mut defines = HashMap::new();
+        defines.insert(String::from("SCREEN"), 0x8000);
+        let eval = |e: &str| evaluate(e, &defines);
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("SCREEN == 0x8000 && !defined(FLOPPY)"), Ok(1));
+        assert_eq!(eval("defined SCREEN && 1 / (SCREEN - 0x8000)"),
+                   Err(String::from("Division by zero")));
+        assert_eq!(eval("-'a' + 0b10 << 1"), Ok(-190));
+        assert!(eval("FLOPPY").is_err());
+        assert!(eval("(1").is_err());
+        assert!(eval("1 2").is_err());
+    }
+}
@@ -0,0 +1,329 @@
+//! Canonical formatting for DCPU-16 assembly.
+//!
+//! The output follows the style of the firmware in this repo:
+//!
+//! * Labels use the `:label` form, on their own line, at the start of the line.
+//! * Code is indented under labels, and the instruction after a conditional gets
+//!   indented one more level, so it's clear which instructions can be skipped.
+//! * Mnemonics and directives are lowercase, while registers and keywords like `PC` and
+//!   `PUSH` are uppercase.  Label names are left alone.
+//! * Operands start in the same column, and are separated by ", ".  Brackets don't have
+//!   spaces in them, like `[0x8000+I]`.
+//! * Trailing comments in a run of lines (i.e., without blank lines in between) line up
+//!   with each other.  Full-line comments are indented like the code after them.
+//! * Blank lines are kept as they are, since they're how code gets split into sections.
+//!
+//! Formatting only changes whitespace, case and label syntax, so the formatted source
+//! assembles to the same words.
+
+use super::{string_mode, tokenize, Token, OPERAND_KEYWORDS};
+use super::super::op::{BasicOp, SpecialOp};
+use super::super::register;
+
+/// Indentation for each level of nesting.
+const INDENT: &str = "  ";
+/// Mnemonics are padded to this width, so operands line up.  Every real op is three
+/// letters long, so only pseudo-instructions and data directives stick out.
+const MNEMONIC_WIDTH: usize = 3;
+/// Space between the end of the longest line of code and the trailing comments.
+const COMMENT_GAP: usize = 2;
+
+
+enum Line {
+    Blank,
+    Comment(String),
+    Code {
+        indent: usize,
+        code: String,
+        comment: Option<String>,
+    },
+}
+
+/// Formats `source` in the canonical style.
+pub fn format(source: &str) -> String {
+    let mut lines = Vec::new();
+    // How many conditionals in a row we've seen, which is how far the next instruction
+    // gets indented past the base level.
+    let mut if_depth = 0;
+    for line in source.split('\n') {
+        let line = line.trim_end_matches('\r');
+        let (code, comment) = split_comment(line);
+        let comment = comment.map(|c| String::from(c.trim_end()));
+        let tokens = tokenize(code);
+
+        if tokens.len() == 0 {
+            lines.push(match comment {
+                Some(c) => Line::Comment(c),
+                None => Line::Blank,
+            });
+            continue;
+        }
+
+        let (label, rest) = split_label(&tokens);
+        if let Some(label) = label {
+            // The comment stays with the code, if there is any.
+            let label_comment = if rest.len() == 0 { comment.clone() } else { None };
+            lines.push(Line::Code {
+                indent: 0,
+                code: format!(":{}", label),
+                comment: label_comment,
+            });
+            if rest.len() == 0 {
+                continue;
+            }
+        }
+
+        let first = rest[0].text.to_lowercase();
+        let is_op = BasicOp::try_from(&first).is_some() ||
+            SpecialOp::try_from(&first).is_some() || super::PSEUDO_OP_NAMES.contains(&&*first);
+        let (indent, code) = if first.starts_with(".") && string_mode(&first).is_none() {
+            // Directives other than data go at the start of the line, with the rest of
+            // the line left as it was, since it might be an expression.
+            let args = code[rest[0].col + rest[0].text.len()..].trim();
+            if args.len() == 0 {
+                (0, first)
+            } else {
+                (0, format!("{} {}", first, args))
+            }
+        } else {
+            let indent = 1 + if is_op { if_depth } else { 0 };
+            if is_op {
+                let is_conditional = BasicOp::try_from(&first)
+                    .map(|op| op.is_conditional())
+                    .unwrap_or(false);
+                if_depth = if is_conditional { if_depth + 1 } else { 0 };
+            }
+            (indent, format_instruction(&first, &rest[1..]))
+        };
+        lines.push(Line::Code {
+            indent,
+            code,
+            comment,
+        });
+    }
+    render(lines)
+}
+
+/// Formats a mnemonic (or data directive) and its operands.
+fn format_instruction(mnemonic: &str, operands: &[Token]) -> String {
+    if operands.len() == 0 {
+        return String::from(mnemonic);
+    }
+    // Malformed lines might have operands without commas between them, so keep every
+    // token, and only normalize the commas.
+    let mut groups: Vec<Vec<String>> = vec![Vec::new()];
+    for token in operands {
+        if token.text == "," {
+            groups.push(Vec::new());
+        } else {
+            groups.last_mut().unwrap().push(format_operand(token.text));
+        }
+    }
+    let operands: Vec<String> = groups.iter().map(|g| g.join(" ")).collect();
+    format!("{:<width$} {}", mnemonic, operands.join(", "), width = MNEMONIC_WIDTH)
+}
+
+/// Normalizes the case of registers and keywords in an operand, and the spacing inside
+/// of it.
+fn format_operand(operand: &str) -> String {
+    if operand.starts_with('"') {
+        return String::from(operand);
+    }
+    let mut result = String::new();
+    let mut word = String::new();
+    let mut chars = operand.chars();
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() || c == '_' || c == '.' {
+            word.push(c);
+            continue;
+        }
+        result.push_str(&format_word(&word));
+        word.clear();
+        if c == '\'' {
+            // Copy character literals as-is.
+            result.push(c);
+            let mut escaped = false;
+            while let Some(c) = chars.next() {
+                result.push(c);
+                if c == '\'' && !escaped {
+                    break;
+                }
+                escaped = c == '\\' && !escaped;
+            }
+        } else if c.is_whitespace() {
+            // The only space that belongs in an operand is after "PICK".
+            if result.to_uppercase() == "PICK" {
+                result.push(' ');
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result.push_str(&format_word(&word));
+    result
+}
+
+fn format_word(word: &str) -> String {
+    let upper = word.to_uppercase();
+    if register::try_from(word).is_some() || OPERAND_KEYWORDS.contains(&upper.as_str()) {
+        upper
+    } else {
+        String::from(word)
+    }
+}
+
+/// Splits the label off the front of a line, if there is one.
+fn split_label<'a, 'b>(tokens: &'b [Token<'a>]) -> (Option<&'a str>, &'b [Token<'a>]) {
+    let first = tokens[0].text;
+    if first.starts_with(":") && first.len() > 1 {
+        (Some(&first[1..]), &tokens[1..])
+    } else if first.len() > 1 && first.ends_with(":") && !first.starts_with("\"") &&
+              !first.starts_with("'") {
+        (Some(&first[..first.len() - 1]), &tokens[1..])
+    } else {
+        (None, tokens)
+    }
+}
+
+/// Splits a line into its code and its comment (including the ";"), skipping over
+/// semicolons in strings and character literals.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            },
+            None => {
+                if c == ';' {
+                    return (&line[..i], Some(&line[i..]));
+                } else if c == '"' || c == '\'' {
+                    quote = Some(c);
+                }
+            },
+        }
+    }
+    (line, None)
+}
+
+fn render(lines: Vec<Line>) -> String {
+    // Full-line comments take the indentation of the code after them.
+    let mut comment_indents = vec![0; lines.len()];
+    let mut next_indent = 0;
+    for (i, line) in lines.iter().enumerate().rev() {
+        match *line {
+            Line::Code { indent, .. } => next_indent = indent,
+            Line::Comment(_) => comment_indents[i] = next_indent,
+            Line::Blank => (),
+        }
+    }
+
+    // Trailing comments line up within each run of lines without a blank line.
+    let mut comment_columns = vec![0; lines.len()];
+    let mut run_start = 0;
+    for i in 0..lines.len() + 1 {
+        let run_ended = match lines.get(i) {
+            Some(&Line::Blank) | None => true,
+            _ => false,
+        };
+        if !run_ended {
+            continue;
+        }
+        let column = lines[run_start..i].iter()
+            .filter_map(|l| match *l {
+                Line::Code { indent, ref code, comment: Some(_) } => {
+                    Some(indent * INDENT.len() + code.len())
+                },
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0) + COMMENT_GAP;
+        for c in &mut comment_columns[run_start..i] {
+            *c = column;
+        }
+        run_start = i + 1;
+    }
+
+    let mut result: Vec<String> = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate() {
+        let text = match *line {
+            Line::Blank => String::new(),
+            Line::Comment(ref c) => format!("{}{}", INDENT.repeat(comment_indents[i]), c),
+            Line::Code { indent, ref code, ref comment } => {
+                let code = format!("{}{}", INDENT.repeat(indent), code);
+                match *comment {
+                    Some(ref c) => format!("{:<width$}{}", code, c,
+                                           width = comment_columns[i]),
+                    None => code,
+                }
+            },
+        };
+        result.push(text);
+    }
+    result.join("\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::assemble;
+
+    #[test]
+    fn canonical_style() {
+        let source = "\
+; Print a message.
+start:  SET a,msg ; message
+loop: IFE [ a ], 0
+SUB pc,1
+JSR print   ; one char
+      add A , 1
+
+
+jmp loop
+:msg dat \"hi; there\", 0
+.define SCREEN 0x8000
+set PICK   1 , [SCREEN + 'a']
+";
+        let formatted = format(source);
+        assert_eq!(formatted, "\
+; Print a message.
+:start
+  set A, msg  ; message
+:loop
+  ife [A], 0
+    sub PC, 1
+  jsr print   ; one char
+  add A, 1
+
+
+  jmp loop
+:msg
+  dat \"hi; there\", 0
+.define SCREEN 0x8000
+  set PICK 1, [SCREEN+'a']
+");
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn same_words() {
+        let source = "\
+start: SET A, 0x30
+  ifn a, 0x10
+  ifg A , 3
+      set pc , start
+pop [0x1000] ; done
+";
+        let before = assemble(source).ok().unwrap();
+        let after = assemble(&format(source)).ok().unwrap();
+        assert_eq!(before.words, after.words);
+    }
+}
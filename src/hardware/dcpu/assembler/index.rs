@@ -0,0 +1,260 @@
+//! A lexical index of the symbols in a source file, for editor tooling.
+//!
+//! Unlike the assembler proper, indexing never fails, so it still works while the
+//! source is half-written.  Conditional blocks aren't evaluated, so symbols in every
+//! branch are indexed.
+
+use super::{string_mode, token_span, tokenize, Token, OPERAND_KEYWORDS, PSEUDO_OP_NAMES};
+use super::diagnostic::Span;
+use super::super::literal;
+use super::super::op::{BasicOp, SpecialOp};
+use super::super::register;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolKind {
+    /// A label in front of an instruction, whether it's on the same line or an earlier one.
+    CodeLabel,
+    /// A label in front of data, or in front of nothing at all.
+    DataLabel,
+    /// A name from `.define`.
+    Define,
+}
+
+/// Where a symbol is defined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    /// One-based line number.
+    pub line: usize,
+    pub span: Span,
+    pub kind: SymbolKind,
+}
+
+/// Anywhere a symbol is mentioned, including where it's defined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Occurrence {
+    pub name: String,
+    /// One-based line number.
+    pub line: usize,
+    pub span: Span,
+    pub is_definition: bool,
+}
+
+pub struct SourceIndex {
+    /// Definitions, in source order.
+    pub definitions: Vec<Definition>,
+    /// Occurrences, in source order.
+    pub occurrences: Vec<Occurrence>,
+    /// Lines that hold an instruction (including pseudo-instructions), as opposed to data
+    /// or directives.
+    pub instruction_lines: Vec<usize>,
+}
+
+impl SourceIndex {
+    /// Returns the occurrence at a one-based line number and a byte offset into the line.
+    pub fn occurrence_at(&self, line: usize, col: usize) -> Option<&Occurrence> {
+        self.occurrences.iter()
+            .find(|o| o.line == line && o.span.start <= col && col <= o.span.end)
+    }
+
+    /// Returns the first definition of `name`.
+    pub fn definition(&self, name: &str) -> Option<&Definition> {
+        self.definitions.iter().find(|d| d.name == name)
+    }
+
+    /// Returns every occurrence of `name`.
+    pub fn occurrences_of<'a>(&'a self, name: &'a str) -> Vec<&'a Occurrence> {
+        self.occurrences.iter().filter(|o| o.name == name).collect()
+    }
+
+    pub fn is_instruction_line(&self, line: usize) -> bool {
+        self.instruction_lines.binary_search(&line).is_ok()
+    }
+}
+
+/// Indexes every label and define in `source`.
+pub fn index(source: &str) -> SourceIndex {
+    let mut index = SourceIndex {
+        definitions: Vec::new(),
+        occurrences: Vec::new(),
+        instruction_lines: Vec::new(),
+    };
+    // Labels that haven't been followed by an instruction or data yet, by their index in
+    // `definitions`.
+    let mut pending = Vec::new();
+    for (line_idx, line) in source.split('\n').enumerate() {
+        index_line(&mut index, &mut pending, line_idx + 1, line.trim_end_matches('\r'));
+    }
+    index
+}
+
+fn index_line(index: &mut SourceIndex, pending: &mut Vec<usize>, line_num: usize,
+              line: &str) {
+    let tokens = tokenize(line);
+    if tokens.len() == 0 {
+        return;
+    }
+
+    let label = {
+        let first = tokens[0];
+        if first.text.starts_with(":") && first.text.len() > 1 {
+            Some(Token { text: &first.text[1..], col: first.col + 1 })
+        } else if first.text.len() > 1 && first.text.ends_with(":") &&
+                  !first.text.starts_with("\"") && !first.text.starts_with("'") {
+            Some(Token { text: &first.text[..first.text.len() - 1], col: first.col })
+        } else {
+            None
+        }
+    };
+    let rest = if label.is_some() { &tokens[1..] } else { &tokens[..] };
+
+    let first = rest.first().map(|t| t.text.to_lowercase()).unwrap_or(String::new());
+    let is_instruction = BasicOp::try_from(&first).is_some() ||
+        SpecialOp::try_from(&first).is_some() ||
+        PSEUDO_OP_NAMES.contains(&first.as_str());
+    if let Some(label) = label {
+        pending.push(index.definitions.len());
+        define(index, line_num, &label, SymbolKind::DataLabel);
+    }
+    if rest.len() == 0 {
+        return;
+    }
+    // Labels are often on lines of their own, so they belong to whatever comes next.
+    // Directives don't take up any words, so they're skipped over.
+    if !first.starts_with(".") || string_mode(&first).is_some() {
+        let kind = if is_instruction { SymbolKind::CodeLabel } else { SymbolKind::DataLabel };
+        for i in pending.drain(..) {
+            index.definitions[i].kind = kind;
+        }
+    }
+
+    if is_instruction {
+        index.instruction_lines.push(line_num);
+        for token in &rest[1..] {
+            add_references(index, line_num, token);
+        }
+        return;
+    }
+    match first.as_str() {
+        ".define" => {
+            if rest.len() > 1 {
+                define(index, line_num, &rest[1], SymbolKind::Define);
+            }
+            for token in rest.iter().skip(2) {
+                add_references(index, line_num, token);
+            }
+        },
+        ".section" => (),
+        _ if first.starts_with(".") && string_mode(&first).is_none() => {
+            // Conditionals and `.global`/`.extern` all name symbols.
+            for token in &rest[1..] {
+                add_references(index, line_num, token);
+            }
+        },
+        _ if string_mode(&first).is_some() => {
+            for token in &rest[1..] {
+                add_references(index, line_num, token);
+            }
+        },
+        _ => (),
+    }
+}
+
+fn define(index: &mut SourceIndex, line_num: usize, token: &Token, kind: SymbolKind) {
+    index.definitions.push(Definition {
+        name: String::from(token.text),
+        line: line_num,
+        span: token_span(token),
+        kind,
+    });
+    index.occurrences.push(Occurrence {
+        name: String::from(token.text),
+        line: line_num,
+        span: token_span(token),
+        is_definition: true,
+    });
+}
+
+/// Adds every symbol mentioned in an operand or expression token, like "[label + A]".
+fn add_references(index: &mut SourceIndex, line_num: usize, token: &Token) {
+    let bytes = token.text.as_bytes();
+    let is_name_byte = |b: u8| (b as char).is_alphanumeric() || b == b'_' || b == b'.';
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'"' || bytes[i] == b'\'' {
+            // Skip strings and character literals.
+            let quote = bytes[i];
+            i += 1;
+            while i < bytes.len() && bytes[i] != quote {
+                i += if bytes[i] == b'\\' { 2 } else { 1 };
+            }
+            i += 1;
+        } else if is_name_byte(bytes[i]) {
+            let start = i;
+            while i < bytes.len() && is_name_byte(bytes[i]) {
+                i += 1;
+            }
+            let name = &token.text[start..i];
+            if is_symbol_name(name) {
+                index.occurrences.push(Occurrence {
+                    name: String::from(name),
+                    line: line_num,
+                    span: Span::new(token.col + start, token.col + i),
+                    is_definition: false,
+                });
+            }
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Whether `name` could be a label or define, as opposed to a literal or keyword.
+fn is_symbol_name(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    !literal::is_literal(name) && register::try_from(name).is_none() &&
+        !OPERAND_KEYWORDS.contains(&upper.as_str()) && name != "defined"
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_and_defines() {
+        let index = index("\
+.define SCREEN 0x8000
+start:
+  set A, [SCREEN + 1]
+  jmp start ; start again
+:msg
+.define LENGTH 2
+  dat \"start\", SCREEN
+  set B, [msg+A]
+:end
+");
+        let names: Vec<(&str, SymbolKind)> = index.definitions.iter()
+            .map(|d| (d.name.as_str(), d.kind))
+            .collect();
+        assert_eq!(names, vec![("SCREEN", SymbolKind::Define),
+                               ("start", SymbolKind::CodeLabel),
+                               ("msg", SymbolKind::DataLabel),
+                               ("LENGTH", SymbolKind::Define),
+                               ("end", SymbolKind::DataLabel)]);
+
+        let starts: Vec<(usize, usize)> = index.occurrences_of("start").iter()
+            .map(|o| (o.line, o.span.start))
+            .collect();
+        assert_eq!(starts, vec![(2, 0), (4, 6)]);
+        assert_eq!(index.occurrences_of("SCREEN").len(), 3);
+        assert_eq!(index.occurrence_at(8, 12).map(|o| o.name.as_str()), Some("msg"));
+        assert_eq!(index.instruction_lines, vec![3, 4, 8]);
+
+        // Labels on the same line as what they're in front of work too.
+        let same_line = super::index(":loop set PC, loop\n:data dat 1\n");
+        let kinds: Vec<SymbolKind> = same_line.definitions.iter().map(|d| d.kind).collect();
+        assert_eq!(kinds, vec![SymbolKind::CodeLabel, SymbolKind::DataLabel]);
+    }
+}
@@ -0,0 +1,2412 @@
+pub mod diagnostic;
+pub mod embed;
+mod expr;
+pub mod format;
+pub mod index;
+pub mod program;
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::str;
+
+use super::op::{BasicOp, SpecialOp};
+use super::val_type;
+use self::diagnostic::{AssemblerDiagnostic, DiagnosticCode, Span};
+use self::program::{AssembledProgram, LineMapping, Symbol};
+use super::object::{ObjectFile, ObjectSymbol, Relocation, RelocationTarget, Section};
+
+/// File name used in diagnostics when the source didn't come from a file.
+const DEFAULT_FILE_NAME: &str = "<source>";
+/// Section that code goes in until a `.section` directive says otherwise.
+const DEFAULT_SECTION_NAME: &str = "text";
+/// Pseudo-instructions, which get rewritten in terms of real ones.
+pub const PSEUDO_OP_NAMES: &[&str] = &["jmp", "ret", "brk", "hlt", "nop", "push", "pop"];
+/// Keywords that can be used as operands, besides the general-purpose registers.
+pub const OPERAND_KEYWORDS: &[&str] = &["PC", "SP", "EX", "PUSH", "POP", "PEEK", "PICK"];
+
+
+/// Represents the state of the assembler for a single instruction line.
+#[derive(PartialEq)]
+enum BasicInstructionParseState {
+    OpName,
+    OperandB,
+    Comma,
+    OperandA,
+    End,
+}
+
+#[derive(PartialEq)]
+enum SpecialInstructionParseState {
+    OpName,
+    OperandA,
+    End,
+}
+
+/// How the strings on a data line are encoded.
+#[derive(Clone, Copy, PartialEq)]
+enum StringMode {
+    /// One character per word (`dat`).
+    Plain,
+    /// One character per word, followed by a zero word (`.asciiz`).
+    ZeroTerminated,
+    /// One character per word, preceded by the number of characters (`.pstring`).
+    LengthPrefixed,
+    /// Two characters per word, with the first one in the high byte (`.packed`).
+    Packed,
+    /// One LEM screen cell per character, with a color attribute ORed in (`.lem`).
+    Lem,
+}
+
+/// Result from processing a single line of a program.
+enum LineResult {
+    BasicInstruction(BasicInstructionComponents),
+    SpecialInstruction(SpecialInstructionComponents),
+    Data(DataComponents),
+}
+
+/// Intermediate data used to create a basic instruction.
+struct BasicInstructionComponents {
+    op: BasicOp,
+    b: Operand,
+    a: Operand,
+    line_num: usize,
+    /// Where the op name is.
+    span: Span,
+}
+
+/// Intermediate data used to create a special instruction.
+struct SpecialInstructionComponents {
+    op: SpecialOp,
+    a: Operand,
+    line_num: usize,
+    /// Where the op name is.
+    span: Span,
+}
+
+/// Words from a `dat` line.
+struct DataComponents {
+    words: Vec<u16>,
+    /// Words that hold the address of a label, which gets filled in once labels are laid
+    /// out.
+    labels: Vec<DataLabel>,
+    line_num: usize,
+}
+
+/// A label named on a data line.
+struct DataLabel {
+    /// Index of the word the label's address goes in.
+    index: usize,
+    name: String,
+    span: Span,
+}
+
+/// An operand, along with where it came from, so later passes can report errors on it.
+struct Operand {
+    val: ValType,
+    span: Span,
+    /// Whether a label operand is currently being encoded as an inline literal.
+    short: bool,
+}
+
+/// Where a label was defined.
+struct LabelDef {
+    section: usize,
+    /// Index of the line result the label points at, within its section.
+    line_result_idx: usize,
+    line_num: usize,
+    span: Span,
+}
+
+/// A section, as it's being assembled.
+struct PendingSection {
+    name: String,
+    line_results: Vec<LineResult>,
+    words: Vec<u16>,
+    relocations: Vec<Relocation>,
+}
+
+/// A symbol named by a `.global` or `.extern` directive.
+struct SymbolDirective {
+    name: String,
+    line_num: usize,
+    span: Span,
+}
+
+/// An `.if` (or `.ifdef` or `.ifndef`) block we're inside of.
+struct Conditional {
+    line_num: usize,
+    span: Span,
+    /// Whether lines in the current branch get assembled.
+    active: bool,
+    /// Whether any branch so far was taken, so later ones shouldn't be.
+    taken: bool,
+    seen_else: bool,
+}
+
+/// A token, along with the column it starts at in its line.
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    col: usize,
+}
+
+/// The assembler needs its own value types that store more information than the
+/// value types used by the emulator.
+#[derive(PartialEq)]
+enum ValType {
+    Register(u16),
+    RegisterDeref(u16),
+    NextWordRegisterDeref(u16, u16),
+    Push,
+    Pop,
+    Peek,
+    /// `[SP + n]`, with `n` in the next word.
+    Pick(u16),
+    StackPointer,
+    ProgramCounter,
+    Extra,
+    NextWordDeref(u16),
+    NextWord(u16),
+    Literal(u16),
+    Label(String),
+    LabelDeref(String),
+    LabelRegisterDeref(String, u16),
+    LabelNextWordDeref(String, u16),
+}
+
+/// Settings that control how a source file is assembled.
+pub struct AssemblerOptions {
+    /// The name to report diagnostics against.
+    pub file_name: String,
+    /// Whether label operands are encoded as inline literals when their final values
+    /// allow it.
+    pub relax_labels: bool,
+    /// Whether to add a note saying how many bytes were saved by relaxing labels.
+    pub report_relaxation: bool,
+    /// Names that are defined before the source starts, like with `.define`.  They can
+    /// be tested with `.ifdef` and used as values in expressions and operands.
+    pub defines: HashMap<String,u16>,
+    /// Whether to run the peephole optimizer, and report what it found.  See
+    /// `AssemblerContext::optimize` for what it does.
+    pub optimize: bool,
+    /// Whether the optimizer can also encode `set PC, label` as `add PC, n` or
+    /// `sub PC, n` when that's shorter.  Unlike `set`, those overwrite EX, so this is only
+    /// safe for code that never reads EX after a jump.
+    pub relative_jumps: bool,
+}
+
+/// Everything the assembler has to say about a source file.
+pub struct Assembly {
+    /// The assembled program, or `None` if there were any errors.
+    pub program: Option<AssembledProgram>,
+    /// Errors and warnings, in the order they were found.
+    pub diagnostics: Vec<AssemblerDiagnostic>,
+}
+
+/// Everything the assembler has to say about a source file assembled for linking.
+pub struct ObjectAssembly {
+    /// The object file, or `None` if there were any errors.
+    pub object: Option<ObjectFile>,
+    /// Errors and warnings, in the order they were found.
+    pub diagnostics: Vec<AssemblerDiagnostic>,
+}
+
+struct AssemblerContext<'a> {
+    options: &'a AssemblerOptions,
+    /// Whether we're making an object file, in which case label values are relative to
+    /// their sections, and get relocated by the linker.
+    relocatable: bool,
+    diagnostics: Vec<AssemblerDiagnostic>,
+    /// Sections in the order they first appear.  Everything starts out in `text`.
+    sections: Vec<PendingSection>,
+    curr_section: usize,
+    labels: HashMap<String,LabelDef>,
+    defines: HashMap<String,u16>,
+    conditionals: Vec<Conditional>,
+    exports: Vec<SymbolDirective>,
+    imports: Vec<SymbolDirective>,
+    /// Label values, section start addresses, and the address of every line result in
+    /// every section, as of the most recent layout.
+    addresses: HashMap<String,u16>,
+    section_bases: Vec<u16>,
+    line_result_addresses: Vec<Vec<u16>>,
+    line_map: Vec<LineMapping>,
+}
+
+impl Default for AssemblerOptions {
+    fn default() -> AssemblerOptions {
+        AssemblerOptions {
+            file_name: String::from(DEFAULT_FILE_NAME),
+            relax_labels: true,
+            report_relaxation: false,
+            defines: HashMap::new(),
+            optimize: false,
+            relative_jumps: false,
+        }
+    }
+}
+
+impl<'a> AssemblerContext<'a> {
+    fn new(options: &'a AssemblerOptions, relocatable: bool) -> AssemblerContext<'a> {
+        AssemblerContext {
+            options,
+            relocatable,
+            diagnostics: Vec::new(),
+            sections: vec![PendingSection::new(DEFAULT_SECTION_NAME)],
+            curr_section: 0,
+            labels: HashMap::new(),
+            defines: options.defines.clone(),
+            conditionals: Vec::new(),
+            exports: Vec::new(),
+            imports: Vec::new(),
+            addresses: HashMap::new(),
+            section_bases: Vec::new(),
+            line_result_addresses: Vec::new(),
+            line_map: Vec::new(),
+        }
+    }
+
+    fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|d| d.is_error())
+    }
+
+    fn finish(self, source: &str, words_saved: u16) -> Assembly {
+        let program = if self.has_errors() {
+            None
+        } else {
+            let addresses = self.addresses;
+            let symbols = self.labels.into_iter()
+                .map(|(name, def)| Symbol {
+                    address: addresses[&name],
+                    name,
+                    line: def.line_num,
+                })
+                .collect();
+            let mut words = Vec::new();
+            for section in self.sections {
+                words.extend(section.words);
+            }
+            Some(AssembledProgram::new(words, symbols, self.line_map, words_saved, source))
+        };
+        Assembly {
+            program,
+            diagnostics: self.diagnostics,
+        }
+    }
+
+    fn finish_object(mut self) -> ObjectAssembly {
+        // Exports have to name a label in this file, now that we know them all.
+        let exports = mem::replace(&mut self.exports, Vec::new());
+        for export in &exports {
+            if !self.labels.contains_key(&export.name) {
+                self.report(export.line_num, export.span, DiagnosticCode::UndefinedLabel,
+                            &format!("No label \"{}\" found to export", export.name));
+            }
+        }
+
+        let object = if self.has_errors() {
+            None
+        } else {
+            let mut symbols: Vec<ObjectSymbol> = self.labels.iter()
+                .map(|(name, def)| ObjectSymbol {
+                    name: name.clone(),
+                    section: def.section,
+                    offset: self.addresses[name],
+                    exported: exports.iter().any(|e| e.name == *name),
+                })
+                .collect();
+            symbols.sort_by(|a, b| (a.section, a.offset).cmp(&(b.section, b.offset)));
+            Some(ObjectFile {
+                name: self.options.file_name.clone(),
+                sections: self.sections.into_iter()
+                    .map(|s| Section {
+                        name: s.name,
+                        words: s.words,
+                        relocations: s.relocations,
+                    })
+                    .collect(),
+                symbols,
+                imports: self.imports.into_iter().map(|i| i.name).collect(),
+            })
+        };
+        ObjectAssembly {
+            object,
+            diagnostics: self.diagnostics,
+        }
+    }
+
+    /// Computes the address of every line result and label, given the current sizes of
+    /// the operands.
+    ///
+    /// Sections are placed one after another, in the order they first appear.  When
+    /// we're making an object file, every section starts at 0 instead, since the linker
+    /// decides where they go.
+    fn lay_out(&mut self) {
+        use self::LineResult::*;
+
+        let mut result_addresses = Vec::with_capacity(self.sections.len());
+        self.section_bases.clear();
+        let mut address: u16 = 0;
+        for section in &self.sections {
+            if self.relocatable {
+                address = 0;
+            }
+            self.section_bases.push(address);
+            let mut section_addresses = Vec::with_capacity(section.line_results.len() + 1);
+            for lr in &section.line_results {
+                section_addresses.push(address);
+                let size = match *lr {
+                    BasicInstruction(ref i) => 1 + i.b.num_words() + i.a.num_words(),
+                    SpecialInstruction(ref i) => 1 + i.a.num_words(),
+                    Data(ref d) => d.words.len() as u16,
+                };
+                address = address.wrapping_add(size);
+            }
+            // Labels at the very end of a section point just past it.
+            section_addresses.push(address);
+            result_addresses.push(section_addresses);
+        }
+
+        self.addresses = self.labels.iter()
+            .map(|(name, def)| {
+                (name.clone(), result_addresses[def.section][def.line_result_idx])
+            })
+            .collect();
+        self.line_result_addresses = result_addresses;
+    }
+
+    /// Lays out the program, encoding label operands as inline literals wherever their
+    /// final values allow it, and returns the number of words that saved.
+    ///
+    /// Every candidate starts out short, and is only ever lengthened, so this always
+    /// settles.  It can take several rounds, because lengthening one operand moves every
+    /// label after it, which might push other operands out of range.
+    fn relax(&mut self) -> u16 {
+        use self::LineResult::*;
+
+        loop {
+            self.lay_out();
+            let mut changed = false;
+            for section in 0..self.sections.len() {
+                for idx in 0..self.sections[section].line_results.len() {
+                    if self.short_operand_fits(section, idx) != Some(false) {
+                        continue;
+                    }
+                    match self.sections[section].line_results[idx] {
+                        BasicInstruction(ref mut i) => i.a.short = false,
+                        SpecialInstruction(ref mut i) => i.a.short = false,
+                        Data(_) => (),
+                    }
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        self.sections.iter()
+            .flat_map(|s| s.line_results.iter())
+            .filter(|lr| match **lr {
+                BasicInstruction(ref i) => i.a.short,
+                SpecialInstruction(ref i) => i.a.short,
+                Data(_) => false,
+            })
+            .count() as u16
+    }
+
+    /// Returns whether the short label operand of a line result still fits in an inline
+    /// literal, given the current layout, or `None` if it doesn't have one.
+    fn short_operand_fits(&self, section: usize, idx: usize) -> Option<bool> {
+        use self::LineResult::*;
+
+        let (operand, jump) = match self.sections[section].line_results[idx] {
+            BasicInstruction(ref i) => (&i.a, self.is_relaxable_jump(i)),
+            SpecialInstruction(ref i) => (&i.a, false),
+            Data(_) => return None,
+        };
+        if !operand.short {
+            return None;
+        }
+        let name = match operand.val {
+            ValType::Label(ref s) => s,
+            _ => return Some(false),
+        };
+        let value = match self.addresses.get(name) {
+            Some(&v) => v,
+            None => return Some(false),
+        };
+        if !self.relocatable && fits_in_literal(value) {
+            return Some(true);
+        }
+        // Jumps within a section can also be made relative, which doesn't need relocating.
+        let same_section = self.labels.get(name).map(|def| def.section) == Some(section);
+        let next = self.line_result_addresses[section][idx].wrapping_add(1);
+        Some(jump && same_section && relative_jump(next, value).is_some())
+    }
+
+    /// Whether `instr` is `set PC, label`, and the options let us encode it as
+    /// `add PC, n` or `sub PC, n` when the label is too far away to be an inline literal.
+    fn is_relaxable_jump(&self, instr: &BasicInstructionComponents) -> bool {
+        self.options.optimize && self.options.relative_jumps && is_jump_to_label(instr)
+    }
+
+    /// Does a peephole pass over every section, before anything's laid out:
+    ///
+    /// * Instructions that don't do anything are removed, like `set A, A` (which is what
+    ///   `nop` assembles to), `add A, 0`, `mul A, 1` or `and A, 0xffff`, as long as they
+    ///   don't follow a conditional.  Some of them would have cleared EX, but nothing
+    ///   that relies on that should be using them.
+    /// * Code after an unconditional jump (`set PC, ...`, `rfi`, or a `sub PC, n` that
+    ///   jumps back) is removed, up to the next label or data.
+    ///
+    /// `add PC, n` and `sub PC, n` jump a fixed number of words, so the code they could
+    /// land on or jump over is left as it is, like it's labeled.  Sections that add or
+    /// subtract anything but a literal from PC are left alone entirely.
+    /// * If `relative_jumps` is on, `set PC, label` is marked so relaxation can also make
+    ///   it relative.
+    ///
+    /// Labels that nothing refers to are reported too, along with the code that was
+    /// removed for being unreachable.
+    fn optimize(&mut self) {
+        use self::LineResult::*;
+
+        let used = self.used_labels();
+        let mut unused: Vec<(&String, &LabelDef)> = self.labels.iter()
+            .filter(|&(name, _)| !used.contains(name))
+            .collect();
+        unused.sort_by_key(|&(_, def)| def.line_num);
+        let unused: Vec<(usize, Span, String)> = unused.into_iter()
+            .map(|(name, def)| (def.line_num, def.span, name.clone()))
+            .collect();
+        for (line_num, span, name) in unused {
+            self.report(line_num, span, DiagnosticCode::UnusedLabel,
+                        &format!("Label \"{}\" is never used", name));
+        }
+
+        let mut words_removed: u32 = 0;
+        for section in 0..self.sections.len() {
+            let line_results = mem::replace(&mut self.sections[section].line_results,
+                                            Vec::new());
+            // Jumps by a register or memory could land anywhere, so nothing can be removed
+            // without changing where they go.
+            if line_results.iter().any(is_computed_jump) {
+                self.sections[section].line_results = line_results;
+                continue;
+            }
+            let mut labeled = vec![false; line_results.len() + 1];
+            for def in self.labels.values().filter(|def| def.section == section) {
+                labeled[def.line_result_idx] = true;
+            }
+            let jumped_over = backward_jump_spans(&line_results);
+
+            let mut kept = Vec::with_capacity(line_results.len());
+            // Where each line result ends up, so labels can follow them.
+            let mut new_indices = Vec::with_capacity(line_results.len() + 1);
+            let mut after_conditional = false;
+            let mut reachable = true;
+            // Whether the current run of unreachable code has been reported yet.
+            let mut reported = false;
+            // How many more line results an `add PC, ...` could land on.
+            let mut landing_area: usize = 0;
+            for (idx, lr) in line_results.into_iter().enumerate() {
+                new_indices.push(kept.len());
+                if labeled[idx] || is_data(&lr) || landing_area > 0 || jumped_over[idx] {
+                    reachable = true;
+                }
+                if !reachable {
+                    let (line_num, span, size) = instruction_extent(&lr);
+                    words_removed += size as u32;
+                    if !reported {
+                        self.report(line_num, span, DiagnosticCode::UnreachableCode,
+                                    "This code can never run, so it was removed");
+                        reported = true;
+                    }
+                    continue;
+                }
+                // Removing anything a jump lands on or skips over would change where it
+                // lands.
+                let in_landing_area = landing_area > 0 || jumped_over[idx];
+                landing_area = landing_area.saturating_sub(1);
+                if !after_conditional && !in_landing_area && does_nothing(&lr) {
+                    words_removed += instruction_extent(&lr).2 as u32;
+                    continue;
+                }
+                if !after_conditional && is_unconditional_jump(&lr) {
+                    reachable = false;
+                    reported = false;
+                }
+                landing_area = landing_area.max(forward_jump_reach(&lr));
+                after_conditional = match lr {
+                    BasicInstruction(ref i) => i.op.is_conditional(),
+                    _ => false,
+                };
+                kept.push(lr);
+            }
+            new_indices.push(kept.len());
+
+            for def in self.labels.values_mut().filter(|def| def.section == section) {
+                def.line_result_idx = new_indices[def.line_result_idx];
+            }
+            self.sections[section].line_results = kept;
+        }
+
+        // Jumps start out short, like other label operands, and relaxation lengthens the
+        // ones that don't fit either way.
+        if self.options.relative_jumps {
+            let line_results = self.sections.iter_mut()
+                .flat_map(|s| s.line_results.iter_mut());
+            for lr in line_results {
+                if let BasicInstruction(ref mut i) = *lr {
+                    if is_jump_to_label(i) {
+                        i.a.short = true;
+                    }
+                }
+            }
+        }
+
+        self.report(0, Span::new(0, 0), DiagnosticCode::OptimizationSavings,
+                    &format!("Optimizing removed {} bytes ({} words)",
+                             words_removed * 2, words_removed));
+    }
+
+    /// Returns every label that's referred to by an operand, a data line or an export.
+    fn used_labels(&self) -> HashSet<String> {
+        use self::LineResult::*;
+
+        let mut used: HashSet<String> = self.exports.iter().map(|e| e.name.clone()).collect();
+        for lr in self.sections.iter().flat_map(|s| s.line_results.iter()) {
+            match *lr {
+                BasicInstruction(ref i) => {
+                    used.extend(i.b.val.label_name().map(String::from));
+                    used.extend(i.a.val.label_name().map(String::from));
+                },
+                SpecialInstruction(ref i) => used.extend(i.a.val.label_name().map(String::from)),
+                Data(ref d) => used.extend(d.labels.iter().map(|l| l.name.clone())),
+            }
+        }
+        used
+    }
+
+    /// Appends `words` to the current section, remembering which line they came from and
+    /// which of them need relocating.
+    fn append_words(&mut self, words: Vec<(u16, Option<RelocationTarget>)>, line_num: usize) {
+        let section = &mut self.sections[self.curr_section];
+        let offset = section.words.len();
+        self.line_map.push(LineMapping {
+            address: self.section_bases[self.curr_section].wrapping_add(offset as u16),
+            num_words: words.len() as u16,
+            line: line_num,
+        });
+        for (i, (word, target)) in words.into_iter().enumerate() {
+            if let Some(target) = target {
+                section.relocations.push(Relocation {
+                    offset: (offset + i) as u16,
+                    target,
+                });
+            }
+            section.words.push(word);
+        }
+    }
+
+    fn append_data(&mut self, data: DataComponents) {
+        let mut words: Vec<(u16, Option<RelocationTarget>)> = data.words.into_iter()
+            .map(|w| (w, None))
+            .collect();
+        for label in data.labels {
+            match self.resolve_label(&label.name) {
+                Ok(resolved) => words[label.index] = resolved,
+                Err(e) => {
+                    self.report(data.line_num, label.span, DiagnosticCode::UndefinedLabel, &e);
+                },
+            }
+        }
+        self.append_words(words, data.line_num);
+    }
+
+    fn report(&mut self, line_num: usize, span: Span, code: DiagnosticCode, message: &str) {
+        let diagnostic = AssemblerDiagnostic::new(
+            &self.options.file_name, line_num, span, code, String::from(message));
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn append_special_instruction(&mut self, instr: SpecialInstructionComponents) {
+        use super::instruction::make_instruction_bits;
+
+        let a_span = instr.a.span;
+        let mut data_words = Vec::new();
+        let a_val = match self.process_val_type(instr.a, &mut data_words) {
+            Ok(vt) => vt,
+            Err(e) => {
+                self.report(instr.line_num, a_span, DiagnosticCode::UndefinedLabel, &e);
+                return;
+            }
+        };
+
+        let op_code = instr.op.op_code();
+        let a_code = a_val.val_code();
+        // Special instructions have their lower 5 bits unset.
+        // TODO: This is using `op_code` in the place of the `b_code` argument.  Hack!
+        let instruction = make_instruction_bits(a_code, op_code, 0x0);
+        data_words.insert(0, (instruction, None));
+        self.append_words(data_words, instr.line_num);
+    }
+
+    fn append_normal_instruction(&mut self, instr: BasicInstructionComponents) {
+        use super::instruction::make_instruction_bits;
+
+        let mut instr = instr;
+        if instr.a.short && self.is_relaxable_jump(&instr) {
+            self.make_jump_relative(&mut instr);
+        }
+        let (b_span, a_span) = (instr.b.span, instr.a.span);
+        let mut data_words = Vec::new();
+        let b_val = self.process_val_type(instr.b, &mut data_words);
+        let a_val = self.process_val_type(instr.a, &mut data_words);
+        // Report both operands before giving up, so every undefined label gets reported.
+        if let Err(ref e) = b_val {
+            self.report(instr.line_num, b_span, DiagnosticCode::UndefinedLabel, e);
+        }
+        if let Err(ref e) = a_val {
+            self.report(instr.line_num, a_span, DiagnosticCode::UndefinedLabel, e);
+        }
+        let (b_val, a_val) = match (b_val, a_val) {
+            (Ok(b), Ok(a)) => (b, a),
+            _ => return,
+        };
+
+        let op_code = instr.op.op_code();
+        let b_code = b_val.val_code();
+        let a_code = a_val.val_code();
+        let instruction = make_instruction_bits(a_code, b_code, op_code);
+        data_words.insert(0, (instruction, None));
+        self.append_words(data_words, instr.line_num);
+    }
+
+    /// Rewrites a short `set PC, label` as `add PC, n` or `sub PC, n`, unless the label
+    /// fits in an inline literal as it is.  Relaxation already made sure one of them works.
+    fn make_jump_relative(&self, instr: &mut BasicInstructionComponents) {
+        let target = match instr.a.val {
+            ValType::Label(ref s) => match self.addresses.get(s) {
+                Some(&v) => v,
+                None => return,
+            },
+            _ => return,
+        };
+        if !self.relocatable && fits_in_literal(target) {
+            return;
+        }
+        let section = &self.sections[self.curr_section];
+        let next = self.section_bases[self.curr_section]
+            .wrapping_add(section.words.len() as u16)
+            .wrapping_add(1);
+        if let Some((op, offset)) = relative_jump(next, target) {
+            instr.op = op;
+            instr.a.val = ValType::Literal(offset);
+        }
+    }
+
+    /// Converts from the assembler's value types into the emulator's value types, and
+    /// mutates the program to include any data held in the assembler's value types.
+    ///
+    /// # Arguments
+    ///
+    /// * `data_words` - A vector of words to push extra data into, so it can eventually be
+    ///                  included in the instruction, along with what the linker has to
+    ///                  relocate them against, if anything.
+    fn process_val_type(&self, operand: Operand,
+                        data_words: &mut Vec<(u16, Option<RelocationTarget>)>)
+        -> Result<val_type::ValType,String> {
+        use self::ValType::*;
+        let short = operand.short;
+        match operand.val {
+            Register(r) => Ok(val_type::ValType::Register(r)),
+            RegisterDeref(r) => Ok(val_type::ValType::RegisterDeref(r)),
+            NextWordRegisterDeref(v, r) => {
+                data_words.push((v, None));
+                Ok(val_type::ValType::RegisterNextWordDeref(r))
+            },
+            Push => Ok(val_type::ValType::Push),
+            Pop => Ok(val_type::ValType::Pop),
+            Peek => Ok(val_type::ValType::Peek),
+            Pick(v) => {
+                data_words.push((v, None));
+                Ok(val_type::ValType::Pick)
+            },
+            StackPointer => Ok(val_type::ValType::StackPointer),
+            ProgramCounter => Ok(val_type::ValType::ProgramCounter),
+            Extra => Ok(val_type::ValType::Extra),
+            NextWordDeref(v) => {
+                data_words.push((v, None));
+                Ok(val_type::ValType::NextWordDeref)
+            },
+            NextWord(v) => {
+                data_words.push((v, None));
+                Ok(val_type::ValType::NextWord)
+            },
+            Literal(v) => Ok(val_type::ValType::Literal(v)),
+            Label(s) => {
+                let (v, target) = self.resolve_label(&s)?;
+                if short {
+                    // Relaxation already made sure short labels fit.
+                    Ok(val_type::ValType::Literal(v))
+                } else {
+                    data_words.push((v, target));
+                    Ok(val_type::ValType::NextWord)
+                }
+            },
+            LabelDeref(s) => {
+                data_words.push(self.resolve_label(&s)?);
+                Ok(val_type::ValType::NextWordDeref)
+            },
+            LabelRegisterDeref(s, r) => {
+                data_words.push(self.resolve_label(&s)?);
+                Ok(val_type::ValType::RegisterNextWordDeref(r))
+            },
+            LabelNextWordDeref(s, v) => {
+                let (x, target) = self.resolve_label(&s)?;
+                data_words.push((x.wrapping_add(v), target));
+                Ok(val_type::ValType::NextWordDeref)
+            }
+        }
+    }
+
+    /// Returns the value of a label, along with what the linker has to relocate it
+    /// against, if anything.
+    fn resolve_label(&self, name: &str) -> Result<(u16, Option<RelocationTarget>), String> {
+        if let Some(def) = self.labels.get(name) {
+            let target = if self.relocatable {
+                Some(RelocationTarget::Section(def.section))
+            } else {
+                None
+            };
+            Ok((self.addresses[name], target))
+        } else if self.imports.iter().any(|i| i.name == name) {
+            if self.relocatable {
+                Ok((0, Some(RelocationTarget::Import(String::from(name)))))
+            } else {
+                Err(format!("Label \"{}\" is imported, so the program has to be linked",
+                            name))
+            }
+        } else {
+            Err(format!("No label \"{}\" found", name))
+        }
+    }
+
+    fn process_line(&mut self, line_num: usize, line: &str) {
+        let tokens: Vec<Token> = tokenize(line);
+        if tokens.len() == 0 {
+            // Empty line
+            return;
+        }
+        // Where the code on this line ends, so incomplete lines can point past it.
+        let line_end = token_span(&tokens[tokens.len() - 1]).end;
+
+        if self.process_conditional(&tokens, line, line_num, line_end) {
+            return;
+        }
+        if !self.conditionals.iter().all(|c| c.active) {
+            // We're in a branch of an `.if` that wasn't taken.
+            return;
+        }
+
+        // Check if the line begins with a label, either as ":label" or "label:".
+        let label = if tokens[0].text.starts_with(":") {
+            Some(&tokens[0].text[1..])
+        } else if tokens[0].text.len() > 1 && tokens[0].text.ends_with(":") &&
+                  !is_quote(tokens[0].text.as_bytes()[0]) {
+            Some(&tokens[0].text[..tokens[0].text.len() - 1])
+        } else {
+            None
+        };
+        let tokens = if let Some(label) = label {
+            // If it does, process it and remove it from `tokens`.  A duplicate label is
+            // reported, but the rest of the line is still assembled, so we can report
+            // any errors in it too.
+            if self.labels.contains_key(label) || self.imports.iter().any(|i| i.name == label) {
+                self.report(line_num, token_span(&tokens[0]), DiagnosticCode::DuplicateLabel,
+                            &format!("Label \"{}\" already exists", label));
+            } else {
+                let label_def = LabelDef {
+                    section: self.curr_section,
+                    line_result_idx: self.sections[self.curr_section].line_results.len(),
+                    line_num,
+                    span: token_span(&tokens[0]),
+                };
+                self.labels.insert(String::from(label), label_def);
+            }
+            &tokens[1..]
+        } else {
+            &tokens[..]
+        };
+
+        if tokens.len() == 0 {
+            // Empty line
+            return;
+        }
+
+        let expanded;
+        let tokens = match self.expand_pseudo_op(tokens, line_num, line_end) {
+            Ok(Some(e)) => {
+                expanded = e;
+                &expanded[..]
+            },
+            Ok(None) => tokens,
+            Err(()) => return,
+        };
+
+        if tokens[0].text.starts_with(".") && string_mode(tokens[0].text).is_none() {
+            self.process_directive(tokens, line, line_num, line_end);
+            return;
+        }
+
+        let line_result = if let Some(mode) = string_mode(tokens[0].text) {
+            // Line contains a data instruction.
+            match self.process_data_line(tokens, line_num, mode) {
+                Some((words, labels)) => {
+                    Some(LineResult::Data(DataComponents { words, labels, line_num }))
+                },
+                None => None,
+            }
+        } else if SpecialOp::try_from(tokens[0].text).is_some() {
+            // Line contains a special instruction.
+            match self.process_special_line(tokens, line_num, line_end) {
+                Some(ic) => Some(LineResult::SpecialInstruction(ic)),
+                None => None,
+            }
+        } else {
+            // Line contains a basic instruction.
+            match self.process_basic_line(tokens, line_num, line_end) {
+                Some(ic) => Some(LineResult::BasicInstruction(ic)),
+                None => None,
+            }
+        };
+        if let Some(lr) = line_result {
+            self.sections[self.curr_section].line_results.push(lr);
+        }
+    }
+
+    /// Rewrites pseudo-instructions in terms of real ones:
+    ///
+    /// * `jmp X` is `set PC, X`
+    /// * `ret` is `set PC, POP`
+    /// * `brk` and `hlt` are `sub PC, 1`, which loops in place and halts the emulator
+    /// * `nop` is `set A, A`
+    /// * `push X` is `set PUSH, X`
+    /// * `pop X` is `set X, POP`
+    ///
+    /// Returns `Ok(None)` if `tokens` doesn't start with a pseudo-instruction, and `Err`
+    /// if it's missing its operand or has too many.
+    fn expand_pseudo_op<'t>(&mut self, tokens: &[Token<'t>], line_num: usize,
+                            line_end: usize) -> Result<Option<Vec<Token<'t>>>,()> {
+        let name = tokens[0].text.to_lowercase();
+        let (num_operands, before, after): (usize, &[&'static str], &[&'static str]) =
+            match name.as_str() {
+                "jmp" => (1, &["set", "PC", ","], &[]),
+                "ret" => (0, &["set", "PC", ",", "POP"], &[]),
+                "brk" | "hlt" => (0, &["sub", "PC", ",", "1"], &[]),
+                "nop" => (0, &["set", "A", ",", "A"], &[]),
+                "push" => (1, &["set", "PUSH", ","], &[]),
+                "pop" => (1, &["set"], &[",", "POP"]),
+                _ => return Ok(None),
+            };
+
+        let operands = &tokens[1..];
+        if operands.len() < num_operands {
+            self.report(line_num, Span::new(line_end, line_end + 1),
+                        DiagnosticCode::IncompleteLine, "Incomplete line");
+            return Err(());
+        }
+        if operands.len() > num_operands {
+            let extra = &operands[num_operands];
+            self.report(line_num, token_span(extra), DiagnosticCode::ExpectedLineEnd,
+                        &format!("Expected line to end; got \"{}\"", extra.text));
+            return Err(());
+        }
+
+        // The generated tokens borrow the pseudo-op's position, so any errors in them
+        // still point somewhere sensible.
+        let col = tokens[0].col;
+        let make_token = |text: &'static str| Token { text, col };
+        let mut result: Vec<Token> = before.iter().map(|t| make_token(t)).collect();
+        result.extend(operands.iter().cloned());
+        result.extend(after.iter().map(|t| make_token(t)));
+        Ok(Some(result))
+    }
+
+    /// Handles `.if`, `.ifdef`, `.ifndef`, `.elif`, `.else` and `.endif`, returning
+    /// whether `tokens` was one of them.
+    ///
+    /// Conditions are only evaluated when they could affect the output, so undefined
+    /// names in branches that are skipped anyway aren't errors.
+    fn process_conditional(&mut self, tokens: &[Token], line: &str, line_num: usize,
+                           line_end: usize) -> bool {
+        let directive = tokens[0].text.to_lowercase();
+        let span = token_span(&tokens[0]);
+        let parent_active = self.conditionals.iter().all(|c| c.active);
+        match directive.as_str() {
+            ".if" | ".ifdef" | ".ifndef" => {
+                let active = parent_active &&
+                    self.evaluate_condition(&directive, tokens, line, line_num, line_end);
+                self.conditionals.push(Conditional {
+                    line_num,
+                    span,
+                    active,
+                    // Nothing in a skipped block gets assembled, so act like a branch was
+                    // already taken.
+                    taken: active || !parent_active,
+                    seen_else: false,
+                });
+            },
+            ".elif" | ".else" => {
+                let (taken, seen_else) = match self.conditionals.last() {
+                    Some(c) => (c.taken, c.seen_else),
+                    None => {
+                        self.report(line_num, span, DiagnosticCode::UnbalancedConditional,
+                                    &format!("{} without a matching .if", directive));
+                        return true;
+                    },
+                };
+                if seen_else {
+                    self.report(line_num, span, DiagnosticCode::UnbalancedConditional,
+                                &format!("{} after .else", directive));
+                    return true;
+                }
+                let active = if directive == ".else" {
+                    if tokens.len() > 1 {
+                        self.report(line_num, token_span(&tokens[1]),
+                                    DiagnosticCode::ExpectedLineEnd,
+                                    &format!("Expected line to end; got \"{}\"",
+                                             tokens[1].text));
+                    }
+                    !taken
+                } else {
+                    !taken && self.evaluate_condition(&directive, tokens, line, line_num,
+                                                      line_end)
+                };
+                let conditional = self.conditionals.last_mut().unwrap();
+                conditional.active = active;
+                conditional.taken = taken || active;
+                conditional.seen_else = directive == ".else";
+            },
+            ".endif" => {
+                if self.conditionals.pop().is_none() {
+                    self.report(line_num, span, DiagnosticCode::UnbalancedConditional,
+                                ".endif without a matching .if");
+                }
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    /// Evaluates the condition of an `.if`, `.elif`, `.ifdef` or `.ifndef`.  Invalid
+    /// conditions are reported, and count as false.
+    fn evaluate_condition(&mut self, directive: &str, tokens: &[Token], line: &str,
+                          line_num: usize, line_end: usize) -> bool {
+        if tokens.len() < 2 {
+            let span = token_span(&tokens[0]);
+            self.report(line_num, Span::new(span.end, span.end + 1),
+                        DiagnosticCode::IncompleteLine,
+                        &format!("Expected a condition after {}", directive));
+            return false;
+        }
+        if directive == ".ifdef" || directive == ".ifndef" {
+            if tokens.len() > 2 {
+                self.report(line_num, token_span(&tokens[2]), DiagnosticCode::ExpectedLineEnd,
+                            &format!("Expected line to end; got \"{}\"", tokens[2].text));
+                return false;
+            }
+            let defined = self.defines.contains_key(tokens[1].text);
+            return defined == (directive == ".ifdef");
+        }
+
+        let expr = &line[tokens[1].col..line_end];
+        match expr::evaluate(expr, &self.defines) {
+            Ok(v) => v != 0,
+            Err(e) => {
+                self.report(line_num, Span::new(tokens[1].col, line_end),
+                            DiagnosticCode::InvalidExpression, &e);
+                false
+            },
+        }
+    }
+
+    /// Reports every `.if` that never got closed.
+    fn finish_conditionals(&mut self) {
+        let conditionals = mem::replace(&mut self.conditionals, Vec::new());
+        for c in conditionals {
+            self.report(c.line_num, c.span, DiagnosticCode::UnbalancedConditional,
+                        "Missing .endif");
+        }
+    }
+
+    /// Handles directives that don't produce any words.
+    fn process_directive(&mut self, tokens: &[Token], line: &str, line_num: usize,
+                         line_end: usize) {
+        let directive = tokens[0].text;
+        let lowercase = directive.to_lowercase();
+        let names: Vec<&Token> = tokens[1..].iter().filter(|t| t.text != ",").collect();
+        if names.len() == 0 {
+            let span = token_span(&tokens[0]);
+            self.report(line_num, Span::new(span.end, span.end + 1),
+                        DiagnosticCode::IncompleteLine,
+                        &format!("Expected a name after {}", directive));
+            return;
+        }
+
+        match lowercase.as_str() {
+            ".define" => {
+                // `.define NAME` is the same as `.define NAME 1`.
+                let name = tokens[1].text;
+                let value = if tokens.len() > 2 {
+                    let expr = &line[tokens[2].col..line_end];
+                    match expr::evaluate(expr, &self.defines) {
+                        Ok(v) => {
+                            if v < i16::min_value() as i64 || v > u16::max_value() as i64 {
+                                self.report(line_num, Span::new(tokens[2].col, line_end),
+                                            DiagnosticCode::LiteralTruncated,
+                                            &format!("Value of {} truncated to {:#x}",
+                                                     name, v as u16));
+                            }
+                            v as u16
+                        },
+                        Err(e) => {
+                            self.report(line_num, Span::new(tokens[2].col, line_end),
+                                        DiagnosticCode::InvalidExpression, &e);
+                            return;
+                        },
+                    }
+                } else {
+                    1
+                };
+                self.defines.insert(String::from(name), value);
+            },
+            ".section" => {
+                if names.len() > 1 {
+                    self.report(line_num, token_span(names[1]), DiagnosticCode::ExpectedLineEnd,
+                                &format!("Expected line to end; got \"{}\"", names[1].text));
+                    return;
+                }
+                let name = names[0].text;
+                self.curr_section = match self.sections.iter().position(|s| s.name == name) {
+                    Some(idx) => idx,
+                    None => {
+                        self.sections.push(PendingSection::new(name));
+                        self.sections.len() - 1
+                    },
+                };
+            },
+            ".global" | ".export" | ".extern" | ".import" => {
+                let importing = lowercase == ".extern" || lowercase == ".import";
+                for name in names {
+                    if importing && self.labels.contains_key(name.text) {
+                        self.report(line_num, token_span(name), DiagnosticCode::DuplicateLabel,
+                                    &format!("Label \"{}\" already exists", name.text));
+                        continue;
+                    }
+                    let symbol = SymbolDirective {
+                        name: String::from(name.text),
+                        line_num,
+                        span: token_span(name),
+                    };
+                    if importing {
+                        self.imports.push(symbol);
+                    } else {
+                        self.exports.push(symbol);
+                    }
+                }
+            },
+            _ => {
+                self.report(line_num, token_span(&tokens[0]), DiagnosticCode::UnknownDirective,
+                            &format!("Unknown directive \"{}\"", directive));
+            },
+        }
+    }
+
+    fn process_data_line(&mut self, tokens: &[Token], line_num: usize, mode: StringMode)
+        -> Option<(Vec<u16>, Vec<DataLabel>)> {
+        use super::literal;
+
+        let mut data: Vec<u16> = Vec::new();
+        let mut labels = Vec::new();
+        let mut expect_comma = false;
+        // For `.lem` lines, the leading color attribute.
+        let mut attribute: Option<u16> = None;
+        for token in &tokens[1..] {
+            if expect_comma {
+                if token.text != "," {
+                    self.report(line_num, token_span(token), DiagnosticCode::ExpectedComma,
+                                &format!("Expected comma; got \"{}\"", token.text));
+                    return None;
+                }
+                expect_comma = false;
+                continue;
+            }
+
+            let value = if literal::is_literal(token.text) {
+                Some(self.parse_literal(token.text, line_num, token_span(token)))
+            } else {
+                self.defines.get(token.text).map(|&v| Ok(v))
+            };
+            if let Some(value) = value {
+                match value {
+                    Ok(v) if mode == StringMode::Lem && attribute.is_none() => {
+                        if v & 0x7f != 0 {
+                            self.report(line_num, token_span(token),
+                                        DiagnosticCode::InvalidData,
+                                        &format!("Color attribute {:#x} overlaps the \
+                                                  character bits", v));
+                            return None;
+                        }
+                        attribute = Some(v);
+                    },
+                    Ok(v) => data.push(v),
+                    Err(e) => {
+                        self.report(line_num, token_span(token),
+                                    DiagnosticCode::InvalidLiteral, &e);
+                        return None;
+                    },
+                }
+            } else if token.text.starts_with('"') {
+                if token.text.len() < 2 || !token.text.ends_with('"') {
+                    self.report(line_num, token_span(token), DiagnosticCode::InvalidStringByte,
+                                "Unterminated string");
+                    return None;
+                }
+                // Strip off quotes.
+                let string = &token.text[1..token.text.len()-1];
+                if mode == StringMode::Lem && attribute.is_none() {
+                    self.report(line_num, token_span(token), DiagnosticCode::InvalidData,
+                                "Expected a color attribute before the first string");
+                    return None;
+                }
+                let encoded = literal::unescape(string)
+                    .and_then(|chars| encode_string(&chars, mode, attribute.unwrap_or(0)));
+                match encoded {
+                    Ok(words) => data.extend(words),
+                    Err((offset, e)) => {
+                        // Point at the offending character, past the opening quote.
+                        let col = token.col + 1 + offset;
+                        self.report(line_num, Span::new(col, col + 1),
+                                    DiagnosticCode::InvalidStringByte, &e);
+                        return None;
+                    },
+                }
+            } else if is_label_name(token.text) && mode != StringMode::Lem {
+                // Leave room for the label's address.
+                labels.push(DataLabel {
+                    index: data.len(),
+                    name: String::from(token.text),
+                    span: token_span(token),
+                });
+                data.push(0);
+            } else {
+                self.report(line_num, token_span(token), DiagnosticCode::InvalidData,
+                            &format!("Unrecognized data format \"{}\"", token.text));
+                return None;
+            }
+            expect_comma = true;
+        }
+        Some((data, labels))
+    }
+
+    fn process_special_line(&mut self, tokens: &[Token], line_num: usize, line_len: usize)
+                          -> Option<SpecialInstructionComponents> {
+        use self::SpecialInstructionParseState::*;
+
+        let mut state = OpName;
+        let mut result_op = None;
+        let mut result_a = None;
+        for token in tokens {
+            match state {
+                OpName => {
+                    match SpecialOp::try_from(token.text) {
+                        Some(op) => result_op = Some(op),
+                        None => {
+                            self.report(line_num, token_span(token), DiagnosticCode::UnknownOp,
+                                        &format!("Expected an op name; got \"{}\"",
+                                                 token.text));
+                            return None;
+                        },
+                    }
+                    state = OperandA;
+                },
+                OperandA => {
+                    match self.get_val_type(token.text, line_num, token_span(token)) {
+                        Ok(vt) => result_a = Some(self.make_a_operand(vt, token)),
+                        Err((code, e)) => {
+                            self.report(line_num, token_span(token), code, &e);
+                            return None;
+                        },
+                    }
+
+                    state = End;
+                },
+                End => {
+                    self.report(line_num, token_span(token), DiagnosticCode::ExpectedLineEnd,
+                                &format!("Expected line to end; got \"{}\"", token.text));
+                    return None;
+                },
+            }
+        }
+
+        if state == End && result_op.is_some() && result_a.is_some() {
+            Some(SpecialInstructionComponents {
+                op: result_op.unwrap(),
+                a: result_a.unwrap(),
+                line_num,
+                span: token_span(&tokens[0]),
+            })
+        } else {
+            // If we're at the `OpName` state, then we've only seen a label on this line,
+            // which is fine.  Otherwise, the line is incomplete.
+            if state != OpName {
+                self.report(line_num, Span::new(line_len, line_len + 1),
+                            DiagnosticCode::IncompleteLine, "Incomplete line");
+            }
+            None
+        }
+    }
+
+    fn process_basic_line(&mut self, tokens: &[Token], line_num: usize, line_len: usize)
+                          -> Option<BasicInstructionComponents> {
+        use self::BasicInstructionParseState::*;
+        use self::ValType::*;
+
+        let mut state = OpName;
+        let mut result_op = None;
+        let mut result_b = None;
+        let mut result_a = None;
+        for token in tokens {
+            match state {
+                OpName => {
+                    match BasicOp::try_from(token.text) {
+                        Some(op) => result_op = Some(op),
+                        None => {
+                            self.report(line_num, token_span(token), DiagnosticCode::UnknownOp,
+                                        &format!("Expected an op name; got \"{}\"",
+                                                 token.text));
+                            return None;
+                        },
+                    }
+                    state = OperandB;
+                },
+                OperandB => {
+                    match self.get_val_type(token.text, line_num, token_span(token)) {
+                        Ok(vt) => {
+                            // The `b` field is too small to hold an inline literal.
+                            let vt = match vt {
+                                Literal(v) => NextWord(v),
+                                vt => vt,
+                            };
+                            // Writes to literals are silently ignored by the DCPU, which
+                            // is almost never what the programmer wanted.
+                            let writes_b = match result_op {
+                                Some(ref op) => !op.is_conditional(),
+                                None => false,
+                            };
+                            let unwritable = match vt {
+                                Label(ref s) => Some(format!("Label \"{}\"", s)),
+                                Literal(_) | NextWord(_) => Some(String::from("Literal")),
+                                _ => None,
+                            };
+                            if let (true, Some(what)) = (writes_b, unwritable) {
+                                self.report(line_num, token_span(token),
+                                            DiagnosticCode::LabelAsLvalue,
+                                            &format!("{} used as an lvalue; writes to it \
+                                                      will be ignored", what));
+                            }
+                            result_b = Some(Operand {
+                                val: vt,
+                                span: token_span(token),
+                                short: false,
+                            });
+                        },
+                        Err((code, e)) => {
+                            self.report(line_num, token_span(token), code, &e);
+                            return None;
+                        },
+                    }
+                    state = Comma;
+                },
+                Comma => {
+                    if token.text == "," {
+                        state = OperandA;
+                    } else {
+                        self.report(line_num, token_span(token), DiagnosticCode::ExpectedComma,
+                                    &format!("Expected comma; got \"{}\"", token.text));
+                        return None;
+                    }
+                }
+                OperandA => {
+                    match self.get_val_type(token.text, line_num, token_span(token)) {
+                        Ok(vt) => result_a = Some(self.make_a_operand(vt, token)),
+                        Err((code, e)) => {
+                            self.report(line_num, token_span(token), code, &e);
+                            return None;
+                        },
+                    }
+
+                    state = End;
+                },
+                End => {
+                    self.report(line_num, token_span(token), DiagnosticCode::ExpectedLineEnd,
+                                &format!("Expected line to end; got \"{}\"", token.text));
+                    return None;
+                },
+            }
+        }
+
+        if state == End && result_op.is_some() && result_b.is_some() &&
+            result_a.is_some() {
+            Some(BasicInstructionComponents {
+                op: result_op.unwrap(),
+                b: result_b.unwrap(),
+                a: result_a.unwrap(),
+                line_num,
+                span: token_span(&tokens[0]),
+            })
+        } else {
+            // If we're at the `OpName` state, then we've only seen a label on this line,
+            // which is fine.  Otherwise, the line is incomplete.
+            if state != OpName {
+                self.report(line_num, Span::new(line_len, line_len + 1),
+                            DiagnosticCode::IncompleteLine, "Incomplete line");
+            }
+            None
+        }
+    }
+
+    /// Wraps an `a` operand, which is the only kind that can hold an inline literal, so
+    /// it's the only kind that labels can be relaxed in.
+    fn make_a_operand(&self, val: ValType, token: &Token) -> Operand {
+        // A label's final value isn't known until link time in an object file, so we
+        // can't tell if it'll fit.
+        let short = match val {
+            ValType::Label(_) => self.options.relax_labels && !self.relocatable,
+            _ => false,
+        };
+        Operand {
+            val,
+            span: token_span(token),
+            short,
+        }
+    }
+
+    /// Parses a literal, warning if it has to be truncated to fit in a word.  Negative
+    /// values are stored as two's complement.
+    fn parse_literal(&mut self, token: &str, line_num: usize, span: Span)
+        -> Result<u16, String> {
+        use super::literal;
+
+        let v = literal::parse(token)?;
+        if v < i16::min_value() as i64 || v > u16::max_value() as i64 {
+            self.report(line_num, span, DiagnosticCode::LiteralTruncated,
+                        &format!("Literal \"{}\" truncated to {:#x}", token, v as u16));
+        }
+        Ok(v as u16)
+    }
+
+    /// If not an error, returns a tuple containing the extracted value type, and a value
+    /// if it's one of the "next word" variants, since none of those variants can store
+    /// the value in themselves.
+    fn get_val_type(&mut self, token: &str, line_num: usize, span: Span)
+        -> Result<ValType,(DiagnosticCode,String)> {
+        use self::ValType::*;
+
+        let deref = token.starts_with("[") && token.ends_with("]");
+        // Strip off the brackets once we know it's a dereference.  Spaces are allowed
+        // inside of them, like in "[SP + 1]".
+        let token = if deref { token[1..token.len()-1].trim() } else { token };
+
+        // "PICK n" is the only operand with a space in it.
+        let mut words = token.split_whitespace();
+        if !deref && words.next().map(|w| w.to_uppercase()) == Some(String::from("PICK")) {
+            let offset = words.next();
+            if let (Some(offset), None) = (offset, words.next()) {
+                return match self.get_base_type(offset, line_num, span)? {
+                    Literal(v) => Ok(Pick(v)),
+                    _ => Err((DiagnosticCode::InvalidOperand,
+                              format!("PICK offset must be a literal; got \"{}\"", offset))),
+                };
+            }
+            return Err((DiagnosticCode::InvalidOperand,
+                        String::from("PICK needs a single offset, like \"PICK 1\"")));
+        }
+
+        let err_str;
+        match self.get_base_type(token, line_num, span) {
+            Ok(vt) => {
+                if deref {
+                    match vt {
+                        Register(r) => return Ok(RegisterDeref(r)),
+                        // Literal values in the range [-1,30] will generally be too small
+                        // and uncommon enough, so we don't implement literal derefs as a
+                        // value type.
+                        Literal(v) => return Ok(NextWordDeref(v)),
+                        Label(s) => return Ok(LabelDeref(s)),
+                        StackPointer => return Ok(Peek),
+                        _ => return Err((DiagnosticCode::InvalidOperand,
+                                         format!("Can't dereference \"{}\"", token))),
+                    };
+                } else {
+                    match vt {
+                        Register(r) => return Ok(Register(r)),
+                        Push => return Ok(Push),
+                        Pop => return Ok(Pop),
+                        Peek => return Ok(Peek),
+                        Pick(v) => return Ok(Pick(v)),
+                        StackPointer => return Ok(StackPointer),
+                        ProgramCounter => return Ok(ProgramCounter),
+                        Extra => return Ok(Extra),
+                        Literal(v) => {
+                            if fits_in_literal(v) {
+                                return Ok(Literal(v));
+                            } else {
+                                return Ok(NextWord(v));
+                            }
+                        },
+                        Label(s) => return Ok(Label(s)),
+                        _ => panic!("(Supposedly) impossible branch reached"),
+                    };
+                }
+            },
+            Err(e) => {
+                err_str = e;
+            },
+        };
+
+        // If it didn't parse correctly as a base type, then it must be an addition
+        // expression (assuming it's well-formed).
+        if token.contains("+") {
+            let tokens: Vec<&str> = token.split("+").collect();
+            if tokens.len() > 2 {
+                return Err((DiagnosticCode::InvalidOperand,
+                            String::from("Only one \"+\" allowed inside brackets")));
+            }
+
+            if !deref {
+                return Err((DiagnosticCode::InvalidOperand,
+                            String::from("Must dereference addition expressions")));
+            }
+
+            let lhs = match self.get_base_type(tokens[0].trim(), line_num, span) {
+                Ok(vt) => vt,
+                Err(e) => return Err(e),
+            };
+            let rhs = match self.get_base_type(tokens[1].trim(), line_num, span) {
+                Ok(vt) => vt,
+                Err(e) => return Err(e),
+            };
+
+            match (lhs, rhs) {
+                (StackPointer, Literal(v)) | (Literal(v), StackPointer) => Ok(Pick(v)),
+                (Literal(v), Register(r)) | (Register(r), Literal(v)) =>
+                    Ok(NextWordRegisterDeref(v, r)),
+                (Label(s), Register(r)) | (Register(r), Label(s)) =>
+                    Ok(LabelRegisterDeref(s, r)),
+                (Label(s), Literal(v)) | (Literal(v), Label(s)) =>
+                    Ok(LabelNextWordDeref(s, v)),
+                _ => Err((DiagnosticCode::InvalidOperand,
+                          format!("Improperly formatted addition expression \"{}\"", token))),
+            }
+        } else {
+            Err(err_str)
+        }
+    }
+
+    /// Returns the value type of a token without any nested structure (e.g., not "a+b" or
+    /// "[a]").
+    fn get_base_type(&mut self, token: &str, line_num: usize, span: Span)
+        -> Result<ValType,(DiagnosticCode,String)> {
+        use super::literal;
+        use super::register;
+        use self::ValType::*;
+
+        // Like registers, keywords are case-insensitive.
+        let keyword = token.to_uppercase();
+        if let Some(reg) = register::try_from(token) {
+            Ok(Register(reg as u16))
+        } else if literal::is_literal(token) {
+            match self.parse_literal(token, line_num, span) {
+                Ok(v) => Ok(Literal(v)),
+                Err(e) => Err((DiagnosticCode::InvalidLiteral, e)),
+            }
+        } else if let Some(&v) = self.defines.get(token) {
+            Ok(Literal(v))
+        } else if keyword == "PC" {
+            Ok(ProgramCounter)
+        } else if keyword == "SP" {
+            Ok(StackPointer)
+        } else if keyword == "EX" {
+            Ok(Extra)
+        } else if keyword == "PUSH" {
+            Ok(Push)
+        } else if keyword == "POP" {
+            Ok(Pop)
+        } else if keyword == "PEEK" {
+            Ok(Peek)
+        } else if keyword == "PICK" {
+            Err((DiagnosticCode::InvalidOperand,
+                 String::from("PICK needs an offset, like \"PICK 1\"")))
+        } else if token.len() > 0 && !token.contains("+") &&
+                  !token.contains(char::is_whitespace) {
+            // TODO: Check against reserved keywords.
+            Ok(Label(String::from(token)))
+        } else {
+            Err((DiagnosticCode::InvalidOperand, format!("Invalid syntax \"{}\"", token)))
+        }
+    }
+}
+
+impl PendingSection {
+    fn new(name: &str) -> PendingSection {
+        PendingSection {
+            name: String::from(name),
+            line_results: Vec::new(),
+            words: Vec::new(),
+            relocations: Vec::new(),
+        }
+    }
+}
+
+impl Operand {
+    /// How many words does this operand extend beyond the first word.
+    fn num_words(&self) -> u16 {
+        if self.short { 0 } else { self.val.num_words() }
+    }
+}
+
+impl ValType {
+    /// Returns the label this value type refers to, if any.
+    fn label_name(&self) -> Option<&str> {
+        use self::ValType::*;
+        match *self {
+            Label(ref s) | LabelDeref(ref s) | LabelRegisterDeref(ref s, _) |
+            LabelNextWordDeref(ref s, _) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Whether this value type names somewhere that can be read and written without
+    /// side effects, i.e., anything but `PUSH`, `POP` and literals.
+    fn is_location(&self) -> bool {
+        use self::ValType::*;
+        match *self {
+            Push | Pop | NextWord(_) | Literal(_) | Label(_) => false,
+            _ => true,
+        }
+    }
+
+    /// How many words does this value type extend beyond the first word.
+    fn num_words(&self) -> u16 {
+        use self::ValType::*;
+        match *self {
+            NextWordRegisterDeref(_, _) | Pick(_) | NextWordDeref(_) | NextWord(_) |
+            Label(_) | LabelDeref(_) | LabelRegisterDeref(_, _) |
+            LabelNextWordDeref(_, _) => 1,
+            _ => 0,
+        }
+    }
+}
+
+
+/// Assembles `source`, returning the program, or every diagnostic (including warnings)
+/// if there were any errors.
+pub fn assemble(source: &str) -> Result<AssembledProgram, Vec<AssemblerDiagnostic>> {
+    let assembly = assemble_with_options(source, &AssemblerOptions::default());
+    match assembly.program {
+        Some(program) => Ok(program),
+        None => Err(assembly.diagnostics),
+    }
+}
+
+/// Assembles `source`, reporting every error and warning found in it.
+pub fn assemble_with_options(source: &str, options: &AssemblerOptions) -> Assembly {
+    let (context, words_saved) = run_passes(source, options, false);
+    context.finish(source, words_saved)
+}
+
+/// Assembles `source` into an object file, which has to be linked before it can run.
+///
+/// Labels used by other object files need to be exported with `.global`, and labels
+/// from other object files need to be imported with `.extern`.
+pub fn assemble_object(source: &str, options: &AssemblerOptions) -> ObjectAssembly {
+    let (context, _) = run_passes(source, options, true);
+    context.finish_object()
+}
+
+fn run_passes<'a>(source: &str, options: &'a AssemblerOptions, relocatable: bool)
+    -> (AssemblerContext<'a>, u16) {
+    // First, we do a pre-pass to collect intermediate values, and wait until we have
+    // all label names.
+    let mut context = AssemblerContext::new(options, relocatable);
+    let lines = source.split("\n");
+    for (line_idx, line) in lines.enumerate() {
+        // Line numbers are one-based, like in every editor.
+        context.process_line(line_idx + 1, line.trim_end_matches('\r'));
+    }
+    context.finish_conditionals();
+    if options.optimize {
+        context.optimize();
+    }
+
+    // Then, we figure out where everything goes.
+    let words_saved = context.relax();
+    if options.report_relaxation {
+        context.report(0, Span::new(0, 0), DiagnosticCode::RelaxationSavings,
+                       &format!("Relaxing labels saved {} bytes ({} words)",
+                                words_saved as u32 * 2, words_saved));
+    }
+
+    // Now, we generate the program words.  Even if the first pass had errors, we keep
+    // going, so errors like undefined labels are reported all at once.
+    for idx in 0..context.sections.len() {
+        context.curr_section = idx;
+        let line_results = mem::replace(&mut context.sections[idx].line_results, Vec::new());
+        for lr in line_results {
+            use self::LineResult::*;
+            match lr {
+                BasicInstruction(instr) => context.append_normal_instruction(instr),
+                SpecialInstruction(instr) => context.append_special_instruction(instr),
+                Data(d) => context.append_data(d),
+            };
+        }
+    }
+
+    (context, words_saved)
+}
+
+/// Returns the string mode for a data line starting with `token`, or `None` if it isn't
+/// a data line.
+fn string_mode(token: &str) -> Option<StringMode> {
+    match token.to_lowercase().as_str() {
+        "dat" => Some(StringMode::Plain),
+        ".asciiz" => Some(StringMode::ZeroTerminated),
+        ".pstring" => Some(StringMode::LengthPrefixed),
+        ".packed" => Some(StringMode::Packed),
+        ".lem" => Some(StringMode::Lem),
+        _ => None,
+    }
+}
+
+/// Encodes a string's characters as words.
+///
+/// On failure, returns the offset of the offending character in `chars` (which only
+/// lines up with its byte offset in the source if there were no escapes before it),
+/// along with the error.
+fn encode_string(chars: &[u16], mode: StringMode, attribute: u16)
+    -> Result<Vec<u16>, (usize, String)> {
+    use self::StringMode::*;
+    match mode {
+        Plain => Ok(chars.to_vec()),
+        ZeroTerminated => {
+            let mut words = chars.to_vec();
+            words.push(0);
+            Ok(words)
+        },
+        LengthPrefixed => {
+            let mut words = vec![chars.len() as u16];
+            words.extend_from_slice(chars);
+            Ok(words)
+        },
+        Packed => {
+            // Odd-length strings get a zero in the low byte of their last word.
+            Ok(chars.chunks(2)
+                .map(|pair| (pair[0] << 8) | pair.get(1).cloned().unwrap_or(0))
+                .collect())
+        },
+        Lem => {
+            let mut words = Vec::with_capacity(chars.len());
+            for (i, &c) in chars.iter().enumerate() {
+                // The LEM's font is indexed by ASCII, but only has printable characters.
+                if c < 0x20 || c > 0x7e {
+                    return Err((i, format!("Character {:#x} can't be shown on the LEM", c)));
+                }
+                words.push(attribute | c);
+            }
+            Ok(words)
+        },
+    }
+}
+
+/// Whether `token` could name a label on a data line, as opposed to being a literal,
+/// register or keyword.
+fn is_label_name(token: &str) -> bool {
+    use super::literal;
+    use super::register;
+
+    token.len() > 0 && !literal::is_literal(token) && register::try_from(token).is_none() &&
+        !OPERAND_KEYWORDS.contains(&token.to_uppercase().as_str()) &&
+        token.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Whether `v` can be encoded as an inline literal, which covers the range [-1, 30].
+fn fits_in_literal(v: u16) -> bool {
+    let vi = v as i16;
+    vi >= -1 && vi <= 30
+}
+
+/// Returns the op and inline literal that jump from `next`, the address after the jump,
+/// to `target`, if it's close enough.
+fn relative_jump(next: u16, target: u16) -> Option<(BasicOp, u16)> {
+    let offset = target.wrapping_sub(next) as i16;
+    if offset >= 0 && offset <= 30 {
+        Some((BasicOp::ADD, offset as u16))
+    } else if offset < 0 && offset >= -30 {
+        Some((BasicOp::SUB, (-offset) as u16))
+    } else {
+        None
+    }
+}
+
+/// Whether `instr` is `set PC, label`.
+fn is_jump_to_label(instr: &BasicInstructionComponents) -> bool {
+    match (&instr.op, &instr.b.val, &instr.a.val) {
+        (&BasicOp::SET, &ValType::ProgramCounter, &ValType::Label(_)) => true,
+        _ => false,
+    }
+}
+
+fn is_data(lr: &LineResult) -> bool {
+    match *lr {
+        LineResult::Data(_) => true,
+        _ => false,
+    }
+}
+
+/// Returns the line an instruction is on, the span from its op name to the end of its
+/// operands, and how many words it takes up if none of its labels are relaxed.
+fn instruction_extent(lr: &LineResult) -> (usize, Span, u16) {
+    use self::LineResult::*;
+    match *lr {
+        BasicInstruction(ref i) => {
+            let end = i.span.end.max(i.a.span.end);
+            (i.line_num, Span::new(i.span.start, end), 1 + i.b.val.num_words() +
+                                                       i.a.val.num_words())
+        },
+        SpecialInstruction(ref i) => {
+            let end = i.span.end.max(i.a.span.end);
+            (i.line_num, Span::new(i.span.start, end), 1 + i.a.val.num_words())
+        },
+        Data(ref d) => (d.line_num, Span::new(0, 0), d.words.len() as u16),
+    }
+}
+
+/// Whether an instruction leaves everything but EX as it was.
+fn does_nothing(lr: &LineResult) -> bool {
+    use super::op::BasicOp::*;
+    use self::ValType::Literal;
+
+    let i = match *lr {
+        LineResult::BasicInstruction(ref i) => i,
+        _ => return false,
+    };
+    // Pushing, popping or writing to a literal do something, even with the same value.
+    if !i.b.val.is_location() {
+        return false;
+    }
+    match (&i.op, &i.a.val) {
+        (&SET, a) => *a == i.b.val,
+        (&ADD, &Literal(0)) | (&SUB, &Literal(0)) | (&BOR, &Literal(0)) |
+        (&XOR, &Literal(0)) | (&SHR, &Literal(0)) | (&ASR, &Literal(0)) |
+        (&SHL, &Literal(0)) => true,
+        (&MUL, &Literal(1)) | (&MLI, &Literal(1)) | (&DIV, &Literal(1)) |
+        (&DVI, &Literal(1)) => true,
+        (&AND, &Literal(0xffff)) => true,
+        _ => false,
+    }
+}
+
+/// Whether an instruction always jumps somewhere else, if it runs at all, and never to
+/// the code right after it.
+fn is_unconditional_jump(lr: &LineResult) -> bool {
+    use super::op::BasicOp::*;
+    match *lr {
+        LineResult::BasicInstruction(ref i) if i.b.val == ValType::ProgramCounter => {
+            match (&i.op, &i.a.val) {
+                (&SET, _) => true,
+                // Jumps back, like the `sub PC, 1` that programs halt with.
+                (&SUB, &ValType::Literal(n)) => (n as i16) > 0,
+                _ => false,
+            }
+        },
+        LineResult::BasicInstruction(_) => false,
+        LineResult::SpecialInstruction(ref i) => match i.op {
+            SpecialOp::RFI => true,
+            _ => false,
+        },
+        LineResult::Data(_) => false,
+    }
+}
+
+/// How many words an `add PC, n` or `sub PC, n` jumps forward, from the word after it.
+fn relative_jump_offset(lr: &LineResult) -> Option<i32> {
+    match *lr {
+        LineResult::BasicInstruction(ref i) if i.b.val == ValType::ProgramCounter => {
+            match (&i.op, &i.a.val) {
+                (&BasicOp::ADD, &ValType::Literal(n)) => Some(n as i16 as i32),
+                (&BasicOp::SUB, &ValType::Literal(n)) => Some(-(n as i16 as i32)),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Whether an instruction adds or subtracts something other than a literal from PC.
+fn is_computed_jump(lr: &LineResult) -> bool {
+    match *lr {
+        LineResult::BasicInstruction(ref i) if i.b.val == ValType::ProgramCounter => {
+            match i.op {
+                BasicOp::ADD | BasicOp::SUB => relative_jump_offset(lr).is_none(),
+                _ => false,
+            }
+        },
+        _ => false,
+    }
+}
+
+/// How many of the line results after an instruction it could jump to by adding to PC.
+fn forward_jump_reach(lr: &LineResult) -> usize {
+    match relative_jump_offset(lr) {
+        // Every instruction is at least a word long, so it can't get past n + 1.
+        Some(n) if n > 0 => n as usize + 1,
+        _ => 0,
+    }
+}
+
+/// Marks the line results that each backward relative jump lands on or jumps over,
+/// along with the jump itself.
+fn backward_jump_spans(line_results: &[LineResult]) -> Vec<bool> {
+    let mut spans = vec![false; line_results.len()];
+    for (idx, lr) in line_results.iter().enumerate() {
+        let distance = match relative_jump_offset(lr) {
+            Some(n) if n < 0 => (-n) as u32,
+            _ => continue,
+        };
+        // Count back from the word after the jump until we've gone far enough.
+        let mut start = idx + 1;
+        let mut words = 0;
+        while start > 0 && words < distance {
+            start -= 1;
+            words += instruction_extent(&line_results[start]).2 as u32;
+        }
+        for spanned in &mut spans[start..idx + 1] {
+            *spanned = true;
+        }
+    }
+    spans
+}
+
+
+/// Decomposes `line` into tokens for the assembler, stopping at the start of a comment.
+///
+/// Bracketed operands like "[SP + 1]" and "PICK n" operands each make a single token,
+/// even though they have spaces in them.
+fn tokenize<'a>(line: &'a str) -> Vec<Token<'a>> {
+    let mut tokens: Vec<Token> = Vec::new();
+    // The quote character we're currently inside of, if any.
+    let mut quote: Option<u8> = None;
+    let mut in_brackets = false;
+    let mut escaped = false;
+    let mut token_start: Option<usize> = None;
+    let s = line.as_bytes();
+    let make_token = |start: usize, end: usize| Token {
+        text: str::from_utf8(&s[start..end]).unwrap(),
+        col: start,
+    };
+    for i in 0..s.len() {
+        match token_start {
+            Some(j) => {
+                if let Some(q) = quote {
+                    if escaped {
+                        escaped = false;
+                    } else if s[i] == '\\' as u8 {
+                        escaped = true;
+                    } else if s[i] == q {
+                        // The token goes on past the quote, like in "[A+'a']".
+                        quote = None;
+                    }
+                } else {
+                    if is_whitespace(s[i]) && !in_brackets {
+                        tokens.push(make_token(j, i));
+                        token_start = None;
+                    } else if s[i] == ']' as u8 {
+                        in_brackets = false;
+                    } else if s[i] == ',' as u8 {
+                        // Unquoted commas represent a single token.
+                        tokens.push(make_token(j, i));
+                        tokens.push(make_token(i, i+1));
+                        token_start = None;
+                    } else if s[i] == ';' as u8 {
+                        tokens.push(make_token(j, i));
+                        return tokens;
+                    } else if is_quote(s[i]) {
+                        // Quotes can show up mid-token, like in "[A+'a']".
+                        quote = Some(s[i]);
+                    }
+                }
+            },
+            None => {
+                if s[i] == ';' as u8 {
+                    return tokens;
+                } else if s[i] == ',' as u8 {
+                    // Unquoted commas represent a single token.
+                    tokens.push(make_token(i, i+1));
+                } else if !is_whitespace(s[i]) {
+                    token_start = Some(i);
+                    in_brackets = s[i] == '[' as u8;
+                    if is_quote(s[i]) {
+                        quote = Some(s[i]);
+                    }
+                }
+            }
+        }
+    }
+
+    match token_start {
+        Some(j) => {
+            // Trailing spaces in an unclosed bracket aren't part of the token.
+            let end = j + line[j..].trim_end().len();
+            tokens.push(make_token(j, end));
+        },
+        None => (),
+    }
+    join_pick_operands(line, tokens)
+}
+
+/// Joins every "PICK" token with the offset after it.
+fn join_pick_operands<'a>(line: &'a str, tokens: Vec<Token<'a>>) -> Vec<Token<'a>> {
+    let mut result: Vec<Token> = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        match tokens.get(i + 1) {
+            Some(next) if token.text.to_uppercase() == "PICK" && next.text != "," => {
+                let end = next.col + next.text.len();
+                result.push(Token { text: &line[token.col..end], col: token.col });
+                i += 2;
+            },
+            _ => {
+                result.push(token);
+                i += 1;
+            },
+        }
+    }
+    result
+}
+
+fn is_quote(c: u8) -> bool {
+    c == '"' as u8 || c == '\'' as u8
+}
+
+fn is_whitespace(c: u8) -> bool {
+    c == ' ' as u8 || c == '\t' as u8
+}
+
+fn token_span(token: &Token) -> Span {
+    Span::new(token.col, token.col + token.text.len())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::diagnostic::Severity;
+
+    #[test]
+    fn reports_every_error() {
+        let assembly = assemble_with_options("\
+set A, nowhere
+frob A, 1
+set B, also_nowhere
+", &test_options());
+        assert!(assembly.program.is_none());
+        let lines: Vec<(usize, DiagnosticCode)> = assembly.diagnostics.iter()
+            .map(|d| (d.line, d.code))
+            .collect();
+        assert_eq!(lines, vec![
+            (2, DiagnosticCode::UnknownOp),
+            (1, DiagnosticCode::UndefinedLabel),
+            (3, DiagnosticCode::UndefinedLabel),
+        ]);
+        // Columns point at the offending token.
+        assert_eq!(assembly.diagnostics[1].span, Span::new(7, 14));
+    }
+
+    #[test]
+    fn warnings_dont_fail_assembly() {
+        let assembly = assemble_with_options("\
+:target set target, 0x12345
+ife 5, A
+", &test_options());
+        let codes: Vec<(DiagnosticCode, Severity)> = assembly.diagnostics.iter()
+            .map(|d| (d.code, d.severity))
+            .collect();
+        assert_eq!(codes, vec![
+            (DiagnosticCode::LabelAsLvalue, Severity::Warning),
+            (DiagnosticCode::LiteralTruncated, Severity::Warning),
+        ]);
+        assert_eq!(assembly.program.unwrap().words, vec![0x7fe1, 0x0000, 0x2345, 0x03f2, 0x0005]);
+    }
+
+    #[test]
+    fn debug_info() {
+        let source = "\
+set A, 0x30
+:loop
+  sub A, 1     ; Count down.
+  ifn A, 0
+    set PC, loop
+:message dat \"hello\", 0
+";
+        let program = assemble(source).ok().unwrap();
+        assert_eq!(program.symbol("loop").unwrap().address, 2);
+        assert_eq!(program.symbol("message").unwrap().address, 5);
+        assert_eq!(program.symbol_at(5).unwrap().name, "message");
+        assert_eq!(program.line_at(0), Some(1));
+        assert_eq!(program.line_at(1), Some(1));
+        assert_eq!(program.line_at(4), Some(5));
+        assert_eq!(program.line_at(10), Some(6));
+        assert_eq!(program.line_at(11), None);
+
+        let listing: Vec<&str> = program.listing.lines().collect();
+        assert_eq!(listing[0], "0000  7c01 0030             set A, 0x30");
+        assert_eq!(listing[1], "0002                        :loop");
+        assert_eq!(listing[5], "0005  0068 0065 006c 006c   :message dat \"hello\", 0");
+        assert_eq!(listing[6], "0009  006f 0000");
+        assert!(program.listing.ends_with("SYMBOLS\n0002  loop\n0005  message\n"));
+    }
+
+    #[test]
+    fn relaxation() {
+        // `far` starts out in range, until lengthening `set B, end` pushes it out, which
+        // then lengthens both jumps to `far`.
+        let mut source = String::from(":start set PC, far\nset B, end\nset C, start\n");
+        for _ in 0..27 {
+            source.push_str("set A, 1\n");
+        }
+        source.push_str(":far set PC, far\ndat 0, 0, 0, 0, 0\n:end\n");
+        let mut options = test_options();
+        options.report_relaxation = true;
+        let assembly = assemble_with_options(&source, &options);
+        let program = assembly.program.unwrap();
+        assert_eq!(program.symbol("far").unwrap().address, 32);
+        assert_eq!(program.symbol("end").unwrap().address, 39);
+        assert_eq!(&program.words[..5], &[0x7f81, 0x0020, 0x7c21, 0x0027, 0x8441]);
+        assert_eq!(program.words.len(), 39);
+        assert_eq!(program.words_saved, 1);
+        assert_eq!(assembly.diagnostics[0].to_string(),
+                   "test.dasm: note[N0001]: Relaxing labels saved 2 bytes (1 words)");
+
+        options.relax_labels = false;
+        let program = assemble_with_options(&source, &options).program.unwrap();
+        assert_eq!(program.words.len(), 40);
+        assert_eq!(program.words_saved, 0);
+    }
+
+    #[test]
+    fn literal_syntax() {
+        let source = r#"
+set A, -1
+dat -2, 0b101, 0o17, 'A', '\n', '\'', ',', ';'
+dat "a;b\n\"\x7f", 0 ; The comment starts here.
+"#;
+        let program = assemble(source).ok().unwrap();
+        assert_eq!(program.words, vec![
+            0x8001,
+            0xfffe, 0x0005, 0x000f, 0x0041, 0x000a, 0x0027, 0x002c, 0x003b,
+            0x0061, 0x003b, 0x0062, 0x000a, 0x0022, 0x007f, 0x0000,
+        ]);
+    }
+
+    #[test]
+    fn invalid_literals() {
+        let assembly = assemble_with_options(r#"
+set A, 0xZZ
+dat 'ab'
+dat "tab\q"
+"#, &test_options());
+        let diagnostics: Vec<(usize, DiagnosticCode, Span)> = assembly.diagnostics.iter()
+            .map(|d| (d.line, d.code, d.span))
+            .collect();
+        assert_eq!(diagnostics, vec![
+            (2, DiagnosticCode::InvalidLiteral, Span::new(7, 11)),
+            (3, DiagnosticCode::InvalidLiteral, Span::new(4, 8)),
+            (4, DiagnosticCode::InvalidStringByte, Span::new(8, 9)),
+        ]);
+    }
+
+    #[test]
+    fn string_modes() {
+        let source = r#"
+.asciiz "hi", "!"
+.pstring "abc", 7
+.packed "abc"
+.lem 0xf100, "Hi", 0x0080
+"#;
+        let program = assemble(source).ok().unwrap();
+        assert_eq!(program.words, vec![
+            0x0068, 0x0069, 0x0000, 0x0021, 0x0000,
+            0x0003, 0x0061, 0x0062, 0x0063, 0x0007,
+            0x6162, 0x6300,
+            0xf148, 0xf169, 0x0080,
+        ]);
+
+        let assembly = assemble_with_options(".lem \"Hi\"\n.lem 0xf1, \"Hi\"\n",
+                                             &test_options());
+        assert_eq!(assembly.diagnostics.len(), 2);
+        assert!(assembly.diagnostics.iter().all(|d| d.code == DiagnosticCode::InvalidData));
+    }
+
+    #[test]
+    fn data_labels() {
+        let program = assemble("\
+:table dat first, 7, second
+:first set A, 1
+:second dat table
+").ok().unwrap();
+        assert_eq!(program.words, vec![0x0003, 0x0007, 0x0004, 0x8801, 0x0000]);
+
+        let assembly = assemble_with_options("dat nowhere, A\n", &test_options());
+        let codes: Vec<DiagnosticCode> = assembly.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec![DiagnosticCode::InvalidData]);
+        let assembly = assemble_with_options("dat nowhere\n", &test_options());
+        let codes: Vec<DiagnosticCode> = assembly.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec![DiagnosticCode::UndefinedLabel]);
+
+        let object = assemble_object(":start dat 1, start\n", &test_options())
+            .object.unwrap();
+        assert_eq!(object.sections[0].relocations.len(), 1);
+    }
+
+    #[test]
+    fn optimization() {
+        use super::super::Dcpu;
+
+        let source = "\
+:start
+  set A, A
+  add B, 0
+  ife A, 0
+    add C, 0
+  set PC, skip
+  set A, 1
+  set B, 2
+:skip
+  jsr sub
+  set PC, end
+:sub
+  mul A, 1
+  set PC, POP
+  set C, 3
+:table dat \"abcdefghijklmnopqrstuvwxyz01\"
+:back set X, 1
+:end set PC, back
+";
+        let mut options = test_options();
+        options.optimize = true;
+        options.relative_jumps = true;
+        let assembly = assemble_with_options(source, &options);
+        let diagnostics: Vec<(usize, DiagnosticCode)> = assembly.diagnostics.iter()
+            .map(|d| (d.line, d.code))
+            .collect();
+        assert_eq!(diagnostics, vec![
+            (1, DiagnosticCode::UnusedLabel),
+            (16, DiagnosticCode::UnusedLabel),
+            (7, DiagnosticCode::UnreachableCode),
+            (15, DiagnosticCode::UnreachableCode),
+            (0, DiagnosticCode::OptimizationSavings),
+        ]);
+        assert_eq!(assembly.diagnostics[2].span, Span::new(2, 10));
+        assert_eq!(assembly.diagnostics[4].message, "Optimizing removed 12 bytes (6 words)");
+
+        let program = assembly.program.unwrap();
+        // Labels on removed lines move to the next line that's kept.
+        assert_eq!(program.symbol("skip").unwrap().address, 3);
+        assert_eq!(program.symbol("sub").unwrap().address, 5);
+        assert_eq!(program.symbol("end").unwrap().address, 35);
+        // Only the `add C, 0` after the conditional survives.  The jumps to `end` and
+        // `back` are too far away for inline literals, so they're made relative.
+        assert_eq!(&program.words[..6], &[0x8412, 0x8442, 0x9381, 0x9820, 0xff82, 0x6381]);
+        assert_eq!(&program.words[34..], &[0x8861, 0x8f83]);
+
+        // Relative jumps overwrite EX, so they're only made when asked for.
+        options.relative_jumps = false;
+        let program = assemble_with_options(source, &options).program.unwrap();
+        assert_eq!(&program.words[4..7], &[0x7f81, 0x0024, 0x6381]);
+
+        // `sub PC, n` loops count back by words, so nothing they jump over or back to can
+        // be removed, even if it's unreachable otherwise.
+        options.relative_jumps = false;
+        let loops = "\
+  set A, 0
+  add A, 1
+  set B, B
+  ifl A, 5
+    sub PC, 4
+  set PC, skip
+  add C, 1
+:skip
+  ifl C, 3
+    sub PC, 3
+  sub PC, 1
+";
+        let assembly = assemble_with_options(loops, &options);
+        let codes: Vec<DiagnosticCode> = assembly.diagnostics.iter().map(|d| d.code).collect();
+        assert!(!codes.contains(&DiagnosticCode::UnreachableCode));
+        let mut dcpu = Dcpu::new();
+        dcpu.load_program(&assembly.program.unwrap().words);
+        for _ in 0..200 {
+            dcpu.tick();
+        }
+        assert_eq!((dcpu.reg(0), dcpu.reg(2)), (5, 3));
+    }
+
+    #[test]
+    fn optimizing_computed_jumps() {
+        use super::super::Dcpu;
+
+        // The rows of the jump table and the code `add PC, 1` lands on come right after
+        // jumps, but they aren't labeled, so only the `add PC`s keep them around.
+        let source = "\
+  set A, 1
+  jsr dispatch
+  add PC, 1
+  set PC, fail
+  set C, 1
+  sub PC, 1
+:dispatch
+  add PC, A
+  set PC, zero
+  set PC, one
+  set PC, two
+:zero set B, 10
+  set PC, POP
+:one set B, 11
+  set PC, POP
+:two set B, 12
+  set PC, POP
+:fail set C, 2
+  sub PC, 1
+";
+        let mut options = test_options();
+        options.optimize = true;
+        let assembly = assemble_with_options(source, &options);
+        assert!(assembly.diagnostics.iter()
+                .all(|d| d.code != DiagnosticCode::UnreachableCode));
+
+        let mut dcpu = Dcpu::new();
+        dcpu.load_program(&assembly.program.unwrap().words);
+        for _ in 0..100 {
+            dcpu.tick();
+        }
+        assert_eq!((dcpu.reg(1), dcpu.reg(2)), (11, 1));
+    }
+
+    #[test]
+    fn pick_operands() {
+        let program = assemble("\
+set A, PICK 3
+set [SP + 3], B
+set [ 2+SP ], PEEK
+set [SP], [A + 1]
+").ok().unwrap();
+        assert_eq!(program.words, vec![
+            0x6801, 0x0003,
+            0x0741, 0x0003,
+            0x6741, 0x0002,
+            0x4321, 0x0001,
+        ]);
+
+        let assembly = assemble_with_options("set A, PICK\nset A, PICK B\n", &test_options());
+        assert_eq!(assembly.diagnostics.len(), 2);
+        assert!(assembly.diagnostics.iter()
+                .all(|d| d.code == DiagnosticCode::InvalidOperand));
+    }
+
+    #[test]
+    fn conditional_assembly() {
+        let source = "\
+.ifndef SCREEN
+.define SCREEN 0x8000
+.endif
+.if SCREEN == 0x8000 && !defined(FLOPPY)
+set A, 1
+.elif defined FLOPPY
+set A, 2
+.else
+  .if UNDEFINED_BUT_SKIPPED
+  .endif
+set A, 3
+.endif
+set [SCREEN], A
+dat SCREEN
+";
+        let program = assemble(source).ok().unwrap();
+        assert_eq!(program.words, vec![0x8801, 0x03c1, 0x8000, 0x8000]);
+
+        let mut options = test_options();
+        options.defines.insert(String::from("SCREEN"), 0x9000);
+        options.defines.insert(String::from("FLOPPY"), 1);
+        let program = assemble_with_options(source, &options).program.unwrap();
+        assert_eq!(program.words, vec![0x8c01, 0x03c1, 0x9000, 0x9000]);
+
+        let assembly = assemble_with_options(".else\n.if 1 +\n.endif\n.if 1\n",
+                                             &test_options());
+        let codes: Vec<DiagnosticCode> = assembly.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec![DiagnosticCode::UnbalancedConditional,
+                               DiagnosticCode::InvalidExpression,
+                               DiagnosticCode::UnbalancedConditional]);
+
+        let assembly = assemble_with_options(".define BIG 0x12345\ndat BIG\n",
+                                             &test_options());
+        let codes: Vec<DiagnosticCode> = assembly.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec![DiagnosticCode::LiteralTruncated]);
+        assert_eq!(assembly.program.unwrap().words, vec![0x2345]);
+    }
+
+    #[test]
+    fn community_syntax() {
+        let community = assemble("\
+start:
+    SET A, 0x30
+    Push a
+    JSR sub
+    pop [0x1000]
+    NOP
+    HLT
+sub: ADD peek, 1
+    RET
+DAT 0
+").ok().unwrap();
+        let canonical = assemble("\
+:start
+    set A, 0x30
+    set PUSH, A
+    jsr sub
+    set [0x1000], POP
+    set A, A
+    sub PC, 1
+:sub add PEEK, 1
+    set PC, POP
+dat 0
+").ok().unwrap();
+        assert_eq!(community.words, canonical.words);
+        assert_eq!(community.symbol("sub").unwrap().address,
+                   canonical.symbol("sub").unwrap().address);
+
+        let assembly = assemble_with_options("jmp\nret A\n", &test_options());
+        let codes: Vec<DiagnosticCode> = assembly.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec![DiagnosticCode::IncompleteLine,
+                               DiagnosticCode::ExpectedLineEnd]);
+    }
+
+    fn test_options() -> AssemblerOptions {
+        let mut options = AssemblerOptions::default();
+        options.file_name = String::from("test.dasm");
+        options
+    }
+}
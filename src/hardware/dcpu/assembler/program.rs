@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+/// Number of words shown on each row of a listing.
+const LISTING_WORDS_PER_ROW: usize = 4;
+
+
+/// A label, and where it was defined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u16,
+    /// One-based line number.
+    pub line: usize,
+}
+
+/// Maps a run of words in the program back to the source line that produced them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineMapping {
+    pub address: u16,
+    pub num_words: u16,
+    /// One-based line number.
+    pub line: usize,
+}
+
+/// An assembled program, along with the debug info needed to map it back to its source.
+pub struct AssembledProgram {
+    pub words: Vec<u16>,
+    /// Every label in the program, sorted by address.
+    pub symbols: Vec<Symbol>,
+    /// Every line that produced words, sorted by address.
+    pub line_map: Vec<LineMapping>,
+    /// Addresses and words side by side with the source they came from.
+    pub listing: String,
+    /// Words saved by encoding label operands as inline literals.
+    pub words_saved: u16,
+}
+
+impl AssembledProgram {
+    pub fn new(words: Vec<u16>, symbols: Vec<Symbol>, line_map: Vec<LineMapping>,
+               words_saved: u16, source: &str) -> AssembledProgram {
+        let mut symbols = symbols;
+        symbols.sort_by(|a, b| (a.address, a.line).cmp(&(b.address, b.line)));
+        let mut line_map = line_map;
+        line_map.sort_by_key(|m| m.address);
+        let listing = make_listing(&words, &symbols, &line_map, source);
+        AssembledProgram {
+            words,
+            symbols,
+            line_map,
+            listing,
+            words_saved,
+        }
+    }
+
+    /// Looks up a label by name.
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    /// Returns the first label defined at exactly `address`, if there is one.
+    pub fn symbol_at(&self, address: u16) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.address == address)
+    }
+
+    /// Returns the line of the instruction or data that `address` falls inside of.
+    pub fn line_at(&self, address: u16) -> Option<usize> {
+        self.mapping_at(address).map(|m| m.line)
+    }
+
+    /// Returns the mapping for the instruction or data that `address` falls inside of.
+    pub fn mapping_at(&self, address: u16) -> Option<&LineMapping> {
+        let idx = match self.line_map.binary_search_by_key(&address, |m| m.address) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let mapping = &self.line_map[idx];
+        if (address as u32) < mapping.address as u32 + mapping.num_words as u32 {
+            Some(mapping)
+        } else {
+            None
+        }
+    }
+}
+
+/// Builds a listing with a row for every line in `source`, followed by a symbol table.
+fn make_listing(words: &[u16], symbols: &[Symbol], line_map: &[LineMapping], source: &str)
+    -> String {
+    let words_width = LISTING_WORDS_PER_ROW * 5;
+    let mut listing = String::new();
+
+    let mappings_by_line: HashMap<usize, &LineMapping> = line_map.iter()
+        .map(|m| (m.line, m))
+        .collect();
+    let mut symbols_by_line: HashMap<usize, &Symbol> = HashMap::new();
+    for symbol in symbols {
+        symbols_by_line.entry(symbol.line).or_insert(symbol);
+    }
+
+    for (line_idx, line) in source.split('\n').enumerate() {
+        let line_num = line_idx + 1;
+        let line = line.trim_end_matches('\r');
+        match mappings_by_line.get(&line_num) {
+            Some(mapping) if mapping.num_words > 0 => {
+                let start = mapping.address as usize;
+                let end = start + mapping.num_words as usize;
+                // Long data lines wrap onto extra rows, without repeating the source.
+                for (i, row) in words[start..end].chunks(LISTING_WORDS_PER_ROW).enumerate() {
+                    let hex: Vec<String> = row.iter().map(|w| format!("{:04x}", w)).collect();
+                    let address = start + i * LISTING_WORDS_PER_ROW;
+                    let source = if i == 0 { line } else { "" };
+                    listing.push_str(&format_row(&format!("{:04x}", address), &hex.join(" "),
+                                                 words_width, source));
+                }
+            },
+            _ => {
+                // Label-only lines still show the address their label resolves to.
+                let address = match symbols_by_line.get(&line_num) {
+                    Some(s) => format!("{:04x}", s.address),
+                    None => String::from("    "),
+                };
+                listing.push_str(&format_row(&address, "", words_width, line));
+            },
+        }
+    }
+
+    if symbols.len() > 0 {
+        listing.push_str("\nSYMBOLS\n");
+        for symbol in symbols {
+            listing.push_str(&format!("{:04x}  {}\n", symbol.address, symbol.name));
+        }
+    }
+    listing
+}
+
+fn format_row(address: &str, words: &str, words_width: usize, source: &str) -> String {
+    let row = format!("{}  {:<width$}  {}", address, words, source, width = words_width);
+    format!("{}\n", row.trim_end())
+}
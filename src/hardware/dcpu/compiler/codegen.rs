@@ -0,0 +1,670 @@
+use std::collections::HashMap;
+
+use super::CompileError;
+use super::parser::{BinaryOp, Expr, ExprKind, Function, Item, Stmt, StmtKind, UnaryOp};
+use super::super::assembler::OPERAND_KEYWORDS;
+use super::super::register;
+
+/// Prefix for labels the compiler makes up, which user names aren't allowed to start with.
+const INTERNAL_PREFIX: &str = "__";
+
+
+/// What a name refers to.
+#[derive(Clone, Copy)]
+enum Symbol {
+    /// A local variable or parameter, at an offset from the frame pointer.
+    Local { offset: u16, is_array: bool },
+    /// A global variable, whose label is its name.
+    Global { is_array: bool },
+    Const(u16),
+    /// A function and how many parameters it takes.
+    Function(usize),
+}
+
+struct Generator {
+    code: Vec<String>,
+    data: Vec<String>,
+    next_label: usize,
+    /// Globals, constants and functions.
+    globals: HashMap<String, Symbol>,
+    /// Locals, innermost block last.
+    scopes: Vec<HashMap<String, Symbol>>,
+    /// The `continue` and `break` labels for each loop we're in, innermost last.
+    loops: Vec<(String, String)>,
+    /// Offset of the next local to be declared in the current function.
+    next_local: u16,
+    return_label: String,
+}
+
+/// Generates assembly for a parsed program.
+pub fn generate(items: &[Item]) -> Result<String, CompileError> {
+    let mut gen = Generator {
+        code: Vec::new(),
+        data: Vec::new(),
+        next_label: 0,
+        globals: HashMap::new(),
+        scopes: Vec::new(),
+        loops: Vec::new(),
+        next_local: 0,
+        return_label: String::new(),
+    };
+
+    // Declare everything up front, so functions can call ones defined after them.
+    // Constants and globals still have to be defined before they're used.
+    for item in items {
+        if let Item::Function(ref f) = *item {
+            gen.declare_global(&f.name, Symbol::Function(f.params.len()), f.line, f.col)?;
+        }
+    }
+    let main = gen.globals.get("main").cloned();
+    match main {
+        Some(Symbol::Function(0)) => (),
+        Some(_) => return Err(CompileError::new(0, 0, "main() can't take any parameters")),
+        None => return Err(CompileError::new(0, 0, "There's no main() function")),
+    }
+
+    for item in items {
+        match *item {
+            Item::Function(ref f) => gen.function(f)?,
+            Item::Global(ref stmt) => gen.global(stmt)?,
+            Item::Const { ref name, ref value, line, col } => {
+                let value = gen.const_value(value)
+                    .ok_or(CompileError::new(line, col, "Constants must be constant"))?;
+                gen.declare_global(name, Symbol::Const(value), line, col)?;
+            },
+        }
+    }
+
+    let mut lines = vec![String::from("  jsr main")];
+    lines.push(format!(":{}halt", INTERNAL_PREFIX));
+    lines.push(String::from("  sub PC, 1"));
+    lines.extend(gen.code);
+    lines.extend(gen.data);
+    lines.push(String::new());
+    Ok(lines.join("\n"))
+}
+
+impl Generator {
+    fn emit(&mut self, instruction: &str) {
+        self.code.push(format!("  {}", instruction));
+    }
+
+    fn label(&mut self, name: &str) {
+        self.code.push(format!(":{}", name));
+    }
+
+    fn new_label(&mut self) -> String {
+        self.next_label += 1;
+        format!("{}L{}", INTERNAL_PREFIX, self.next_label)
+    }
+
+    fn declare_global(&mut self, name: &str, symbol: Symbol, line: usize, col: usize)
+        -> Result<(), CompileError> {
+        check_name(name, line, col)?;
+        if self.globals.contains_key(name) {
+            return Err(CompileError::new(line, col, &format!(
+                "\"{}\" is already defined", name)));
+        }
+        self.globals.insert(String::from(name), symbol);
+        Ok(())
+    }
+
+    fn declare_local(&mut self, name: &str, symbol: Symbol, line: usize, col: usize)
+        -> Result<(), CompileError> {
+        // Locals never become labels, so any name will do.
+        let scope = self.scopes.last_mut().unwrap();
+        if scope.contains_key(name) {
+            return Err(CompileError::new(line, col, &format!(
+                "\"{}\" is already defined in this block", name)));
+        }
+        scope.insert(String::from(name), symbol);
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Option<Symbol> {
+        self.scopes.iter().rev()
+            .filter_map(|scope| scope.get(name))
+            .next()
+            .or(self.globals.get(name))
+            .cloned()
+    }
+
+    fn lookup_expr(&self, name: &str, expr: &Expr) -> Result<Symbol, CompileError> {
+        self.lookup(name).ok_or(CompileError::new(expr.line, expr.col, &format!(
+            "\"{}\" isn't defined", name)))
+    }
+
+    fn global(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        let (name, size, init) = match stmt.kind {
+            StmtKind::Var { ref name, ref size, ref init } => (name, size, init),
+            _ => unreachable!(),
+        };
+        let is_array = size.is_some();
+        self.declare_global(name, Symbol::Global { is_array }, stmt.line, stmt.col)?;
+        let words = match (size, init) {
+            (&Some(ref size), _) => {
+                let size = self.array_size(size)?;
+                vec![String::from("0"); size as usize]
+            },
+            (&None, &Some(Expr { kind: ExprKind::Str(ref s), .. })) => vec![self.string(s)],
+            (&None, &Some(ref init)) => {
+                let value = self.const_value(init).ok_or(CompileError::new(
+                    init.line, init.col, "Global variables must start out as a constant"))?;
+                vec![value.to_string()]
+            },
+            (&None, &None) => vec![String::from("0")],
+        };
+        self.data.push(format!(":{}", name));
+        self.data.push(format!("  dat {}", words.join(", ")));
+        Ok(())
+    }
+
+    /// Adds a zero-terminated string to the data, and returns its label.
+    fn string(&mut self, s: &str) -> String {
+        let label = self.new_label();
+        let mut words: Vec<String> = s.chars().map(|c| (c as u16).to_string()).collect();
+        words.push(String::from("0"));
+        self.data.push(format!(":{}", label));
+        self.data.push(format!("  dat {}", words.join(", ")));
+        label
+    }
+
+    fn array_size(&self, size: &Expr) -> Result<u16, CompileError> {
+        match self.const_value(size) {
+            Some(size) if size > 0 => Ok(size),
+            _ => Err(CompileError::new(size.line, size.col,
+                                       "Array sizes must be positive constants")),
+        }
+    }
+
+    fn function(&mut self, f: &Function) -> Result<(), CompileError> {
+        // Every local gets its own slot for the whole function, so the frame size is
+        // known before any code is generated.
+        let frame_size = self.frame_size(&f.body)?;
+        self.scopes.push(HashMap::new());
+        for (i, param) in f.params.iter().enumerate() {
+            let offset = frame_size + 2 + i as u16;
+            self.declare_local(param, Symbol::Local { offset, is_array: false }, f.line,
+                               f.col)?;
+        }
+        self.next_local = 0;
+        self.return_label = format!("{}ret_{}", INTERNAL_PREFIX, f.name);
+
+        self.label(&f.name);
+        self.emit("set PUSH, J");
+        if frame_size > 0 {
+            self.emit(&format!("sub SP, {}", frame_size));
+        }
+        self.emit("set J, SP");
+        self.block(&f.body)?;
+        match f.body.last() {
+            Some(&Stmt { kind: StmtKind::Return(_), .. }) => (),
+            _ => self.emit("set A, 0"),
+        }
+        let return_label = self.return_label.clone();
+        self.label(&return_label);
+        self.emit("set SP, J");
+        if frame_size > 0 {
+            self.emit(&format!("add SP, {}", frame_size));
+        }
+        self.emit("set J, POP");
+        self.emit("set PC, POP");
+        self.scopes.pop();
+        Ok(())
+    }
+
+    /// Counts the words of locals declared anywhere in `stmts`.
+    fn frame_size(&self, stmts: &[Stmt]) -> Result<u16, CompileError> {
+        let mut size = 0u16;
+        for stmt in stmts {
+            let words = match stmt.kind {
+                StmtKind::Var { size: Some(ref s), .. } => self.array_size(s)?,
+                StmtKind::Var { size: None, .. } => 1,
+                StmtKind::If(_, ref then, ref otherwise) => {
+                    self.frame_size(then)? + self.frame_size(otherwise)?
+                },
+                StmtKind::While(_, ref body) | StmtKind::Block(ref body) => {
+                    self.frame_size(body)?
+                },
+                _ => 0,
+            };
+            size = size.checked_add(words).ok_or(CompileError::new(
+                stmt.line, stmt.col, "Too many locals to fit in memory"))?;
+        }
+        Ok(size)
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), CompileError> {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.statement(stmt)?;
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        match stmt.kind {
+            StmtKind::Var { ref name, ref size, ref init } => {
+                let offset = self.next_local;
+                let words = match *size {
+                    Some(ref size) => self.array_size(size)?,
+                    None => 1,
+                };
+                self.next_local += words;
+                let symbol = Symbol::Local { offset, is_array: size.is_some() };
+                // The variable isn't in scope in its own initializer.
+                if let Some(ref init) = *init {
+                    self.store(&local_operand(offset), init)?;
+                }
+                self.declare_local(name, symbol, stmt.line, stmt.col)?;
+            },
+            StmtKind::Assign(ref target, ref value) => self.assign(target, value)?,
+            StmtKind::Expr(ref expr) => self.expr(expr)?,
+            StmtKind::If(ref cond, ref then, ref otherwise) => {
+                let else_label = self.new_label();
+                self.branch_if_false(cond, &else_label)?;
+                self.block(then)?;
+                if otherwise.len() == 0 {
+                    self.label(&else_label);
+                } else {
+                    let end_label = self.new_label();
+                    self.emit(&format!("set PC, {}", end_label));
+                    self.label(&else_label);
+                    self.block(otherwise)?;
+                    self.label(&end_label);
+                }
+            },
+            StmtKind::While(ref cond, ref body) => {
+                let top_label = self.new_label();
+                let end_label = self.new_label();
+                self.label(&top_label);
+                self.branch_if_false(cond, &end_label)?;
+                self.loops.push((top_label.clone(), end_label.clone()));
+                self.block(body)?;
+                self.loops.pop();
+                self.emit(&format!("set PC, {}", top_label));
+                self.label(&end_label);
+            },
+            StmtKind::Break | StmtKind::Continue => {
+                let target = match (self.loops.last(), &stmt.kind) {
+                    (Some(&(_, ref end)), &StmtKind::Break) => end.clone(),
+                    (Some(&(ref top, _)), _) => top.clone(),
+                    (None, _) => {
+                        return Err(CompileError::new(stmt.line, stmt.col,
+                                                     "This isn't inside of a loop"));
+                    },
+                };
+                self.emit(&format!("set PC, {}", target));
+            },
+            StmtKind::Return(ref value) => {
+                match *value {
+                    Some(ref value) => self.expr(value)?,
+                    None => self.emit("set A, 0"),
+                }
+                let return_label = self.return_label.clone();
+                self.emit(&format!("set PC, {}", return_label));
+            },
+            StmtKind::Asm(ref code) => {
+                for line in code.lines() {
+                    let line = self.substitute_names(line.trim(), stmt)?;
+                    if line.len() > 0 {
+                        self.emit(&line);
+                    }
+                }
+            },
+            StmtKind::Block(ref stmts) => self.block(stmts)?,
+        }
+        Ok(())
+    }
+
+    /// Replaces `{name}` in inline assembly with an operand for the variable.
+    fn substitute_names(&self, line: &str, stmt: &Stmt) -> Result<String, CompileError> {
+        let mut result = String::new();
+        let mut rest = line;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}').ok_or(CompileError::new(
+                stmt.line, stmt.col, "Unclosed \"{\" in inline assembly"))? + start;
+            let name = rest[start + 1..end].trim();
+            let operand = self.lookup(name)
+                .and_then(|symbol| symbol_operand(name, symbol))
+                .ok_or(CompileError::new(stmt.line, stmt.col, &format!(
+                    "\"{}\" can't be used in inline assembly", name)))?;
+            result.push_str(&rest[..start]);
+            result.push_str(&operand);
+            rest = &rest[end + 1..];
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+
+    fn assign(&mut self, target: &Expr, value: &Expr) -> Result<(), CompileError> {
+        if let ExprKind::Name(ref name) = target.kind {
+            let operand = match self.lookup_expr(name, target)? {
+                Symbol::Local { offset, is_array: false } => local_operand(offset),
+                Symbol::Global { is_array: false } => format!("[{}]", name),
+                _ => {
+                    return Err(CompileError::new(target.line, target.col, &format!(
+                        "Can't assign to \"{}\"", name)));
+                },
+            };
+            return self.store(&operand, value);
+        }
+        match target.kind {
+            ExprKind::Deref(_) | ExprKind::Index(_, _) => (),
+            _ => return Err(CompileError::new(target.line, target.col,
+                                              "Can't assign to this expression")),
+        }
+        match self.operand(value) {
+            Some(operand) => {
+                self.address(target)?;
+                self.emit(&format!("set [A], {}", operand));
+            },
+            None => {
+                self.expr(value)?;
+                self.emit("set PUSH, A");
+                self.address(target)?;
+                self.emit("set [A], POP");
+            },
+        }
+        Ok(())
+    }
+
+    /// Evaluates `value` into `destination`, which is an operand that doesn't use A.
+    fn store(&mut self, destination: &str, value: &Expr) -> Result<(), CompileError> {
+        match self.operand(value) {
+            Some(operand) => self.emit(&format!("set {}, {}", destination, operand)),
+            None => {
+                self.expr(value)?;
+                self.emit(&format!("set {}, A", destination));
+            },
+        }
+        Ok(())
+    }
+
+    fn branch_if_false(&mut self, cond: &Expr, label: &str) -> Result<(), CompileError> {
+        self.expr(cond)?;
+        self.emit("ife A, 0");
+        self.emit(&format!("set PC, {}", label));
+        Ok(())
+    }
+
+    /// Returns an operand that holds the value of `expr`, if it's simple enough to have
+    /// one.  Undefined names don't have one, so `expr()` can report them.
+    fn operand(&self, expr: &Expr) -> Option<String> {
+        if let Some(value) = self.const_value(expr) {
+            return Some(value.to_string());
+        }
+        match expr.kind {
+            ExprKind::Name(ref name) => {
+                self.lookup(name).and_then(|symbol| symbol_operand(name, symbol))
+            },
+            _ => None,
+        }
+    }
+
+    /// Evaluates a constant expression at compile time.
+    fn const_value(&self, expr: &Expr) -> Option<u16> {
+        match expr.kind {
+            ExprKind::Number(v) => Some(v as u16),
+            ExprKind::Name(ref name) => match self.lookup(name) {
+                Some(Symbol::Const(v)) => Some(v),
+                _ => None,
+            },
+            ExprKind::Unary(op, ref operand) => {
+                let v = self.const_value(operand)?;
+                Some(match op {
+                    UnaryOp::Negate => v.wrapping_neg(),
+                    UnaryOp::Not => (v == 0) as u16,
+                    UnaryOp::BitNot => !v,
+                })
+            },
+            ExprKind::Binary(op, ref lhs, ref rhs) => {
+                let a = self.const_value(lhs)?;
+                let b = self.const_value(rhs)?;
+                let (sa, sb) = (a as i16, b as i16);
+                Some(match op {
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    // Leave division by zero for the DCPU, which makes it 0.
+                    BinaryOp::Div if b == 0 => return None,
+                    BinaryOp::Mod if b == 0 => return None,
+                    BinaryOp::Div => sa.wrapping_div(sb) as u16,
+                    BinaryOp::Mod => sa.wrapping_rem(sb) as u16,
+                    BinaryOp::And => a & b,
+                    BinaryOp::Or => a | b,
+                    BinaryOp::Xor => a ^ b,
+                    BinaryOp::Shl => a.checked_shl(b as u32).unwrap_or(0),
+                    BinaryOp::Shr => (sa >> b.min(15)) as u16,
+                    BinaryOp::Eq => (a == b) as u16,
+                    BinaryOp::Ne => (a != b) as u16,
+                    BinaryOp::Lt => (sa < sb) as u16,
+                    BinaryOp::Gt => (sa > sb) as u16,
+                    BinaryOp::Le => (sa <= sb) as u16,
+                    BinaryOp::Ge => (sa >= sb) as u16,
+                    BinaryOp::LogicalAnd => (a != 0 && b != 0) as u16,
+                    BinaryOp::LogicalOr => (a != 0 || b != 0) as u16,
+                })
+            },
+            _ => None,
+        }
+    }
+
+    /// Evaluates `expr` into A.
+    fn expr(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        if let Some(operand) = self.operand(expr) {
+            self.emit(&format!("set A, {}", operand));
+            return Ok(());
+        }
+        match expr.kind {
+            ExprKind::Number(_) => unreachable!(),
+            ExprKind::Str(ref s) => {
+                let label = self.string(s);
+                self.emit(&format!("set A, {}", label));
+            },
+            ExprKind::Name(ref name) => {
+                // Everything else has an operand.
+                match self.lookup_expr(name, expr)? {
+                    Symbol::Local { .. } => self.address(expr)?,
+                    _ => unreachable!(),
+                }
+            },
+            ExprKind::Unary(op, ref operand) => {
+                self.expr(operand)?;
+                match op {
+                    UnaryOp::Negate => self.emit("mli A, 0xffff"),
+                    UnaryOp::Not => {
+                        self.emit("set B, A");
+                        self.emit("set A, 0");
+                        self.emit("ife B, 0");
+                        self.emit("set A, 1");
+                    },
+                    UnaryOp::BitNot => self.emit("xor A, 0xffff"),
+                }
+            },
+            ExprKind::Binary(op, ref lhs, ref rhs)
+                if op == BinaryOp::LogicalAnd || op == BinaryOp::LogicalOr => {
+                // Only evaluate the right side if the left side didn't decide it.
+                let is_and = op == BinaryOp::LogicalAnd;
+                let short_label = self.new_label();
+                let end_label = self.new_label();
+                let short_test = if is_and { "ife A, 0" } else { "ifn A, 0" };
+                self.expr(lhs)?;
+                self.emit(short_test);
+                self.emit(&format!("set PC, {}", short_label));
+                self.expr(rhs)?;
+                self.emit(short_test);
+                self.emit(&format!("set PC, {}", short_label));
+                self.emit(&format!("set A, {}", if is_and { 1 } else { 0 }));
+                self.emit(&format!("set PC, {}", end_label));
+                self.label(&short_label);
+                self.emit(&format!("set A, {}", if is_and { 0 } else { 1 }));
+                self.label(&end_label);
+            },
+            ExprKind::Binary(op, ref lhs, ref rhs) => {
+                let rhs = match self.operand(rhs) {
+                    Some(operand) => {
+                        self.expr(lhs)?;
+                        operand
+                    },
+                    None => {
+                        self.expr(lhs)?;
+                        self.emit("set PUSH, A");
+                        self.expr(rhs)?;
+                        self.emit("set B, A");
+                        self.emit("set A, POP");
+                        String::from("B")
+                    },
+                };
+                self.binary_op(op, &rhs);
+            },
+            ExprKind::Call(ref name, ref args) => {
+                match self.lookup_expr(name, expr)? {
+                    Symbol::Function(arity) if arity == args.len() => (),
+                    Symbol::Function(arity) => {
+                        return Err(CompileError::new(expr.line, expr.col, &format!(
+                            "{}() takes {} argument(s), but got {}", name, arity,
+                            args.len())));
+                    },
+                    _ => {
+                        return Err(CompileError::new(expr.line, expr.col, &format!(
+                            "\"{}\" isn't a function", name)));
+                    },
+                }
+                for arg in args.iter().rev() {
+                    match self.operand(arg) {
+                        Some(operand) => self.emit(&format!("set PUSH, {}", operand)),
+                        None => {
+                            self.expr(arg)?;
+                            self.emit("set PUSH, A");
+                        },
+                    }
+                }
+                self.emit(&format!("jsr {}", name));
+                if args.len() > 0 {
+                    self.emit(&format!("add SP, {}", args.len()));
+                }
+            },
+            ExprKind::Index(_, _) => {
+                self.address(expr)?;
+                self.emit("set A, [A]");
+            },
+            ExprKind::Deref(ref pointer) => {
+                self.expr(pointer)?;
+                self.emit("set A, [A]");
+            },
+            ExprKind::AddressOf(ref target) => self.address(target)?,
+        }
+        Ok(())
+    }
+
+    fn binary_op(&mut self, op: BinaryOp, rhs: &str) {
+        let mnemonic = match op {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mli",
+            BinaryOp::Div => "dvi",
+            BinaryOp::Mod => "mdi",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "bor",
+            BinaryOp::Xor => "xor",
+            BinaryOp::Shl => "shl",
+            BinaryOp::Shr => "asr",
+            _ => "",
+        };
+        if mnemonic.len() > 0 {
+            self.emit(&format!("{} A, {}", mnemonic, rhs));
+            return;
+        }
+
+        // Comparisons leave 0 or 1 in A.  There's no single "less than or equal"
+        // test, so those test for the opposite and flip the result.
+        let (test, when_true) = match op {
+            BinaryOp::Eq => ("ife", 1),
+            BinaryOp::Ne => ("ifn", 1),
+            BinaryOp::Lt => ("ifu", 1),
+            BinaryOp::Gt => ("ifa", 1),
+            BinaryOp::Le => ("ifa", 0),
+            BinaryOp::Ge => ("ifu", 0),
+            _ => unreachable!(),
+        };
+        self.emit(&format!("set C, {}", 1 - when_true));
+        self.emit(&format!("{} A, {}", test, rhs));
+        self.emit(&format!("set C, {}", when_true));
+        self.emit("set A, C");
+    }
+
+    /// Evaluates the address of `expr` into A.
+    fn address(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        match expr.kind {
+            ExprKind::Name(ref name) => match self.lookup_expr(name, expr)? {
+                Symbol::Local { offset, .. } => {
+                    self.emit("set A, J");
+                    if offset > 0 {
+                        self.emit(&format!("add A, {}", offset));
+                    }
+                },
+                Symbol::Global { .. } | Symbol::Function(_) => {
+                    self.emit(&format!("set A, {}", name));
+                },
+                Symbol::Const(_) => {
+                    return Err(CompileError::new(expr.line, expr.col, &format!(
+                        "Constant \"{}\" doesn't have an address", name)));
+                },
+            },
+            ExprKind::Deref(ref pointer) => self.expr(pointer)?,
+            ExprKind::Index(ref base, ref index) => {
+                match self.operand(index) {
+                    Some(operand) => {
+                        self.expr(base)?;
+                        self.emit(&format!("add A, {}", operand));
+                    },
+                    None => {
+                        self.expr(base)?;
+                        self.emit("set PUSH, A");
+                        self.expr(index)?;
+                        self.emit("add A, POP");
+                    },
+                }
+            },
+            _ => {
+                return Err(CompileError::new(expr.line, expr.col,
+                                             "This expression doesn't have an address"));
+            },
+        }
+        Ok(())
+    }
+}
+
+fn local_operand(offset: u16) -> String {
+    if offset == 0 {
+        String::from("[J]")
+    } else {
+        format!("[J+{}]", offset)
+    }
+}
+
+/// Returns an operand holding the value of a symbol.  Local arrays don't have one, since
+/// their address has to be computed from the frame pointer.
+fn symbol_operand(name: &str, symbol: Symbol) -> Option<String> {
+    match symbol {
+        Symbol::Local { offset, is_array: false } => Some(local_operand(offset)),
+        Symbol::Local { is_array: true, .. } => None,
+        Symbol::Global { is_array: false } => Some(format!("[{}]", name)),
+        Symbol::Global { is_array: true } | Symbol::Function(_) => Some(String::from(name)),
+        Symbol::Const(v) => Some(v.to_string()),
+    }
+}
+
+/// Names of globals and functions end up as labels in the generated assembly, so they
+/// can't look like registers or the compiler's own labels.
+fn check_name(name: &str, line: usize, col: usize) -> Result<(), CompileError> {
+    let upper = name.to_uppercase();
+    if register::try_from(name).is_some() || OPERAND_KEYWORDS.contains(&upper.as_str()) ||
+       name.starts_with(INTERNAL_PREFIX) {
+        return Err(CompileError::new(line, col, &format!(
+            "\"{}\" is reserved, so it can't be used as a name", name)));
+    }
+    Ok(())
+}
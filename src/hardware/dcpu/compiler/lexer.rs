@@ -0,0 +1,151 @@
+use super::CompileError;
+use super::super::literal;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Ident(String),
+    Number(i64),
+    Str(String),
+    /// An operator or piece of punctuation, like "<=" or "{".
+    Punct(&'static str),
+    Eof,
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// One-based line number.
+    pub line: usize,
+    /// One-based column, counted in characters.
+    pub col: usize,
+}
+
+/// Punctuation, longest first, so "<=" wins over "<".
+const PUNCTUATION: &[&str] = &[
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<", ">", "=",
+    "(", ")", "{", "}", "[", "]", ",", ";",
+];
+
+
+/// Splits `source` into tokens, ending with an `Eof` token.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, CompileError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut line = 1;
+    let mut line_start = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let col = i - line_start + 1;
+        if c == '\n' {
+            i += 1;
+            line += 1;
+            line_start = i;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        // Comments
+        if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            loop {
+                if i >= chars.len() {
+                    return Err(CompileError::new(line, col, "Unterminated comment"));
+                }
+                if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                    i += 2;
+                    break;
+                }
+                if chars[i] == '\n' {
+                    line += 1;
+                    line_start = i + 1;
+                }
+                i += 1;
+            }
+            continue;
+        }
+
+        let start = i;
+        let start_line = line;
+        let kind = if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            TokenKind::Ident(chars[start..i].iter().collect())
+        } else if c.is_digit(10) {
+            while i < chars.len() && chars[i].is_alphanumeric() {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            match literal::parse(&text) {
+                Ok(v) if v <= 0xffff => TokenKind::Number(v),
+                Ok(_) => {
+                    return Err(CompileError::new(start_line, col, &format!(
+                        "Literal \"{}\" doesn't fit in a word", text)));
+                },
+                Err(e) => return Err(CompileError::new(start_line, col, &e)),
+            }
+        } else if c == '"' || c == '\'' {
+            // Find the closing quote, then let the assembler's unescaping do the rest.
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                if chars.get(i) == Some(&'\n') {
+                    line += 1;
+                    line_start = i + 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(CompileError::new(start_line, col, "Unterminated literal"));
+            }
+            i += 1;
+            let text: String = chars[start..i].iter().collect();
+            if c == '\'' {
+                match literal::parse(&text) {
+                    Ok(v) => TokenKind::Number(v),
+                    Err(e) => return Err(CompileError::new(start_line, col, &e)),
+                }
+            } else {
+                match literal::unescape(&text[1..text.len() - 1]) {
+                    Ok(words) => {
+                        TokenKind::Str(words.iter().map(|&w| w as u8 as char).collect())
+                    },
+                    Err((_, e)) => return Err(CompileError::new(start_line, col, &e)),
+                }
+            }
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match PUNCTUATION.iter().find(|p| rest.starts_with(*p)) {
+                Some(p) => {
+                    i += p.len();
+                    TokenKind::Punct(p)
+                },
+                None => {
+                    return Err(CompileError::new(start_line, col, &format!(
+                        "Unexpected character '{}'", c)));
+                },
+            }
+        };
+        tokens.push(Token { kind, line: start_line, col });
+    }
+    tokens.push(Token {
+        kind: TokenKind::Eof,
+        line,
+        col: chars.len() - line_start + 1,
+    });
+    Ok(tokens)
+}
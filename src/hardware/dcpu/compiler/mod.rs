@@ -0,0 +1,317 @@
+//! A compiler for a small structured language, which generates DCPU-16 assembly.
+//!
+//! # The language
+//!
+//! Every value is a signed 16-bit integer, which doubles as a pointer, since the DCPU
+//! addresses memory by the word.
+//!
+//! ```text
+//! const SCREEN = 0x8000;
+//! var count = 0;          // Globals have to start out as constants.
+//! var buffer[16];         // Arrays are zeroed.
+//!
+//! fn fill(p, n, value) {
+//!     var i = 0;
+//!     while (i < n) {
+//!         p[i] = value;
+//!         i = i + 1;
+//!     }
+//! }
+//!
+//! fn main() {
+//!     var local[4];
+//!     fill(local, 4, 'x');
+//!     fill(&buffer[2], 3, *local + 1);
+//!     asm("set {count}, 0xff");
+//!     return count;
+//! }
+//! ```
+//!
+//! * Items are functions (`fn`), globals (`var`) and constants (`const`).  Functions can
+//!   be called before they're defined, but globals and constants can't.
+//! * Statements are `var`, assignment, expression statements, `if`/`else`, `while`,
+//!   `break`, `continue`, `return` and `asm("...")`.  Falling off the end of a function
+//!   returns 0.
+//! * Operators have the same precedence as in C: `||`, `&&`, `|`, `^`, `&`, `== !=`,
+//!   `< > <= >=`, `<< >>`, `+ -`, `* / %`, then unary `- ! ~ * &` and indexing.
+//!   Division and comparisons are signed, and `&&` and `||` short-circuit.
+//! * The name of an array is its address, and `p[i]` is the same as `*(p + i)`.
+//! * String literals are zero-terminated arrays, one character per word.
+//! * `asm` copies its string into the output, one instruction per line.  `{name}`
+//!   is replaced by an operand for a variable, like `[J+2]` or `[count]`, or by the
+//!   value of a constant.
+//!
+//! # Calling convention
+//!
+//! * Arguments are pushed from last to first, then the caller does `jsr`, and pops the
+//!   arguments with `add SP, n` once the call returns.
+//! * The return value is in A.
+//! * A, B, C and EX can be changed by any call.  J is the frame pointer, and is
+//!   preserved.  Compiled code never touches X, Y, Z or I, so inline assembly can use
+//!   them to hold values across calls, but functions written in assembly have to
+//!   preserve them.
+//! * Functions start with `set PUSH, J; sub SP, n; set J, SP`, where `n` is the number of
+//!   words of locals.  So with `n` words of locals, a frame looks like:
+//!
+//! | Address          | Holds                         |
+//! |------------------|-------------------------------|
+//! | `J + 0 .. J + n` | Locals, in declaration order   |
+//! | `J + n`          | The caller's J                 |
+//! | `J + n + 1`      | The return address             |
+//! | `J + n + 2 + i`  | Argument `i`                   |
+//!
+//! Functions and globals are labeled with their own names, so assembly can call them or
+//! use them directly.  The compiler's own labels start with `__`, and compiled programs
+//! start by calling `main`, then halt with the return value in A.
+
+mod codegen;
+mod lexer;
+mod parser;
+
+use std::fmt;
+
+use super::assembler;
+use super::assembler::AssemblerOptions;
+use super::assembler::program::AssembledProgram;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompileError {
+    /// One-based line number, or 0 if the error is about the whole program.
+    pub line: usize,
+    /// One-based column.
+    pub col: usize,
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new(line: usize, col: usize, message: &str) -> CompileError {
+        CompileError {
+            line,
+            col,
+            message: String::from(message),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.line, self.col, self.message)
+        }
+    }
+}
+
+/// Compiles `source` into assembly for `assembler::assemble`.
+pub fn compile(source: &str) -> Result<String, CompileError> {
+    let tokens = lexer::tokenize(source)?;
+    let items = parser::parse(tokens)?;
+    codegen::generate(&items)
+}
+
+/// Compiles and assembles `source`, with the assembler's optimizer cleaning up after the
+/// code generator.  The only way for compiled code not to assemble is a mistake in inline
+/// assembly, which is reported against the generated assembly.
+pub fn compile_program(source: &str) -> Result<AssembledProgram, CompileError> {
+    let asm = compile(source)?;
+    let options = AssemblerOptions {
+        optimize: true,
+        ..AssemblerOptions::default()
+    };
+    let assembly = assembler::assemble_with_options(&asm, &options);
+    match assembly.program {
+        Some(program) => Ok(program),
+        None => {
+            Err(CompileError::new(0, 0, &format!(
+                "The generated assembly didn't assemble:\n{}",
+                assembler::diagnostic::render_all(&assembly.diagnostics, &asm))))
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Dcpu;
+
+    /// Compiles and runs a program, and returns the DCPU once `main` returns.
+    fn run(source: &str) -> (Dcpu, AssembledProgram) {
+        let program = match compile_program(source) {
+            Ok(p) => p,
+            Err(e) => panic!("{}", e),
+        };
+        let mut dcpu = Dcpu::new();
+        dcpu.load_program(&program.words);
+        let mut ticks = 0;
+        while !dcpu.finished() {
+            dcpu.tick();
+            ticks += 1;
+            assert!(ticks < 1_000_000, "Program didn't halt");
+        }
+        (dcpu, program)
+    }
+
+    fn result(source: &str) -> u16 {
+        run(source).0.reg(0)
+    }
+
+    #[test]
+    fn recursion() {
+        assert_eq!(result("
+fn fact(n) {
+    if (n <= 1) {
+        return 1;
+    }
+    return n * fact(n - 1);
+}
+
+fn fib(n) {
+    if (n < 2) { return n; }
+    return fib(n - 1) + fib(n - 2);
+}
+
+fn main() {
+    return fact(7) - fib(10);
+}
+"), 5040 - 55);
+    }
+
+    #[test]
+    fn locals_and_arithmetic() {
+        assert_eq!(result("
+fn main() {
+    var a = 7;
+    var b = -3;
+    var c = a * b + (a << 2) - a / b - a % b;   // -21 + 28 + 2 - 1
+    if (b < 0 && !(a < b) && (a ^ 5) == 2 && ~0 == -1 && (b >> 1) == -2) {
+        return c;
+    }
+    return 0;
+}
+"), 8);
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(result("
+fn main() {
+    var i = 0;
+    var sum = 0;
+    while (1) {
+        i = i + 1;
+        if (i > 100) {
+            break;
+        } else if (i % 2 == 0) {
+            continue;
+        }
+        sum = sum + i;
+    }
+    return sum;
+}
+"), 2500);
+    }
+
+    #[test]
+    fn pointers_and_arrays() {
+        let (dcpu, program) = run("
+const N = 5;
+var squares[N];
+var total;
+
+fn fill(p, n) {
+    var i = 0;
+    while (i < n) {
+        p[i] = i * i;
+        i = i + 1;
+    }
+}
+
+fn sum(p, n) {
+    var total = 0;
+    while (n > 0) {
+        total = total + *p;
+        p = p + 1;
+        n = n - 1;
+    }
+    return total;
+}
+
+fn swap(a, b) {
+    var t = *a;
+    *a = *b;
+    *b = t;
+}
+
+fn main() {
+    var local[N];
+    var x = 1;
+    var y = 2;
+    fill(squares, N);
+    fill(local, N);
+    swap(&x, &y);
+    local[0] = x * 10 + y;
+    total = sum(squares, N) + sum(&local[1], N - 1);
+    return local[0];
+}
+");
+        assert_eq!(dcpu.reg(0), 21);
+        // 0 + 1 + 4 + 9 + 16, then 1 + 4 + 9 + 16.
+        let total = program.symbol("total").unwrap().address;
+        assert_eq!(dcpu.mem(total), 60);
+        // Calls leave the stack as they found it.
+        assert_eq!(dcpu.sp(), 0xffff);
+    }
+
+    #[test]
+    fn strings_and_inline_asm() {
+        assert_eq!(result("
+var message = \"hi\\n\";
+
+fn length(s) {
+    var n = 0;
+    while (s[n]) {
+        n = n + 1;
+    }
+    return n;
+}
+
+fn main() {
+    var x = 40;
+    asm(\"
+        set X, {x}
+        add X, 2
+        set {x}, X
+    \");
+    return x * 100 + length(message) * 10 + length(\"abcd\");
+}
+"), 4234);
+    }
+
+    #[test]
+    fn string_globals() {
+        // String globals point at their characters from the start, without any code
+        // having to run.
+        let program = compile_program("var greeting = \"ok\"; fn main() {}").ok().unwrap();
+        let greeting = program.symbol("greeting").unwrap().address as usize;
+        let chars = program.words[greeting] as usize;
+        assert_eq!(&program.words[chars..chars + 3], &['o' as u16, 'k' as u16, 0][..]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source: &str| compile(source).err().unwrap();
+        assert_eq!(error("fn main() { return x; }"),
+                   CompileError::new(1, 20, "\"x\" isn't defined"));
+        assert_eq!(error("fn f(a) {} fn main() { f(); }").message,
+                   "f() takes 1 argument(s), but got 0");
+        assert_eq!(error("fn main() {\n  break;\n}"),
+                   CompileError::new(2, 3, "This isn't inside of a loop"));
+        assert_eq!(error("var pc; fn main() {}").message,
+                   "\"pc\" is reserved, so it can't be used as a name");
+        assert_eq!(error("fn f() {}").message, "There's no main() function");
+        assert_eq!(error("fn main() { 1 = 2; }").message, "Can't assign to this expression");
+    }
+}
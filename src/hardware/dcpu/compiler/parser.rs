@@ -0,0 +1,418 @@
+use super::CompileError;
+use super::lexer::{Token, TokenKind};
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    BitNot,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    LogicalAnd,
+    LogicalOr,
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
+    Number(i64),
+    /// A string literal, which evaluates to a pointer to its zero-terminated characters.
+    Str(String),
+    Name(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Deref(Box<Expr>),
+    AddressOf(Box<Expr>),
+}
+
+#[derive(Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug)]
+pub enum StmtKind {
+    /// `var name;`, `var name = init;` or `var name[size];`
+    Var {
+        name: String,
+        size: Option<Expr>,
+        init: Option<Expr>,
+    },
+    Assign(Expr, Expr),
+    Expr(Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Break,
+    Continue,
+    Return(Option<Expr>),
+    Asm(String),
+    Block(Vec<Stmt>),
+}
+
+#[derive(Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug)]
+pub enum Item {
+    Function(Function),
+    /// A global variable, which is a `Var` statement outside of any function.
+    Global(Stmt),
+    Const {
+        name: String,
+        value: Expr,
+        line: usize,
+        col: usize,
+    },
+}
+
+const KEYWORDS: &[&str] = &["fn", "var", "const", "if", "else", "while", "break",
+                            "continue", "return", "asm"];
+
+/// Binary operators from loosest to tightest binding.
+const PRECEDENCE_LEVELS: &[&[(&str, BinaryOp)]] = &[
+    &[("||", BinaryOp::LogicalOr)],
+    &[("&&", BinaryOp::LogicalAnd)],
+    &[("|", BinaryOp::Or)],
+    &[("^", BinaryOp::Xor)],
+    &[("&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[("<", BinaryOp::Lt), (">", BinaryOp::Gt), ("<=", BinaryOp::Le), (">=", BinaryOp::Ge)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Mod)],
+];
+
+
+pub fn parse(tokens: Vec<Token>) -> Result<Vec<Item>, CompileError> {
+    let mut parser = Parser { tokens, pos: 0 };
+    let mut items = Vec::new();
+    while parser.peek().kind != TokenKind::Eof {
+        items.push(parser.item()?);
+    }
+    Ok(items)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, CompileError> {
+        let token = self.peek();
+        Err(CompileError::new(token.line, token.col, message))
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        match self.peek().kind {
+            TokenKind::Punct(p) => p == punct,
+            _ => false,
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek().kind {
+            TokenKind::Ident(ref name) => name == keyword,
+            _ => false,
+        }
+    }
+
+    /// Consumes the given punctuation if it's next.
+    fn eat(&mut self, punct: &str) -> bool {
+        if self.is_punct(punct) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), CompileError> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            self.error(&format!("Expected \"{}\"", punct))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, CompileError> {
+        let name = match self.peek().kind {
+            TokenKind::Ident(ref name) if !KEYWORDS.contains(&name.as_str()) => name.clone(),
+            _ => return self.error("Expected a name"),
+        };
+        self.next();
+        Ok(name)
+    }
+
+    fn item(&mut self) -> Result<Item, CompileError> {
+        let (line, col) = (self.peek().line, self.peek().col);
+        if self.is_keyword("fn") {
+            self.next();
+            let name = self.ident()?;
+            self.expect("(")?;
+            let mut params = Vec::new();
+            if !self.eat(")") {
+                loop {
+                    params.push(self.ident()?);
+                    if self.eat(")") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            let body = self.block()?;
+            Ok(Item::Function(Function { name, params, body, line, col }))
+        } else if self.is_keyword("var") {
+            Ok(Item::Global(self.statement()?))
+        } else if self.is_keyword("const") {
+            self.next();
+            let name = self.ident()?;
+            self.expect("=")?;
+            let value = self.expr()?;
+            self.expect(";")?;
+            Ok(Item::Const { name, value, line, col })
+        } else {
+            self.error("Expected \"fn\", \"var\" or \"const\"")
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if self.peek().kind == TokenKind::Eof {
+                return self.error("Expected \"}\"");
+            }
+            stmts.push(self.statement()?);
+        }
+        Ok(stmts)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, CompileError> {
+        let (line, col) = (self.peek().line, self.peek().col);
+        let kind = if self.is_punct("{") {
+            StmtKind::Block(self.block()?)
+        } else if self.is_keyword("var") {
+            self.next();
+            let name = self.ident()?;
+            let size = if self.eat("[") {
+                let size = self.expr()?;
+                self.expect("]")?;
+                Some(size)
+            } else {
+                None
+            };
+            let init = if size.is_none() && self.eat("=") {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            self.expect(";")?;
+            StmtKind::Var { name, size, init }
+        } else if self.is_keyword("if") {
+            self.next();
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            let then = self.block()?;
+            let otherwise = if self.is_keyword("else") {
+                self.next();
+                if self.is_keyword("if") {
+                    vec![self.statement()?]
+                } else {
+                    self.block()?
+                }
+            } else {
+                Vec::new()
+            };
+            StmtKind::If(cond, then, otherwise)
+        } else if self.is_keyword("while") {
+            self.next();
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            StmtKind::While(cond, self.block()?)
+        } else if self.is_keyword("break") {
+            self.next();
+            self.expect(";")?;
+            StmtKind::Break
+        } else if self.is_keyword("continue") {
+            self.next();
+            self.expect(";")?;
+            StmtKind::Continue
+        } else if self.is_keyword("return") {
+            self.next();
+            let value = if self.is_punct(";") { None } else { Some(self.expr()?) };
+            self.expect(";")?;
+            StmtKind::Return(value)
+        } else if self.is_keyword("asm") {
+            self.next();
+            self.expect("(")?;
+            let code = match self.next().kind {
+                TokenKind::Str(code) => code,
+                _ => return Err(CompileError::new(line, col, "Expected a string after asm(")),
+            };
+            self.expect(")")?;
+            self.expect(";")?;
+            StmtKind::Asm(code)
+        } else {
+            let target = self.expr()?;
+            let kind = if self.eat("=") {
+                StmtKind::Assign(target, self.expr()?)
+            } else {
+                StmtKind::Expr(target)
+            };
+            self.expect(";")?;
+            kind
+        };
+        Ok(Stmt { kind, line, col })
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        if level == PRECEDENCE_LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        'outer: loop {
+            for &(punct, op) in PRECEDENCE_LEVELS[level] {
+                if self.is_punct(punct) {
+                    let (line, col) = (self.peek().line, self.peek().col);
+                    self.next();
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr {
+                        kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+                        line,
+                        col,
+                    };
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        let (line, col) = (self.peek().line, self.peek().col);
+        let kind = if self.eat("-") {
+            ExprKind::Unary(UnaryOp::Negate, Box::new(self.unary()?))
+        } else if self.eat("!") {
+            ExprKind::Unary(UnaryOp::Not, Box::new(self.unary()?))
+        } else if self.eat("~") {
+            ExprKind::Unary(UnaryOp::BitNot, Box::new(self.unary()?))
+        } else if self.eat("*") {
+            ExprKind::Deref(Box::new(self.unary()?))
+        } else if self.eat("&") {
+            ExprKind::AddressOf(Box::new(self.unary()?))
+        } else {
+            return self.postfix();
+        };
+        Ok(Expr { kind, line, col })
+    }
+
+    fn postfix(&mut self) -> Result<Expr, CompileError> {
+        let mut expr = self.primary()?;
+        while self.is_punct("[") {
+            let (line, col) = (self.peek().line, self.peek().col);
+            self.next();
+            let index = self.expr()?;
+            self.expect("]")?;
+            expr = Expr {
+                kind: ExprKind::Index(Box::new(expr), Box::new(index)),
+                line,
+                col,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        let (line, col) = (self.peek().line, self.peek().col);
+        let kind = match self.peek().kind.clone() {
+            TokenKind::Number(v) => {
+                self.next();
+                ExprKind::Number(v)
+            },
+            TokenKind::Str(s) => {
+                self.next();
+                ExprKind::Str(s)
+            },
+            TokenKind::Punct("(") => {
+                self.next();
+                let expr = self.expr()?;
+                self.expect(")")?;
+                return Ok(expr);
+            },
+            TokenKind::Ident(_) => {
+                let name = self.ident()?;
+                if self.eat("(") {
+                    let mut args = Vec::new();
+                    if !self.eat(")") {
+                        loop {
+                            args.push(self.expr()?);
+                            if self.eat(")") {
+                                break;
+                            }
+                            self.expect(",")?;
+                        }
+                    }
+                    ExprKind::Call(name, args)
+                } else {
+                    ExprKind::Name(name)
+                }
+            },
+            _ => return self.error("Expected an expression"),
+        };
+        Ok(Expr { kind, line, col })
+    }
+}
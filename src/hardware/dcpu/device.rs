@@ -0,0 +1,29 @@
+//! Hardware that plugs into a DCPU.
+//!
+//! Programs count devices with `HWN`, ask what each one is with `HWQ`, and talk to them
+//! with `HWI`.  Devices are shared, so whatever owns the DCPU can keep a handle on them
+//! too, e.g. to draw what a monitor is showing.
+
+use super::Dcpu;
+
+
+pub trait Device {
+    /// What kind of device this is, as reported in `A` and `B` by `HWQ`.
+    fn id(&self) -> u32;
+
+    /// Reported in `C` by `HWQ`.
+    fn version(&self) -> u16;
+
+    /// Who made the device, as reported in `X` and `Y` by `HWQ`.  Devices whose spec
+    /// leaves this open, and our own devices that have no spec, report 0.
+    fn manufacturer(&self) -> u32;
+
+    /// Handles an `HWI` sent to the device.  Arguments are in the DCPU's registers, and
+    /// results go back into its registers or memory.
+    fn interrupt(&mut self, dcpu: &mut Dcpu);
+
+    /// Called after every instruction the DCPU runs, so devices that keep time can go by
+    /// `Dcpu::cycles`.  Does nothing by default.
+    #[allow(unused_variables)]
+    fn tick(&mut self, dcpu: &mut Dcpu) {}
+}
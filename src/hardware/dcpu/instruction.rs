@@ -65,6 +65,27 @@ impl Instruction {
         }
     }
 
+    /// Whether the instruction is one of the `IF*` ops.
+    pub fn is_conditional(&self) -> bool {
+        match *self {
+            Basic(ref i) => i.op.is_conditional(),
+            Special(_) => false,
+        }
+    }
+
+    /// How many cycles the instruction takes, not counting the extra cycle a failed
+    /// conditional takes.
+    pub fn num_cycles(&self) -> u16 {
+        match *self {
+            Basic(ref i) => {
+                i.op.num_cycles() + (i.a.num_cycles() + i.b.num_cycles()) as u16
+            },
+            Special(ref i) => {
+                i.op.num_cycles() + i.a.num_cycles() as u16
+            },
+        }
+    }
+
     pub fn num_words(&self) -> u16 {
         1 + match *self {
             Basic(ref i) => {
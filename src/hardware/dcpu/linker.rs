@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+
+use super::object::{ObjectFile, RelocationTarget};
+
+
+/// Settings that control how object files are linked.
+pub struct LinkOptions {
+    /// The address the first section gets placed at.
+    pub origin: u16,
+}
+
+/// A symbol, and where the linker put it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkedSymbol {
+    pub name: String,
+    /// Name of the object file the symbol came from.
+    pub module: String,
+    pub address: u16,
+    pub exported: bool,
+}
+
+/// A program made by linking object files together.
+pub struct LinkedImage {
+    /// The program, starting at the origin.
+    pub words: Vec<u16>,
+    /// Every symbol from every object file, sorted by address.
+    pub symbols: Vec<LinkedSymbol>,
+    /// Where every section and symbol ended up.
+    pub map: String,
+}
+
+/// Where a single object file's section got placed.
+struct Placement {
+    section_name: String,
+    module: String,
+    start: u32,
+    size: u32,
+}
+
+impl Default for LinkOptions {
+    fn default() -> LinkOptions {
+        LinkOptions {
+            origin: 0,
+        }
+    }
+}
+
+impl LinkedImage {
+    /// Looks up the address of a symbol exported by any of the linked object files.
+    pub fn exported_address(&self, name: &str) -> Option<u16> {
+        self.symbols.iter()
+            .find(|s| s.exported && s.name == name)
+            .map(|s| s.address)
+    }
+}
+
+/// Links `objects` into a single program.
+///
+/// Sections with the same name are placed next to each other, in the order the object
+/// files were given.  Section names are ordered by when they're first seen, so putting
+/// the object with the entry point first is enough to make it start at the origin.
+pub fn link(objects: &[ObjectFile], options: &LinkOptions) -> Result<LinkedImage, Vec<String>> {
+    let mut errors = Vec::new();
+
+    // Object files can be made by hand, as well as read in, so they might not make sense.
+    for object in objects {
+        if let Err(e) = object.check() {
+            errors.push(format!("{}: {}", object.name, e));
+        }
+    }
+    if errors.len() > 0 {
+        return Err(errors);
+    }
+
+    // Place the sections.
+    let mut section_names: Vec<&str> = Vec::new();
+    for object in objects {
+        for section in &object.sections {
+            if !section_names.contains(&section.name.as_str()) {
+                section_names.push(&section.name);
+            }
+        }
+    }
+    // `bases[i][j]` is where section `j` of object `i` starts.
+    let mut bases: Vec<Vec<u32>> = objects.iter()
+        .map(|o| vec![0; o.sections.len()])
+        .collect();
+    let mut placements = Vec::new();
+    let mut address = options.origin as u32;
+    for name in &section_names {
+        for (i, object) in objects.iter().enumerate() {
+            for (j, section) in object.sections.iter().enumerate() {
+                if section.name != *name {
+                    continue;
+                }
+                bases[i][j] = address;
+                placements.push(Placement {
+                    section_name: section.name.clone(),
+                    module: object.name.clone(),
+                    start: address,
+                    size: section.words.len() as u32,
+                });
+                address += section.words.len() as u32;
+            }
+        }
+    }
+    if address > 0x10000 {
+        errors.push(format!("Program needs {} words, which doesn't fit in memory",
+                            address - options.origin as u32));
+        return Err(errors);
+    }
+
+    // Resolve the symbols.
+    let mut symbols = Vec::new();
+    let mut exports: HashMap<&str, (u16, &str)> = HashMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        for symbol in &object.symbols {
+            let address = (bases[i][symbol.section] + symbol.offset as u32) as u16;
+            if symbol.exported {
+                if let Some(&(_, other)) = exports.get(symbol.name.as_str()) {
+                    errors.push(format!("Symbol \"{}\" is exported by both {} and {}",
+                                        symbol.name, other, object.name));
+                } else {
+                    exports.insert(&symbol.name, (address, &object.name));
+                }
+            }
+            symbols.push(LinkedSymbol {
+                name: symbol.name.clone(),
+                module: object.name.clone(),
+                address,
+                exported: symbol.exported,
+            });
+        }
+    }
+    for object in objects {
+        for import in &object.imports {
+            if !exports.contains_key(import.as_str()) {
+                errors.push(format!("Undefined symbol \"{}\" imported by {}", import,
+                                    object.name));
+            }
+        }
+    }
+    if errors.len() > 0 {
+        return Err(errors);
+    }
+
+    // Copy the sections into place, and apply the relocations.
+    let mut words = vec![0; (address - options.origin as u32) as usize];
+    for (i, object) in objects.iter().enumerate() {
+        for (j, section) in object.sections.iter().enumerate() {
+            let start = (bases[i][j] - options.origin as u32) as usize;
+            words[start..start + section.words.len()].copy_from_slice(&section.words);
+            for relocation in &section.relocations {
+                let target = match relocation.target {
+                    RelocationTarget::Section(idx) => bases[i][idx] as u16,
+                    RelocationTarget::Import(ref name) => exports[name.as_str()].0,
+                };
+                let word = &mut words[start + relocation.offset as usize];
+                *word = word.wrapping_add(target);
+            }
+        }
+    }
+
+    symbols.sort_by(|a, b| (a.address, &a.module).cmp(&(b.address, &b.module)));
+    let map = make_map(&placements, &symbols);
+    Ok(LinkedImage {
+        words,
+        symbols,
+        map,
+    })
+}
+
+fn make_map(placements: &[Placement], symbols: &[LinkedSymbol]) -> String {
+    let mut map = String::from("SECTIONS\n");
+    for p in placements {
+        map.push_str(&format!("{:04x}  {:04x}  {:<12}  {}\n", p.start, p.size, p.section_name,
+                              p.module));
+    }
+    map.push_str("\nSYMBOLS\n");
+    for s in symbols {
+        // Local symbols can share names across modules, so they're qualified.
+        if s.exported {
+            map.push_str(&format!("{:04x}  {}\n", s.address, s.name));
+        } else {
+            map.push_str(&format!("{:04x}  {}:{}\n", s.address, s.module, s.name));
+        }
+    }
+    map
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Dcpu;
+    use super::super::assembler::{assemble_object, AssemblerOptions};
+    use super::super::assembler::diagnostic::render_all;
+    use super::super::object::Relocation;
+
+    #[test]
+    fn link_two_modules() {
+        let main = object("main.dasm", "\
+.extern double
+.section text
+:start
+  set A, 21
+  jsr double
+  set [result], A
+:finish set PC, finish
+.section data
+:result dat 0
+");
+        let lib = object("math.dasm", "\
+.global double
+:double
+  shl A, 1
+  set PC, POP
+");
+        let image = link(&[main, lib], &LinkOptions::default()).ok().unwrap();
+        let double = image.exported_address("double").unwrap();
+        // `math.dasm`'s text comes right after `main.dasm`'s, and data comes last.
+        assert_eq!(double, 7);
+        assert_eq!(image.words.len(), 10);
+        assert!(image.map.contains("0007  double\n"));
+        assert!(image.map.contains("0009  main.dasm:result\n"));
+
+        let mut dcpu = Dcpu::new();
+        dcpu.load_program(&image.words);
+        while !dcpu.finished() {
+            dcpu.tick();
+        }
+        assert_eq!(dcpu.mem(9), 42);
+    }
+
+    #[test]
+    fn object_round_trip() {
+        let object = object("main.dasm", ".extern far\nset PC, far\n:here set A, here\n");
+        let bytes = object.to_bytes();
+        assert_eq!(ObjectFile::from_bytes(&bytes).ok().unwrap(), object);
+    }
+
+    #[test]
+    fn undefined_and_duplicate_symbols() {
+        let a = object("a.dasm", ".global f\n.extern g\n:f set PC, g\n");
+        let b = object("b.dasm", ".global f\n:f set PC, POP\n");
+        let errors = link(&[a, b], &LinkOptions::default()).err().unwrap();
+        assert_eq!(errors, vec![
+            String::from("Symbol \"f\" is exported by both a.dasm and b.dasm"),
+            String::from("Undefined symbol \"g\" imported by a.dasm"),
+        ]);
+    }
+
+    #[test]
+    fn malformed_objects() {
+        let good = object("a.dasm", ".extern g\n:f set PC, g\n.section data\ndat f\n");
+        let errors = |object: ObjectFile| {
+            link(&[object], &LinkOptions::default()).err().unwrap()
+        };
+
+        let mut bad = good.clone();
+        bad.symbols[0].section = 5;
+        assert_eq!(errors(bad), vec![
+            String::from("a.dasm: Symbol \"f\" is in section 5, which doesn't exist"),
+        ]);
+        let mut bad = good.clone();
+        bad.symbols[0].offset = 100;
+        assert_eq!(errors(bad), vec![
+            String::from("a.dasm: Symbol \"f\" is past the end of section \"text\""),
+        ]);
+        let mut bad = good.clone();
+        bad.sections[1].relocations[0].offset = 1;
+        assert_eq!(errors(bad), vec![
+            String::from("a.dasm: Relocation at 0x1 is past the end of section \"data\""),
+        ]);
+        let mut bad = good.clone();
+        bad.sections[1].relocations.push(Relocation {
+            offset: 0,
+            target: RelocationTarget::Section(2),
+        });
+        assert_eq!(errors(bad), vec![String::from(
+            "a.dasm: Relocation at 0x0 in section \"data\" refers to section 2, which \
+             doesn't exist")]);
+        let mut bad = good.clone();
+        bad.imports.clear();
+        assert_eq!(errors(bad.clone()), vec![String::from(
+            "a.dasm: Relocation at 0x1 in section \"text\" refers to \"g\", which isn't \
+             imported")]);
+
+        // Reading the object file back catches the same mistakes.
+        let error = ObjectFile::from_bytes(&bad.to_bytes()).err().unwrap();
+        assert!(error.ends_with("refers to \"g\", which isn't imported"));
+    }
+
+    fn object(file_name: &str, source: &str) -> ObjectFile {
+        let mut options = AssemblerOptions::default();
+        options.file_name = String::from(file_name);
+        let assembly = assemble_object(source, &options);
+        assert!(assembly.diagnostics.is_empty(), "{}",
+                render_all(&assembly.diagnostics, source));
+        assembly.object.unwrap()
+    }
+}
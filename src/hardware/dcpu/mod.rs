@@ -1,19 +1,35 @@
 pub mod assembler;
+pub mod compiler;
+pub mod device;
 pub mod instruction;
+pub mod linker;
+pub mod object;
 pub mod op;
 pub mod val_type;
 
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::fmt;
+use std::rc::Rc;
 
+use self::device::Device;
 use self::instruction::Instruction;
 
+/// How many cycles the DCPU runs a second.
+pub const CLOCK_SPEED: u64 = 100_000;
+
 const NUM_REGISTERS: u16 = 8;
 const RAM_SIZE: u32 = 0x10000;  // words
 const STACK_START: u16 = 0xffff;
+/// The most interrupts that can wait in the queue.  Any more, and the DCPU catches fire.
+const MAX_QUEUED_INTERRUPTS: usize = 256;
 //const WORD_SIZE: u16 = 16;  // bits
 //const MAX_NUM_DEVICES: u16 = 65535;
 
 pub mod register {
+    /// Names of the general-purpose registers, in encoding order.
+    pub const NAMES: &[&str] = &["A", "B", "C", "X", "Y", "Z", "I", "J"];
+
     #[derive(Debug)]
     pub enum Register {
         A = 0x0,
@@ -26,9 +42,10 @@ pub mod register {
         J = 0x7,
     }
 
+    /// Looks up a register by name, ignoring case.
     pub fn try_from(token: &str) -> Option<Register> {
         use self::Register::*;
-        match token {
+        match token.to_uppercase().as_str() {
             "A" => Some(A),
             "B" => Some(B),
             "C" => Some(C),
@@ -43,42 +60,107 @@ pub mod register {
 }
 
 pub mod literal {
-    pub fn try_from(token: &str) -> Option<u16> {
-        // TODO: Find a reasonable way to propagate up the commented-out error messages.
+    /// Whether `token` is meant to be a literal, as opposed to a label or register.  Any
+    /// token starting with a digit, a minus sign or a single quote is.
+    pub fn is_literal(token: &str) -> bool {
+        let token = if token.starts_with("-") { &token[1..] } else { token };
+        match token.chars().next() {
+            Some(c) => c.is_digit(10) || c == '\'',
+            None => false,
+        }
+    }
+
+    /// Parses a numeric or character literal.
+    ///
+    /// Numbers can be decimal, or `0x` hexadecimal, `0b` binary or `0o` octal, and can
+    /// be negated with a leading minus sign.  Characters are quoted like `'A'` or `'\n'`.
+    /// The value isn't truncated to a word, so callers can tell when it doesn't fit.
+    pub fn parse(token: &str) -> Result<i64, String> {
+        if token.starts_with("'") {
+            return parse_char(token);
+        }
 
-        // First try decimal.
-        let mut result = match u16::from_str_radix(token, 10) {
-            Ok(v) => Some(v),
-            Err(_) => None,
+        let (negative, digits) = if token.starts_with("-") {
+            (true, &token[1..])
+        } else {
+            (false, token)
         };
+        let (radix, digits) = if digits.starts_with("0x") {
+            (16, &digits[2..])
+        } else if digits.starts_with("0b") {
+            (2, &digits[2..])
+        } else if digits.starts_with("0o") {
+            (8, &digits[2..])
+        } else {
+            (10, digits)
+        };
+        // `from_str_radix` would also accept a sign here, which we don't want.
+        if digits.len() == 0 || !digits.chars().all(|c| c.is_digit(radix)) {
+            return Err(format!("Invalid literal \"{}\"", token));
+        }
+        match i64::from_str_radix(digits, radix) {
+            Ok(v) => Ok(if negative { -v } else { v }),
+            Err(_) => Err(format!("Literal \"{}\" is too large", token)),
+        }
+    }
 
-        // Then try hex if decimal fails.
-        if let None = result {
-            if token.len() > 2 {
-                // Strip off the "0x" prefix.
-                result = match u16::from_str_radix(&token[2..], 16) {
-                    Ok(v) => Some(v),
-                    Err(_) => None,
-                    //Err(_) => return Err(
-                    //    format!("Line {}: Failed to parse literal from \"{}\"",
-                    //            line_num, token))
-                };
-            }
+    fn parse_char(token: &str) -> Result<i64, String> {
+        if token.len() < 2 || !token.ends_with("'") {
+            return Err(format!("Unterminated character literal {}", token));
+        }
+        match unescape(&token[1..token.len()-1]) {
+            Ok(ref chars) if chars.len() == 1 => Ok(chars[0] as i64),
+            Ok(_) => Err(format!("Character literal {} must hold exactly one character",
+                                 token)),
+            Err((_, e)) => Err(e),
         }
+    }
 
-        match result {
-            Some(v) => {
-                if v <= u16::max_value() {
-                    Some(v)
-                } else {
-                    //Err(format!("Line {}: Literal \"{}\" too large", line_num, v))
-                    None
+    /// Decodes the contents of a quoted string or character literal into one word per
+    /// character.
+    ///
+    /// Supports `\n`, `\r`, `\t`, `\0`, `\\`, `\"`, `\'` and `\xNN` escapes.  Characters
+    /// outside of ASCII have to be written with `\xNN`.
+    ///
+    /// On failure, returns the byte offset of the offending character along with the
+    /// error.
+    pub fn unescape(s: &str) -> Result<Vec<u16>, (usize, String)> {
+        let mut result = Vec::new();
+        let mut chars = s.char_indices();
+        while let Some((i, c)) = chars.next() {
+            if c != '\\' {
+                if c as u32 > 0x7f {
+                    return Err((i, format!("Character '{}' isn't ASCII; use a \\x escape",
+                                           c)));
                 }
-            },
-            None => None,
-            //None => Err(format!("Line {}: Failed to parse literal from \"{}\"",
-            //                    line_num, token)),
+                result.push(c as u16);
+                continue;
+            }
+
+            let escaped = match chars.next() {
+                Some((_, e)) => e,
+                None => return Err((i, String::from("Unfinished escape sequence"))),
+            };
+            let value = match escaped {
+                'n' => 0x0a,
+                'r' => 0x0d,
+                't' => 0x09,
+                '0' => 0x00,
+                '\\' | '"' | '\'' => escaped as u16,
+                'x' => {
+                    let digits: String = chars.clone().take(2).map(|(_, d)| d).collect();
+                    if digits.len() != 2 || !digits.chars().all(|d| d.is_digit(16)) {
+                        return Err((i, String::from("Expected two hex digits after \\x")));
+                    }
+                    chars.next();
+                    chars.next();
+                    u16::from_str_radix(&digits, 16).unwrap()
+                },
+                _ => return Err((i, format!("Unknown escape sequence \\{}", escaped))),
+            };
+            result.push(value);
         }
+        Ok(result)
     }
 }
 
@@ -98,7 +180,17 @@ pub struct Dcpu {
     ex: u16,
     /// Interrupt Address
     ia: u16,
-    // TODO: Interrupt queue
+    /// Interrupts waiting to be triggered, oldest first.
+    interrupt_queue: VecDeque<u16>,
+    /// Whether interrupts wait in the queue instead of being triggered.  It's on while a
+    /// handler runs, until `RFI`, and `IAQ` sets it too.
+    queue_interrupts: bool,
+    /// Set when the interrupt queue overflows.  A burning DCPU does nothing.
+    on_fire: bool,
+    /// Cycles run since the DCPU was powered on, which is how devices keep time.
+    cycles: u64,
+    /// Attached hardware, in the order `HWN` and `HWQ` see it.
+    devices: Vec<Rc<RefCell<Device>>>,
 }
 
 impl Dcpu {
@@ -113,9 +205,29 @@ impl Dcpu {
             sp: STACK_START,
             ex: 0,
             ia: 0,
+            interrupt_queue: VecDeque::new(),
+            queue_interrupts: false,
+            on_fire: false,
+            cycles: 0,
+            devices: Vec::new(),
         }
     }
 
+    /// Plugs in a device, giving it the next free hardware index.
+    pub fn attach(&mut self, device: Rc<RefCell<Device>>) {
+        assert!(self.devices.len() < u16::max_value() as usize);
+        self.devices.push(device);
+    }
+
+    pub fn num_devices(&self) -> u16 {
+        self.devices.len() as u16
+    }
+
+    /// Returns the device at hardware index `i`, if there is one.
+    pub fn device(&self, i: u16) -> Option<Rc<RefCell<Device>>> {
+        self.devices.get(i as usize).cloned()
+    }
+
     /// Copies a program into the DCPU's memory.
     ///
     /// Programs are loaded starting from memory address 0x0.
@@ -123,26 +235,63 @@ impl Dcpu {
     /// # Arguments
     ///
     /// * `program` - A vector of words containing instructions
-    pub fn load_program(&mut self, program: &Vec<u16>) {
+    pub fn load_program(&mut self, program: &[u16]) {
         for (i, word) in program.iter().enumerate() {
             self.set_mem(i as u16, *word as u16);
         }
         self.set_pc(0);
     }
 
-    /// Runs a single cycle of the DCPU.
+    /// Sends the DCPU an interrupt with `message`, from software or hardware.
+    ///
+    /// Interrupts are thrown away if `IA` is 0.  Otherwise, they're queued, and the next
+    /// tick triggers the oldest one, unless interrupts are being queued.
+    pub fn interrupt(&mut self, message: u16) {
+        if self.ia == 0 {
+            return;
+        }
+        if self.interrupt_queue.len() == MAX_QUEUED_INTERRUPTS {
+            self.on_fire = true;
+            return;
+        }
+        self.interrupt_queue.push_back(message);
+    }
+
+    /// Runs a single instruction, then lets every device catch up to the DCPU's cycle
+    /// count.
     pub fn tick(&mut self) {
         use self::op::OpResult::*;
+        if self.on_fire {
+            return;
+        }
+        if !self.queue_interrupts {
+            if let Some(message) = self.interrupt_queue.pop_front() {
+                self.trigger_interrupt(message);
+                // A DCPU that's waiting in place can still be woken up by interrupts.
+                self.finished = false;
+            }
+        }
+        let curr_instr = Instruction::new(self.mem(self.pc()));
+        self.cycles += curr_instr.num_cycles() as u64;
+        // A finished DCPU keeps spending cycles on the instruction it's stuck on, but
+        // running it again wouldn't change anything.
         if !self.finished {
-            // TODO: Wait for C cycles before actually executing fetched instruction.
-            let curr_instr = Instruction::new(self.mem(self.pc()));
             self.incr_pc();
             match curr_instr.eval(self) {
                 NextInstr => (),
                 SkipNextInstr => {
-                    let curr_pc = self.pc();
-                    let instr_size = Instruction::new(self.mem(self.pc())).num_words();
-                    self.set_pc(curr_pc + instr_size);
+                    // Skipping a conditional skips the instruction after it too, so
+                    // chained conditionals act like an "and".  Each one skipped takes a
+                    // cycle.
+                    loop {
+                        let curr_pc = self.pc();
+                        let skipped = Instruction::new(self.mem(curr_pc));
+                        self.set_pc(curr_pc.wrapping_add(skipped.num_words()));
+                        self.cycles += 1;
+                        if !skipped.is_conditional() {
+                            break;
+                        }
+                    }
                 },
             }
 
@@ -152,6 +301,61 @@ impl Dcpu {
 
             self.last_pc = self.pc();
         }
+
+        for i in 0..self.devices.len() {
+            let device = self.devices[i].clone();
+            device.borrow_mut().tick(self);
+        }
+    }
+
+    /// Jumps to the interrupt handler at `IA`, with `message` in `A`.  `RFI` gets back.
+    fn trigger_interrupt(&mut self, message: u16) {
+        if self.ia == 0 {
+            return;
+        }
+        let (pc, a) = (self.pc(), self.reg(0));
+        self.push(pc);
+        self.push(a);
+        self.set_pc(self.ia);
+        self.set_reg(0, message);
+        self.queue_interrupts = true;
+    }
+
+    /// Returns from an interrupt handler, which is what `RFI` does.
+    pub fn return_from_interrupt(&mut self) {
+        self.queue_interrupts = false;
+        let a = self.pop();
+        self.set_reg(0, a);
+        let pc = self.pop();
+        self.set_pc(pc);
+    }
+
+    pub fn queue_interrupts(&self) -> bool {
+        self.queue_interrupts
+    }
+
+    pub fn set_queue_interrupts(&mut self, queue_interrupts: bool) {
+        self.queue_interrupts = queue_interrupts;
+    }
+
+    pub fn on_fire(&self) -> bool {
+        self.on_fire
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn push(&mut self, val: u16) {
+        self.decr_sp();
+        let sp = self.sp();
+        self.set_mem(sp, val);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let val = self.mem(self.sp());
+        self.incr_sp();
+        val
     }
 
     pub fn reg(&self, i: u16) -> u16 {
@@ -179,7 +383,7 @@ impl Dcpu {
     }
 
     pub fn incr_pc(&mut self) {
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
     }
 
     pub fn sp(&self) -> u16 {
@@ -191,11 +395,11 @@ impl Dcpu {
     }
 
     pub fn incr_sp(&mut self) {
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
     }
 
     pub fn decr_sp(&mut self) {
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
     }
 
     pub fn ex(&self) -> u16 {
@@ -245,6 +449,26 @@ impl fmt::Display for Dcpu {
     }
 }
 
+/// Assembles a program for a test, panicking with the rendered errors if it doesn't
+/// assemble.
+#[cfg(test)]
+pub fn assemble_for_test(program_src: &str) -> Vec<u16> {
+    match assembler::assemble(program_src) {
+        Ok(assembly) => assembly.words,
+        Err(errors) => {
+            panic!("\n{}", assembler::diagnostic::render_all(&errors, program_src))
+        }
+    }
+}
+
+/// A DCPU with `device` attached and the program loaded, for testing devices.
+#[cfg(test)]
+pub fn with_device(device: Rc<RefCell<Device>>, program_src: &str) -> Dcpu {
+    let mut dcpu = Dcpu::new();
+    dcpu.attach(device);
+    dcpu.load_program(&assemble_for_test(program_src));
+    dcpu
+}
 
 #[cfg(test)]
 mod tests {
@@ -290,20 +514,139 @@ set PC, loop
         run_program(program_src);
     }
 
+    #[test]
+    fn stack_operands() {
+        let program_src = "\
+set PUSH, 0x11
+set PUSH, 0x22
+set PUSH, 0x33
+set A, [SP]
+set B, [SP + 1]
+set C, PICK 2
+set [sp+2], 0x44
+set X, PEEK
+add PICK 1, 1
+:finish set PC, finish
+        ";
+        let dcpu = run_program(program_src);
+        assert_eq!((dcpu.reg(0), dcpu.reg(1), dcpu.reg(2), dcpu.reg(3)),
+                   (0x33, 0x22, 0x11, 0x33));
+        let sp = dcpu.sp();
+        assert_eq!((dcpu.mem(sp + 1), dcpu.mem(sp + 2)), (0x23, 0x44));
+    }
+
+    #[test]
+    fn overflow() {
+        let program_src = "\
+set A, 0xffff
+add A, 2
+set B, EX
+set C, 1
+sub C, 2
+set X, EX
+set EX, 0
+sbx Y, 1
+set Z, EX
+:finish set PC, finish
+        ";
+        let dcpu = run_program(program_src);
+        let regs: Vec<u16> = (0..6).map(|r| dcpu.reg(r)).collect();
+        assert_eq!(regs, vec![1, 1, 0xffff, 0xffff, 0xffff, 0xffff]);
+    }
+
+    #[test]
+    fn long_shifts() {
+        let program_src = "\
+set A, 0x8001
+shl A, 16
+set B, EX
+set C, 0x8000
+asr C, 20
+set X, 0x1234
+shr X, 16
+set Y, EX
+:finish set PC, finish
+        ";
+        let dcpu = run_program(program_src);
+        let regs: Vec<u16> = (0..5).map(|r| dcpu.reg(r)).collect();
+        assert_eq!(regs, vec![0, 0x8001, 0xffff, 0, 0x1234]);
+    }
+
+    #[test]
+    fn signed_extra() {
+        let program_src = "\
+set A, -2
+mli A, 3
+set B, EX
+set C, 5
+dvi C, -2
+set X, EX
+:finish set PC, finish
+        ";
+        let dcpu = run_program(program_src);
+        let regs: Vec<u16> = (0..4).map(|r| dcpu.reg(r)).collect();
+        assert_eq!(regs, vec![0xfffa, 0xffff, 0xfffe, 0x8000]);
+    }
+
+    #[test]
+    fn sp_and_ex_operands() {
+        let program_src = "\
+set SP, 0x1000
+set PUSH, 5
+set EX, 7
+set A, EX
+:finish set PC, finish
+        ";
+        let dcpu = run_program(program_src);
+        assert_eq!((dcpu.sp(), dcpu.mem(0x0fff), dcpu.reg(0)), (0x0fff, 5, 7));
+    }
+
+    #[test]
+    fn chained_conditionals() {
+        // A failed conditional skips every conditional chained after it, along with the
+        // instruction they guard.
+        let program_src = "\
+set A, 1
+ife A, 2
+ife A, 1
+set B, 1
+set C, 1
+ifn A, 2
+ife A, 1
+set X, 1
+:finish set PC, finish
+        ";
+        let dcpu = run_program(program_src);
+        assert_eq!((dcpu.reg(1), dcpu.reg(2), dcpu.reg(3)), (0, 1, 1));
+    }
+
+    #[test]
+    fn interrupts() {
+        let program_src = "\
+int 1 ; Dropped, since IA is 0.
+ias handler
+set A, 0x77
+iaq 1
+int 5
+int 6
+set X, Y ; The handlers haven't run yet.
+iaq 0
+:finish set PC, finish
+
+:handler
+mul Y, 0x10
+add Y, A
+rfi 0
+        ";
+        let dcpu = run_program(program_src);
+        assert_eq!((dcpu.reg(0), dcpu.reg(3), dcpu.reg(4)), (0x77, 0, 0x56));
+        assert_eq!(dcpu.sp(), STACK_START);
+        assert!(!dcpu.queue_interrupts());
+    }
+
     /// Assembles and runs the program from source and gives diagnostic information.
-    fn run_program(program_src: &str) {
-        let program = assembler::assemble(program_src);
-        if !program.is_ok() {
-            let errors = program.err().unwrap();
-            println!();
-            println!("[ERRORS]");
-            for error in errors {
-                println!("  {}", error);
-            }
-            println!();
-            panic!();
-        }
-        let program = program.unwrap();
+    fn run_program(program_src: &str) -> Dcpu {
+        let program = assemble_for_test(program_src);
         println!("[Words]");
         print_program_words(&program);
         println!();
@@ -323,6 +666,7 @@ set PC, loop
             dcpu.tick();
             println!("{}", dcpu);
         }
+        dcpu
     }
 
     fn print_instruction_components(instruction: u16) {
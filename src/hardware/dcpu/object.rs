@@ -0,0 +1,269 @@
+//! Relocatable object files, which the assembler produces and the linker combines into
+//! a single program.
+
+const MAGIC: &[u8] = b"DOBJ";
+const VERSION: u16 = 1;
+
+const TARGET_SECTION: u8 = 0;
+const TARGET_IMPORT: u8 = 1;
+
+
+/// A contiguous block of code or data, which the linker places as a unit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub words: Vec<u16>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// A word in a section that holds an address, so the linker has to adjust it once it
+/// knows where everything goes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Relocation {
+    /// Offset of the word within its section.
+    pub offset: u16,
+    pub target: RelocationTarget,
+}
+
+/// What a relocated word's address is relative to.  The final address gets added to
+/// whatever the word already holds.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RelocationTarget {
+    /// The start of a section in the same object file, by index.
+    Section(usize),
+    /// A symbol exported by another object file.
+    Import(String),
+}
+
+/// A label defined in an object file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectSymbol {
+    pub name: String,
+    /// Index of the section the label is in.
+    pub section: usize,
+    /// Offset of the label within its section.
+    pub offset: u16,
+    /// Whether other object files can refer to the label.
+    pub exported: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectFile {
+    /// Usually the name of the source file, so the linker can say where things came from.
+    pub name: String,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<ObjectSymbol>,
+    /// Symbols this object expects other object files to export.
+    pub imports: Vec<String>,
+}
+
+impl ObjectFile {
+    /// Serializes the object file, so it can be written to disk.
+    ///
+    /// Every number is little-endian, and every string is its length as a `u16`,
+    /// followed by its UTF-8 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer { bytes: Vec::new() };
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.u16(VERSION);
+        writer.string(&self.name);
+
+        writer.u16(self.sections.len() as u16);
+        for section in &self.sections {
+            writer.string(&section.name);
+            writer.u32(section.words.len() as u32);
+            for &word in &section.words {
+                writer.u16(word);
+            }
+            writer.u32(section.relocations.len() as u32);
+            for relocation in &section.relocations {
+                writer.u16(relocation.offset);
+                match relocation.target {
+                    RelocationTarget::Section(idx) => {
+                        writer.bytes.push(TARGET_SECTION);
+                        writer.u16(idx as u16);
+                    },
+                    RelocationTarget::Import(ref name) => {
+                        writer.bytes.push(TARGET_IMPORT);
+                        writer.string(name);
+                    },
+                }
+            }
+        }
+
+        writer.u32(self.symbols.len() as u32);
+        for symbol in &self.symbols {
+            writer.string(&symbol.name);
+            writer.u16(symbol.section as u16);
+            writer.u16(symbol.offset);
+            writer.bytes.push(symbol.exported as u8);
+        }
+
+        writer.u32(self.imports.len() as u32);
+        for import in &self.imports {
+            writer.string(import);
+        }
+        writer.bytes
+    }
+
+    /// Reads back an object file written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ObjectFile, String> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(String::from("Not a DCPU object file"));
+        }
+        let mut reader = Reader { bytes, pos: MAGIC.len() };
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(format!("Unsupported object file version {}", version));
+        }
+        let name = reader.string()?;
+
+        let num_sections = reader.u16()?;
+        let mut sections = Vec::new();
+        for _ in 0..num_sections {
+            let name = reader.string()?;
+            let num_words = reader.u32()?;
+            let mut words = Vec::new();
+            for _ in 0..num_words {
+                words.push(reader.u16()?);
+            }
+            let num_relocations = reader.u32()?;
+            let mut relocations = Vec::new();
+            for _ in 0..num_relocations {
+                let offset = reader.u16()?;
+                let target = match reader.u8()? {
+                    TARGET_SECTION => RelocationTarget::Section(reader.u16()? as usize),
+                    TARGET_IMPORT => RelocationTarget::Import(reader.string()?),
+                    kind => return Err(format!("Unknown relocation kind {}", kind)),
+                };
+                relocations.push(Relocation { offset, target });
+            }
+            sections.push(Section { name, words, relocations });
+        }
+
+        let num_symbols = reader.u32()?;
+        let mut symbols = Vec::new();
+        for _ in 0..num_symbols {
+            symbols.push(ObjectSymbol {
+                name: reader.string()?,
+                section: reader.u16()? as usize,
+                offset: reader.u16()?,
+                exported: reader.u8()? != 0,
+            });
+        }
+
+        let num_imports = reader.u32()?;
+        let mut imports = Vec::new();
+        for _ in 0..num_imports {
+            imports.push(reader.string()?);
+        }
+
+        let object = ObjectFile {
+            name,
+            sections,
+            symbols,
+            imports,
+        };
+        object.check()?;
+        Ok(object)
+    }
+
+    /// Makes sure every section index, offset and import refers to something in the
+    /// object file, so the linker can use them without checking.
+    pub fn check(&self) -> Result<(), String> {
+        for symbol in &self.symbols {
+            let section = match self.sections.get(symbol.section) {
+                Some(s) => s,
+                None => {
+                    return Err(format!("Symbol \"{}\" is in section {}, which doesn't exist",
+                                       symbol.name, symbol.section));
+                },
+            };
+            // A label can be right after the end of its section.
+            if symbol.offset as usize > section.words.len() {
+                return Err(format!("Symbol \"{}\" is past the end of section \"{}\"",
+                                   symbol.name, section.name));
+            }
+        }
+        for section in &self.sections {
+            for relocation in &section.relocations {
+                if relocation.offset as usize >= section.words.len() {
+                    return Err(format!("Relocation at {:#x} is past the end of section \"{}\"",
+                                       relocation.offset, section.name));
+                }
+                match relocation.target {
+                    RelocationTarget::Section(idx) if idx >= self.sections.len() => {
+                        return Err(format!("Relocation at {:#x} in section \"{}\" refers to \
+                                            section {}, which doesn't exist",
+                                           relocation.offset, section.name, idx));
+                    },
+                    RelocationTarget::Import(ref name) if !self.imports.contains(name) => {
+                        return Err(format!("Relocation at {:#x} in section \"{}\" refers to \
+                                            \"{}\", which isn't imported",
+                                           relocation.offset, section.name, name));
+                    },
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u16(&mut self, v: u16) {
+        self.bytes.push(v as u8);
+        self.bytes.push((v >> 8) as u8);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.u16(v as u16);
+        self.u16((v >> 16) as u16);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u16(s.len() as u16);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.pos + n > self.bytes.len() {
+            return Err(String::from("Object file ends unexpectedly"));
+        }
+        let result = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(result)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok((b[0] as u16) | ((b[1] as u16) << 8))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let lo = self.u16()? as u32;
+        let hi = self.u16()? as u32;
+        Ok(lo | (hi << 16))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| String::from("Invalid UTF-8 in string"))
+    }
+}
@@ -2,13 +2,23 @@ use std::fmt;
 
 use super::Dcpu;
 use super::val_type::ValKind;
-use super::val_type::ValKind::*;
 use super::register;
 
 use self::BasicOp::*;
 use self::SpecialOp::*;
 use self::OpResult::*;
 
+/// Mnemonics of every basic op, as accepted by `BasicOp::try_from`.
+pub const BASIC_OP_NAMES: &[&str] = &[
+    "set", "add", "sub", "mul", "mli", "div", "dvi", "mod", "mdi", "and", "bor", "xor",
+    "shr", "asr", "shl", "ifb", "ifc", "ife", "ifn", "ifg", "ifa", "ifl", "ifu", "adx",
+    "sbx", "sti", "std",
+];
+
+/// Mnemonics of every special op, as accepted by `SpecialOp::try_from`.
+pub const SPECIAL_OP_NAMES: &[&str] = &[
+    "jsr", "int", "iag", "ias", "rfi", "iaq", "hwn", "hwq", "hwi",
+];
 
 /// Tells whether to skip the next instruction or not.
 pub enum OpResult {
@@ -98,18 +108,8 @@ impl BasicOp {
     pub fn eval(&self, b_kind: ValKind, a_kind: ValKind, dcpu: &mut Dcpu) -> OpResult {
         // Once ValKind's have been extracted, evaluation order of `a` and `b` doesn't
         // matter, and they can be evaluated multiple times.
-        let a_val = match a_kind {
-            Literal(v) => v,
-            Register(v) => dcpu.reg(v),
-            ProgramCounter => dcpu.pc(),
-            Deref(v) => dcpu.mem(v),
-        };
-        let b_val = match b_kind {
-            Literal(v) => v,
-            Register(v) => dcpu.reg(v),
-            ProgramCounter => dcpu.pc(),
-            Deref(v) => dcpu.mem(v),
-        };
+        let a_val = self::get(&a_kind, dcpu);
+        let b_val = self::get(&b_kind, dcpu);
 
         match *self {
             SET => {
@@ -117,7 +117,7 @@ impl BasicOp {
                 NextInstr
             },
             ADD => {
-                let result = b_val + a_val;
+                let result = b_val.wrapping_add(a_val);
                 let overflow_check = b_val as u32 + a_val as u32;
                 if (result as u32) < overflow_check {
                     // There was an overflow.
@@ -129,9 +129,8 @@ impl BasicOp {
                 NextInstr
             },
             SUB => {
-                let result = b_val - a_val;
-                let underflow_check = b_val as u32 - a_val as u32;
-                if (result as u32) < underflow_check {
+                let result = b_val.wrapping_sub(a_val);
+                if a_val > b_val {
                     // There was an underflow.
                     dcpu.set_ex(0xffff);
                 } else {
@@ -141,15 +140,16 @@ impl BasicOp {
                 NextInstr
             },
             MUL => {
-                let result = b_val * a_val;
+                let result = b_val.wrapping_mul(a_val);
                 let ex_val = (((b_val as u32 * a_val as u32) >> 16) & 0xffff) as u16;
                 dcpu.set_ex(ex_val);
                 self::set(b_kind, result, dcpu);
                 NextInstr
             },
             MLI => {
-                let result = (b_val as i16 * a_val as i16) as u16;
-                let ex_val = (((b_val as i32 * a_val as i32) >> 16) & 0xffff) as u16;
+                let result = (b_val as i16).wrapping_mul(a_val as i16) as u16;
+                let product = b_val as i16 as i32 * a_val as i16 as i32;
+                let ex_val = ((product >> 16) & 0xffff) as u16;
                 dcpu.set_ex(ex_val);
                 self::set(b_kind, result, dcpu);
                 NextInstr
@@ -172,8 +172,9 @@ impl BasicOp {
                     dcpu.set_ex(0x0);
                     self::set(b_kind, 0, dcpu);
                 } else {
-                    let result = (b_val as i16 / a_val as i16) as u16;
-                    let ex_val = (((b_val as i32) << 16) / a_val as i32) & 0xffff;
+                    let result = (b_val as i16).wrapping_div(a_val as i16) as u16;
+                    let ex_val = ((b_val as i16 as i32) << 16)
+                        .wrapping_div(a_val as i16 as i32) & 0xffff;
                     let ex_val = ex_val as u16;
                     dcpu.set_ex(ex_val);
                     self::set(b_kind, result, dcpu);
@@ -192,7 +193,8 @@ impl BasicOp {
                 if a_val == 0 {
                     self::set(b_kind, 0, dcpu);
                 } else {
-                    self::set(b_kind, ((b_val as i16) % (a_val as i16)) as u16, dcpu);
+                    let result = (b_val as i16).wrapping_rem(a_val as i16) as u16;
+                    self::set(b_kind, result, dcpu);
                 }
                 NextInstr
             },
@@ -209,22 +211,26 @@ impl BasicOp {
                 NextInstr
             },
             SHR => {
-                let result = b_val >> a_val;
-                let ex_val = ((((b_val as u32) << 16) >> (a_val as u32)) & 0xffff) as u16;
+                let result = b_val.checked_shr(a_val as u32).unwrap_or(0);
+                let shifted = ((b_val as u32) << 16).checked_shr(a_val as u32).unwrap_or(0);
+                let ex_val = (shifted & 0xffff) as u16;
                 dcpu.set_ex(ex_val);
                 self::set(b_kind, result, dcpu);
                 NextInstr
             },
             ASR => {
-                let result = ((b_val as i16) >> (a_val as i16)) as u16;
-                let ex_val = ((((b_val as u32) << 16) >> (a_val as u32)) & 0xffff) as u16;
+                // Shifting by 16 or more fills the result with the sign bit.
+                let result = ((b_val as i16) >> (a_val.min(15) as i16)) as u16;
+                let shifted = ((b_val as u32) << 16).checked_shr(a_val as u32).unwrap_or(0);
+                let ex_val = (shifted & 0xffff) as u16;
                 dcpu.set_ex(ex_val);
                 self::set(b_kind, result, dcpu);
                 NextInstr
             },
             SHL => {
-                let result = b_val << a_val;
-                let ex_val = ((((b_val as u32) << (a_val as u32)) >> 16) & 0xffff) as u16;
+                let result = b_val.checked_shl(a_val as u32).unwrap_or(0);
+                let shifted = (b_val as u64) << (a_val.min(32) as u64);
+                let ex_val = ((shifted >> 16) & 0xffff) as u16;
                 dcpu.set_ex(ex_val);
                 self::set(b_kind, result, dcpu);
                 NextInstr
@@ -287,7 +293,7 @@ impl BasicOp {
             },
             ADX => {
                 let ex = dcpu.ex();
-                let result = b_val + a_val + ex;
+                let result = b_val.wrapping_add(a_val).wrapping_add(ex);
                 let overflow_check = b_val as u32 + a_val as u32 + ex as u32;
                 if (result as u32) < overflow_check {
                     // There was an overflow.
@@ -300,9 +306,8 @@ impl BasicOp {
             },
             SBX => {
                 let ex = dcpu.ex();
-                let result = b_val - a_val + ex;
-                let underflow_check = b_val as u32 - a_val as u32 + ex as u32;
-                if (result as u32) < underflow_check {
+                let result = b_val.wrapping_sub(a_val).wrapping_add(ex);
+                if (b_val as u32 + ex as u32) < a_val as u32 {
                     // There was an underflow.
                     dcpu.set_ex(0xffff);
                 } else {
@@ -314,17 +319,17 @@ impl BasicOp {
             STI => {
                 self::set(b_kind, a_val, dcpu);
                 let i_reg = dcpu.reg(register::Register::I as u16);
-                dcpu.set_reg(register::Register::I as u16, i_reg + 1);
+                dcpu.set_reg(register::Register::I as u16, i_reg.wrapping_add(1));
                 let j_reg = dcpu.reg(register::Register::J as u16);
-                dcpu.set_reg(register::Register::J as u16, j_reg + 1);
+                dcpu.set_reg(register::Register::J as u16, j_reg.wrapping_add(1));
                 NextInstr
             },
             STD => {
                 self::set(b_kind, a_val, dcpu);
                 let i_reg = dcpu.reg(register::Register::I as u16);
-                dcpu.set_reg(register::Register::I as u16, i_reg - 1);
+                dcpu.set_reg(register::Register::I as u16, i_reg.wrapping_sub(1));
                 let j_reg = dcpu.reg(register::Register::J as u16);
-                dcpu.set_reg(register::Register::J as u16, j_reg - 1);
+                dcpu.set_reg(register::Register::J as u16, j_reg.wrapping_sub(1));
                 NextInstr
             },
         }
@@ -396,8 +401,17 @@ impl BasicOp {
         }
     }
 
+    /// Conditional ops only read `b`, while every other op writes its result to `b`.
+    pub fn is_conditional(&self) -> bool {
+        match *self {
+            IFB | IFC | IFE | IFN | IFG | IFA | IFL | IFU => true,
+            _ => false,
+        }
+    }
+
+    /// Looks up an op by its mnemonic, ignoring case.
     pub fn try_from(op_name: &str) -> Option<BasicOp> {
-        match op_name {
+        match op_name.to_lowercase().as_str() {
             "set" => Some(SET),
             "add" => Some(ADD),
             "sub" => Some(SUB),
@@ -449,14 +463,8 @@ impl SpecialOp {
     pub fn eval(&self, a_kind: ValKind, dcpu: &mut Dcpu) -> OpResult {
         use self::SpecialOp::*;
         use self::OpResult::*;
-        use super::val_type::ValKind::*;
 
-        let a_val = match a_kind {
-            Literal(v) => v,
-            Register(v) => dcpu.reg(v),
-            ProgramCounter => dcpu.pc(),
-            Deref(v) => dcpu.mem(v),
-        };
+        let a_val = self::get(&a_kind, dcpu);
 
         match *self {
             JSR => {
@@ -472,7 +480,7 @@ impl SpecialOp {
                 NextInstr
             },
             INT => {
-                // TODO
+                dcpu.interrupt(a_val);
                 NextInstr
             },
             IAG => {
@@ -484,24 +492,36 @@ impl SpecialOp {
                 NextInstr
             },
             RFI => {
-                // TODO
+                dcpu.return_from_interrupt();
                 NextInstr
             },
             IAQ => {
-                // TODO
+                dcpu.set_queue_interrupts(a_val != 0);
                 NextInstr
             },
             HWN => {
-                // TODO
-                self::set(a_kind, 0, dcpu);
+                let num_devices = dcpu.num_devices();
+                self::set(a_kind, num_devices, dcpu);
                 NextInstr
             },
             HWQ => {
-                // TODO
+                // Asking about a device that isn't there leaves the registers alone.
+                if let Some(device) = dcpu.device(a_val) {
+                    use super::register::Register;
+                    let device = device.borrow();
+                    let (id, manufacturer) = (device.id(), device.manufacturer());
+                    dcpu.set_reg(Register::A as u16, id as u16);
+                    dcpu.set_reg(Register::B as u16, (id >> 16) as u16);
+                    dcpu.set_reg(Register::C as u16, device.version());
+                    dcpu.set_reg(Register::X as u16, manufacturer as u16);
+                    dcpu.set_reg(Register::Y as u16, (manufacturer >> 16) as u16);
+                }
                 NextInstr
             },
             HWI => {
-                // TODO
+                if let Some(device) = dcpu.device(a_val) {
+                    device.borrow_mut().interrupt(dcpu);
+                }
                 NextInstr
             },
         }
@@ -535,8 +555,9 @@ impl SpecialOp {
         }
     }
 
+    /// Looks up an op by its mnemonic, ignoring case.
     pub fn try_from(op_name: &str) -> Option<SpecialOp> {
-        match op_name {
+        match op_name.to_lowercase().as_str() {
             "jsr" => Some(JSR),
             "int" => Some(INT),
             "iag" => Some(IAG),
@@ -552,6 +573,18 @@ impl SpecialOp {
 }
 
 
+fn get(kind: &ValKind, dcpu: &Dcpu) -> u16 {
+    use self::ValKind::*;
+    match *kind {
+        Literal(v) => v,
+        Register(v) => dcpu.reg(v),
+        ProgramCounter => dcpu.pc(),
+        StackPointer => dcpu.sp(),
+        Extra => dcpu.ex(),
+        Deref(v) => dcpu.mem(v),
+    }
+}
+
 fn set(lhs: ValKind, rhs: u16, dcpu: &mut Dcpu) {
     use self::ValKind::*;
     match lhs {
@@ -559,6 +592,8 @@ fn set(lhs: ValKind, rhs: u16, dcpu: &mut Dcpu) {
         Literal(_) => (),
         Register(v) => dcpu.set_reg(v, rhs),
         ProgramCounter => dcpu.set_pc(rhs),
+        StackPointer => dcpu.set_sp(rhs),
+        Extra => dcpu.set_ex(rhs),
         Deref(v) => dcpu.set_mem(v, rhs),
     }
 }
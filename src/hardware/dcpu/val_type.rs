@@ -26,6 +26,8 @@ pub enum ValKind {
     Literal(u16),
     Register(u16),
     ProgramCounter,
+    StackPointer,
+    Extra,
     Deref(u16),
 }
 
@@ -75,7 +77,7 @@ impl ValType {
             Register(r) => ValKind::Register(r),
             RegisterDeref(r) => ValKind::Deref(dcpu.reg(r)),
             RegisterNextWordDeref(r) => {
-                let mem_index = dcpu.reg(r) + dcpu.mem(dcpu.pc());
+                let mem_index = dcpu.reg(r).wrapping_add(dcpu.mem(dcpu.pc()));
                 dcpu.incr_pc();
                 ValKind::Deref(mem_index)
             },
@@ -90,13 +92,13 @@ impl ValType {
             },
             Peek => ValKind::Deref(dcpu.sp()),
             Pick => {
-                let mem_index = dcpu.sp() + dcpu.mem(dcpu.pc());
+                let mem_index = dcpu.sp().wrapping_add(dcpu.mem(dcpu.pc()));
                 dcpu.incr_pc();
                 ValKind::Deref(mem_index)
             },
-            StackPointer => ValKind::Literal(dcpu.sp()),
+            StackPointer => ValKind::StackPointer,
             ProgramCounter => ValKind::ProgramCounter,
-            Extra => ValKind::Literal(dcpu.ex()),
+            Extra => ValKind::Extra,
             NextWordDeref => {
                 let mem_index = dcpu.mem(dcpu.pc());
                 dcpu.incr_pc();
@@ -132,7 +134,7 @@ impl ValType {
     /// How many words does this value type extend beyond the first word.
     pub fn num_words(&self) -> u16 {
         match *self {
-            RegisterNextWordDeref(_) | NextWordDeref | NextWord => 1,
+            RegisterNextWordDeref(_) | Pick | NextWordDeref | NextWord => 1,
             _ => 0,
         }
     }
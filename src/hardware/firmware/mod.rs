@@ -0,0 +1,212 @@
+//! Firmware images that ship with the game, written in DCPU assembly and built with the
+//! assembler in `hardware::dcpu::assembler`.
+//!
+//! # Forth
+//!
+//! `FORTH` is the standard image for terminals.  It finds a LEM1802 and a Generic
+//! Keyboard, reads lines from the keyboard, echoes them to the screen, and interprets
+//! them as Forth, printing "ok" after every line that runs without a problem.
+//!
+//! ```text
+//! : square dup * ; ok
+//! variable total ok
+//! 7 square total ! total ? 49 ok
+//! : countdown 0 swap do i . -1 +loop ; ok
+//! 3 countdown 3 2 1 0 ok
+//! ```
+//!
+//! Every cell is a word, and numbers are signed, except where a word says otherwise
+//! (like `u.` and `u<`).  Names are looked up ignoring case.  `words` lists the whole
+//! dictionary.  It has:
+//!
+//! * Stack: `dup drop swap over rot nip tuck ?dup 2dup 2drop pick depth >r r> r@`
+//! * Arithmetic: `+ - * / mod /mod negate abs min max 1+ 1- 2* 2/ and or xor invert
+//!   lshift rshift`
+//! * Comparisons: `= <> < > u< 0= 0< 0>`
+//! * Memory: `@ ! +! c@ c! cells cell+ here , allot ?`
+//! * Input and output: `emit key cr space spaces type . u. .s page words ." ( \`
+//! * Defining words: `: ; immediate [ ] literal ' execute recurse variable constant
+//!   create`
+//! * Control structures: `if else then begin until again while repeat do loop +loop i
+//!   j unloop exit`
+//! * Variables: `base state`, and `hex` and `decimal` to switch bases.
+//!
+//! Mistakes, like an unknown word or an empty stack, print a message, then throw away
+//! the stack and anything that was being compiled.
+//!
+//! It uses memory like so:
+//!
+//! | Address           | Holds                                         |
+//! |-------------------|-----------------------------------------------|
+//! | `0x0000 ..`       | The firmware, then new definitions            |
+//! | `.. 0x7f00`       | The return stack, growing down                |
+//! | `0x7f00 .. 0x7f50`| The line being interpreted                    |
+//! | `0x8000 .. 0x8180`| The screen                                    |
+//! | `.. 0xff00`       | The data stack, growing down                  |
+
+use super::dcpu::assembler;
+use super::dcpu::assembler::AssemblerOptions;
+use super::dcpu::assembler::program::AssembledProgram;
+
+
+/// Expands to the words of a firmware image that `build.rs` assembled, as a
+/// `&'static [u16]`, given the name of its source file without the `.dasm`.
+macro_rules! firmware_image {
+    ($name:tt) => {
+        include!(concat!(env!("OUT_DIR"), "/firmware/", $name, ".rs"))
+    };
+}
+
+
+/// The Forth image for terminals.
+pub static FORTH: &[u16] = firmware_image!("forth");
+
+/// The source of the Forth image.
+pub const FORTH_SOURCE: &str = include_str!("forth.dasm");
+
+/// Assembles the Forth image, along with its symbols and the rest of its debug info.
+pub fn forth() -> AssembledProgram {
+    assemble_firmware("forth.dasm", FORTH_SOURCE)
+}
+
+/// Assembles firmware the same way `build.rs` does.  It's optimized, since every word it
+/// saves is one more for programs to use.
+fn assemble_firmware(file_name: &str, source: &str) -> AssembledProgram {
+    let options = AssemblerOptions {
+        file_name: String::from(file_name),
+        optimize: true,
+        ..AssemblerOptions::default()
+    };
+    let assembly = assembler::assemble_with_options(source, &options);
+    match assembly.program {
+        Some(program) => program,
+        None => {
+            panic!("Firmware {} didn't assemble:\n{}", file_name,
+                   assembler::diagnostic::render_all(&assembly.diagnostics, source))
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use super::super::dcpu::Dcpu;
+    use super::super::keyboard::generic_keyboard::{self, GenericKeyboard};
+    use super::super::lem::lem1802::{Lem1802, SCREEN_SIZE_IN_CELLS};
+
+    /// A DCPU running the Forth image, with a LEM1802 and a Generic Keyboard.
+    struct Terminal {
+        dcpu: Dcpu,
+        lem: Rc<RefCell<Lem1802>>,
+        keyboard: Rc<RefCell<GenericKeyboard>>,
+        /// Where the firmware waits for keys.
+        idle_pc: u16,
+    }
+
+    impl Terminal {
+        fn new() -> Terminal {
+            let program = forth();
+            let mut terminal = Terminal {
+                dcpu: Dcpu::new(),
+                lem: Rc::new(RefCell::new(Lem1802::new())),
+                keyboard: Rc::new(RefCell::new(GenericKeyboard::new())),
+                idle_pc: program.symbol("read_key_wait").unwrap().address,
+            };
+            terminal.dcpu.attach(terminal.keyboard.clone());
+            terminal.dcpu.attach(terminal.lem.clone());
+            terminal.dcpu.load_program(&program.words);
+            terminal.run();
+            terminal
+        }
+
+        /// Runs until the firmware is waiting on an empty keyboard buffer.
+        fn run(&mut self) {
+            let mut ticks = 0;
+            while self.dcpu.pc() != self.idle_pc ||
+                self.keyboard.borrow().has_typed_keys() {
+                self.dcpu.tick();
+                ticks += 1;
+                assert!(ticks < 1_000_000, "Firmware never waited for a key");
+            }
+        }
+
+        /// Types `line`, then presses return.
+        fn type_line(&mut self, line: &str) {
+            let keys = line.chars().map(|c| c as u16)
+                .chain(Some(generic_keyboard::RETURN));
+            for key in keys {
+                self.keyboard.borrow_mut().type_key(key, &mut self.dcpu);
+                self.run();
+            }
+        }
+
+        /// Returns the text on each row of the screen, with blank cells as spaces.
+        fn rows(&self) -> Vec<String> {
+            let lem = self.lem.borrow();
+            assert!(lem.screen_mapped());
+            let (width, height) = SCREEN_SIZE_IN_CELLS;
+            (0..height).map(|row| {
+                (0..width).map(|column| {
+                    match lem.cell(&self.dcpu, row * width + column) & 0x7f {
+                        0 => ' ',
+                        c => c as u8 as char,
+                    }
+                }).collect()
+            }).collect()
+        }
+
+        /// Types `line`, and returns what it printed after it, which can wrap onto the
+        /// following rows.
+        fn eval(&mut self, line: &str) -> String {
+            self.type_line(line);
+            let rows = self.rows();
+            let row = rows.iter().rposition(|r| r.starts_with(line)).unwrap();
+            String::from(rows[row..].concat()[line.len()..].trim())
+        }
+    }
+
+    #[test]
+    fn built_at_compile_time() {
+        assert_eq!(FORTH, &forth().words[..]);
+    }
+
+    #[test]
+    fn repl() {
+        let mut terminal = Terminal::new();
+        assert_eq!(terminal.rows()[0].trim_end(), "DCPU-16 Forth");
+        assert_eq!(terminal.eval("2 3 + . -7 2 / ."), "5 -3 ok");
+        assert_eq!(terminal.eval("hex FF decimal . 10 3 /mod . ."), "255 3 1 ok");
+        assert_eq!(terminal.eval(": sq dup * ; 7 SQ ."), "49 ok");
+        assert_eq!(terminal.eval("variable v 5 v ! 3 v +! v ?"), "8 ok");
+        assert_eq!(terminal.eval("12 constant dozen dozen 2 * ."), "24 ok");
+        assert_eq!(terminal.eval("1 2 3 .s depth . 2drop drop"), "1 2 3 3 ok");
+        assert_eq!(terminal.eval("bogus"), "bogus ?");
+        assert_eq!(terminal.eval("drop"), "stack underflow");
+        // The stack is cleared after mistakes.
+        assert_eq!(terminal.eval("depth ."), "0 ok");
+    }
+
+    #[test]
+    fn control_structures() {
+        let mut terminal = Terminal::new();
+        terminal.type_line(": sign dup 0< if drop -1 else 0> if 1 else 0 then then ;");
+        assert_eq!(terminal.eval("-5 sign . 0 sign . 9 sign ."), "-1 0 1 ok");
+        terminal.type_line(": fact dup 1 > if dup 1- recurse * then ;");
+        assert_eq!(terminal.eval("7 fact ."), "5040 ok");
+        terminal.type_line(": stars 0 do 42 emit loop ;");
+        assert_eq!(terminal.eval("5 stars"), "*****ok");
+        terminal.type_line(": down 0 swap do i . -2 +loop ;");
+        assert_eq!(terminal.eval("6 down"), "6 4 2 0 ok");
+        terminal.type_line(": log2 0 swap begin 2/ dup while swap 1+ swap repeat drop ;");
+        assert_eq!(terminal.eval("1024 log2 ."), "10 ok");
+        terminal.type_line(": greet .\" hi \" ; \\ A comment");
+        assert_eq!(terminal.eval("greet ( another ) .\" there \""), "hi there ok");
+        // Half-finished definitions are thrown away.
+        assert_eq!(terminal.eval(": broken 1 nonsense ;"), "nonsense ?");
+        assert_eq!(terminal.eval("broken"), "broken ?");
+    }
+}
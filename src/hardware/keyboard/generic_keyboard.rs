@@ -0,0 +1,189 @@
+//! The Generic Keyboard, which buffers typed keys and keeps track of which keys are
+//! held down.
+//!
+//! Interrupts, picked by `A`:
+//!
+//! * 0 - Clears the buffer.
+//! * 1 - Takes the next key out of the buffer and puts it in `C`, or 0 if it's empty.
+//! * 2 - Sets `C` to 1 if the key `B` is held down, or 0 if it isn't.
+//! * 3 - Turns on interrupts with message `B`, which are sent whenever a key goes down or
+//!   up, or turns them off if `B` is 0.
+//!
+//! Keys are ASCII from 0x20 to 0x7f, along with the codes below.  Typing a key and
+//! holding it down are separate, since what's typed depends on the keyboard's layout and
+//! modifiers, and keys can repeat while they're held.
+
+use std::collections::{HashSet, VecDeque};
+
+use hardware::dcpu::Dcpu;
+use hardware::dcpu::device::Device;
+use hardware::dcpu::register::Register;
+
+pub const TAB: u16 = 0x09;
+pub const ESCAPE: u16 = 0x1b;
+pub const BACKSPACE: u16 = 0x10;
+pub const RETURN: u16 = 0x11;
+pub const INSERT: u16 = 0x12;
+pub const DELETE: u16 = 0x13;
+pub const ARROW_UP: u16 = 0x80;
+pub const ARROW_DOWN: u16 = 0x81;
+pub const ARROW_LEFT: u16 = 0x82;
+pub const ARROW_RIGHT: u16 = 0x83;
+pub const SHIFT: u16 = 0x90;
+pub const CONTROL: u16 = 0x91;
+
+const ID: u32 = 0x30cf7406;
+const VERSION: u16 = 1;
+const MANUFACTURER: u32 = 0;
+
+const CLEAR_BUFFER: u16 = 0;
+const GET_NEXT_KEY: u16 = 1;
+const CHECK_KEY: u16 = 2;
+const SET_INTERRUPT_MESSAGE: u16 = 3;
+
+/// The most keys the buffer holds.  Keys typed while it's full are lost.
+const BUFFER_CAPACITY: usize = 16;
+
+
+pub struct GenericKeyboard {
+    /// Typed keys, oldest first.
+    buffer: VecDeque<u16>,
+    held: HashSet<u16>,
+    /// Sent with every interrupt, or 0 if interrupts are off.
+    interrupt_message: u16,
+}
+
+impl GenericKeyboard {
+    pub fn new() -> GenericKeyboard {
+        GenericKeyboard {
+            buffer: VecDeque::new(),
+            held: HashSet::new(),
+            interrupt_message: 0,
+        }
+    }
+
+    /// Adds `key` to the buffer.
+    pub fn type_key(&mut self, key: u16, dcpu: &mut Dcpu) {
+        if self.buffer.len() < BUFFER_CAPACITY {
+            self.buffer.push_back(key);
+        }
+        self.send_interrupt(dcpu);
+    }
+
+    /// Holds `key` down, if it isn't already.
+    pub fn press(&mut self, key: u16, dcpu: &mut Dcpu) {
+        if self.held.insert(key) {
+            self.send_interrupt(dcpu);
+        }
+    }
+
+    /// Lets go of `key`, if it's held down.
+    pub fn release(&mut self, key: u16, dcpu: &mut Dcpu) {
+        if self.held.remove(&key) {
+            self.send_interrupt(dcpu);
+        }
+    }
+
+    /// Whether there are typed keys that haven't been taken out of the buffer.
+    pub fn has_typed_keys(&self) -> bool {
+        self.buffer.len() > 0
+    }
+
+    fn send_interrupt(&self, dcpu: &mut Dcpu) {
+        if self.interrupt_message != 0 {
+            dcpu.interrupt(self.interrupt_message);
+        }
+    }
+}
+
+impl Device for GenericKeyboard {
+    fn id(&self) -> u32 {
+        ID
+    }
+
+    fn version(&self) -> u16 {
+        VERSION
+    }
+
+    fn manufacturer(&self) -> u32 {
+        MANUFACTURER
+    }
+
+    fn interrupt(&mut self, dcpu: &mut Dcpu) {
+        let b = dcpu.reg(Register::B as u16);
+        match dcpu.reg(Register::A as u16) {
+            CLEAR_BUFFER => self.buffer.clear(),
+            GET_NEXT_KEY => {
+                let key = self.buffer.pop_front().unwrap_or(0);
+                dcpu.set_reg(Register::C as u16, key);
+            },
+            CHECK_KEY => {
+                let held = self.held.contains(&b);
+                dcpu.set_reg(Register::C as u16, held as u16);
+            },
+            SET_INTERRUPT_MESSAGE => self.interrupt_message = b,
+            _ => (),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use hardware::dcpu::with_device;
+
+    #[test]
+    fn interrupts() {
+        let keyboard = Rc::new(RefCell::new(GenericKeyboard::new()));
+        // Counts keyboard interrupts in X, and records the first two typed keys and
+        // whether shift is held in [0x1000..0x1003].
+        let mut dcpu = with_device(keyboard.clone(), "\
+ias on_key
+set A, 3
+set B, 0x42
+hwi 0
+:wait set PC, wait
+
+:on_key
+add X, 1
+set A, 1
+hwi 0
+ifn C, 0
+  set [0x1000+Y], C
+ifn C, 0
+  add Y, 1
+set A, 2
+set B, 0x90
+hwi 0
+set [0x1002], C
+rfi 0
+");
+        let run = |dcpu: &mut Dcpu| {
+            for _ in 0..100 {
+                dcpu.tick();
+            }
+        };
+        run(&mut dcpu);
+
+        keyboard.borrow_mut().press(SHIFT, &mut dcpu);
+        keyboard.borrow_mut().press(SHIFT, &mut dcpu);
+        run(&mut dcpu);
+        assert_eq!(dcpu.mem(0x1002), 1);
+        keyboard.borrow_mut().press('a' as u16, &mut dcpu);
+        keyboard.borrow_mut().type_key('A' as u16, &mut dcpu);
+        keyboard.borrow_mut().release('a' as u16, &mut dcpu);
+        keyboard.borrow_mut().release(SHIFT, &mut dcpu);
+        keyboard.borrow_mut().type_key(RETURN, &mut dcpu);
+        run(&mut dcpu);
+
+        // Holding shift again doesn't count.
+        assert_eq!(dcpu.reg(Register::X as u16), 6);
+        assert_eq!((dcpu.mem(0x1000), dcpu.mem(0x1001)), ('A' as u16, RETURN));
+        assert_eq!(dcpu.mem(0x1002), 0);
+        assert!(!keyboard.borrow().has_typed_keys());
+    }
+}
@@ -1,125 +1,54 @@
+pub mod generic_keyboard;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
 use glutin::VirtualKeyCode;
 use glutin::VirtualKeyCode::*;
 
-use game::event_handler::EventHandler;
+use game::event_handler::KeyEvent;
 use hardware::dcpu::Dcpu;
 
+use self::generic_keyboard::GenericKeyboard;
 
-const MAPPING_LOCATION: u16 = 0x9000;
-const HEAD_LOCATION: u16 = MAPPING_LOCATION;
-const SIZE_LOCATION: u16 = MAPPING_LOCATION + 1;
-const DATA_LOCATION: u16 = MAPPING_LOCATION + 2;
-const BUFFER_CAPACITY: u16 = 16;
-
-const PRINTABLE_CHAR_OFFSET: u16 = 0x20;
-// Defines the ordering of ASCII characters, starting at the first printable character.
-const KEY_CODE_MAPPINGS: [(bool, VirtualKeyCode); 95] = [
-    (false, Space),
-    (true, Key1),
-    (true, Apostrophe),
-    (true, Key3),
-    (true, Key4),
-    (true, Key5),
-    (true, Key7),
-    (false, Apostrophe),
-    (true, Key9),
-    (true, Key0),
-    (true, Key8),
-    (true, Equals),
-    (false, Comma),
-    (false, Subtract),
-    (false, Period),
-    (false, Slash),
-    (false, Key0),
-    (false, Key1),
-    (false, Key2),
-    (false, Key3),
-    (false, Key4),
-    (false, Key5),
-    (false, Key6),
-    (false, Key7),
-    (false, Key8),
-    (false, Key9),
-    (true, Semicolon),
-    (false, Semicolon),
-    (true, Comma),
-    (false, Equals),
-    (true, Period),
-    (true, Slash),
-    (true, Key2),
-    (true, A),
-    (true, B),
-    (true, C),
-    (true, D),
-    (true, E),
-    (true, F),
-    (true, G),
-    (true, H),
-    (true, I),
-    (true, J),
-    (true, K),
-    (true, L),
-    (true, M),
-    (true, N),
-    (true, O),
-    (true, P),
-    (true, Q),
-    (true, R),
-    (true, S),
-    (true, T),
-    (true, U),
-    (true, V),
-    (true, W),
-    (true, X),
-    (true, Y),
-    (true, Z),
-    (false, LBracket),
-    (false, Backslash),
-    (false, RBracket),
-    (true, Key6),
-    (true, Subtract),
-    (false, Grave),
-    (false, A),
-    (false, B),
-    (false, C),
-    (false, D),
-    (false, E),
-    (false, F),
-    (false, G),
-    (false, H),
-    (false, I),
-    (false, J),
-    (false, K),
-    (false, L),
-    (false, M),
-    (false, N),
-    (false, O),
-    (false, P),
-    (false, Q),
-    (false, R),
-    (false, S),
-    (false, T),
-    (false, U),
-    (false, V),
-    (false, W),
-    (false, X),
-    (false, Y),
-    (false, Z),
-    (true, LBracket),
-    (true, Backslash),
-    (true, RBracket),
-    (true, Grave),
+// Keys that don't type characters, along with their key codes.  Characters come from
+// the text that's typed instead, so they follow the player's layout.
+const SPECIAL_KEY_MAPPINGS: [(VirtualKeyCode, u16); 14] = [
+    (Back, generic_keyboard::BACKSPACE),
+    (Return, generic_keyboard::RETURN),
+    (Insert, generic_keyboard::INSERT),
+    (Delete, generic_keyboard::DELETE),
+    (Up, generic_keyboard::ARROW_UP),
+    (Down, generic_keyboard::ARROW_DOWN),
+    (Left, generic_keyboard::ARROW_LEFT),
+    (Right, generic_keyboard::ARROW_RIGHT),
+    (Tab, generic_keyboard::TAB),
+    (Escape, generic_keyboard::ESCAPE),
+    (LShift, generic_keyboard::SHIFT),
+    (RShift, generic_keyboard::SHIFT),
+    (LControl, generic_keyboard::CONTROL),
+    (RControl, generic_keyboard::CONTROL),
 ];
 
 
+/// Connects the player's keyboard to a Generic Keyboard while it's focused.
 pub struct Keyboard {
     focused: bool,
+    device: Rc<RefCell<GenericKeyboard>>,
+    /// The keys that are down on the Generic Keyboard, by the keys that pressed them.
+    held: HashMap<VirtualKeyCode, u16>,
+    /// The last key to go down, which is the one that types the next character.
+    last_pressed: Option<VirtualKeyCode>,
 }
 
 impl Keyboard {
     pub fn new() -> Keyboard {
         Keyboard {
             focused: false,
+            device: Rc::new(RefCell::new(GenericKeyboard::new())),
+            held: HashMap::new(),
+            last_pressed: None,
         }
     }
 
@@ -130,55 +59,163 @@ impl Keyboard {
     pub fn set_focused(&mut self, focused: bool) {
         self.focused = focused;
     }
-}
 
-pub fn try_push_key(dcpu: &mut Dcpu, event_handler: &EventHandler) {
-    let is_shifted = event_handler.is_key_down(LShift) ||
-        event_handler.is_key_down(RShift);
+    /// The Generic Keyboard, for attaching to a DCPU.
+    pub fn device(&self) -> Rc<RefCell<GenericKeyboard>> {
+        self.device.clone()
+    }
+
+    /// Types, presses and releases keys on the Generic Keyboard to match the player's
+    /// key events.  Every key is released when it isn't focused.
+    pub fn update(&mut self, dcpu: &mut Dcpu, events: &[KeyEvent]) {
+        let device = self.device.clone();
+        let mut device = device.borrow_mut();
+
+        if !self.focused {
+            for (_, key_code) in self.held.drain() {
+                device.release(key_code, dcpu);
+            }
+            self.last_pressed = None;
+            return;
+        }
 
-    let mut key_code = 0;
-    for (i, &(needs_shift, key)) in KEY_CODE_MAPPINGS.iter().enumerate() {
-        if needs_shift == is_shifted && event_handler.is_key_pressed(key) {
-            key_code = (i as u16) + PRINTABLE_CHAR_OFFSET;
-            break;
+        for event in events {
+            match *event {
+                KeyEvent::Pressed(key) => {
+                    self.last_pressed = Some(key);
+                    if let Some(key_code) = special_key_code(key) {
+                        // Modifiers are only ever held.
+                        if key_code != generic_keyboard::SHIFT &&
+                            key_code != generic_keyboard::CONTROL {
+                            device.type_key(key_code, dcpu);
+                        }
+                        self.hold(key, key_code, &mut device, dcpu);
+                    }
+                },
+                KeyEvent::Released(key) => {
+                    if let Some(key_code) = self.held.remove(&key) {
+                        device.release(key_code, dcpu);
+                    }
+                    if self.last_pressed == Some(key) {
+                        self.last_pressed = None;
+                    }
+                },
+                KeyEvent::Typed(c) => {
+                    // Control characters come from special keys.
+                    if c < ' ' || c > '~' {
+                        continue;
+                    }
+                    let key_code = c as u16;
+                    device.type_key(key_code, dcpu);
+                    // Whichever key was pressed last typed it, so that key holds it down.
+                    if let Some(key) = self.last_pressed {
+                        self.hold(key, key_code, &mut device, dcpu);
+                    }
+                },
+            }
         }
     }
 
-    if key_code == 0 {
-        key_code = if event_handler.is_key_pressed(Back) {
-            0x10
-        } else if event_handler.is_key_pressed(Return) {
-            0x11
-        } else if event_handler.is_key_pressed(Insert) {
-            0x12
-        } else if event_handler.is_key_pressed(Delete) {
-            0x13
-        } else if event_handler.is_key_pressed(Up) {
-            0x80
-        } else if event_handler.is_key_pressed(Down) {
-            0x81
-        } else if event_handler.is_key_pressed(Left) {
-            0x82
-        } else if event_handler.is_key_pressed(Right) {
-            0x83
-        } else if event_handler.is_key_pressed(LShift) ||
-            event_handler.is_key_pressed(RShift) {
-            0x90
-        } else if event_handler.is_key_pressed(LControl) ||
-            event_handler.is_key_pressed(RControl) {
-            0x91
-        } else {
-            0x0
-        };
+    fn hold(&mut self, key: VirtualKeyCode, key_code: u16, device: &mut GenericKeyboard,
+            dcpu: &mut Dcpu) {
+        if !self.held.contains_key(&key) {
+            self.held.insert(key, key_code);
+            device.press(key_code, dcpu);
+        }
     }
+}
+
+fn special_key_code(key: VirtualKeyCode) -> Option<u16> {
+    SPECIAL_KEY_MAPPINGS.iter()
+        .find(|&&(special_key, _)| special_key == key)
+        .map(|&(_, key_code)| key_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hardware::dcpu::device::Device;
+    use hardware::dcpu::register::Register;
+
+    use game::event_handler::KeyEvent::*;
 
-    if key_code != 0 {
-        let head = dcpu.mem(HEAD_LOCATION);
-        let size = dcpu.mem(SIZE_LOCATION);
-        if size < BUFFER_CAPACITY {
-            let insert_idx = (head + size) % BUFFER_CAPACITY;
-            dcpu.set_mem(DATA_LOCATION + insert_idx, key_code);
-            dcpu.set_mem(SIZE_LOCATION, size + 1);
+    /// A focused keyboard, attached to a DCPU.
+    fn keyboard() -> (Keyboard, Dcpu) {
+        let mut keyboard = Keyboard::new();
+        keyboard.set_focused(true);
+        let mut dcpu = Dcpu::new();
+        dcpu.attach(keyboard.device());
+        (keyboard, dcpu)
+    }
+
+    /// Takes every key typed on the Generic Keyboard, the way a program would.
+    fn typed(keyboard: &Keyboard, dcpu: &mut Dcpu) -> Vec<u16> {
+        let mut keys = vec![];
+        loop {
+            dcpu.set_reg(Register::A as u16, 1);
+            keyboard.device().borrow_mut().interrupt(dcpu);
+            match dcpu.reg(Register::C as u16) {
+                0 => return keys,
+                key => keys.push(key),
+            }
         }
     }
+
+    fn held(keyboard: &Keyboard, dcpu: &mut Dcpu, key: u16) -> bool {
+        dcpu.set_reg(Register::A as u16, 2);
+        dcpu.set_reg(Register::B as u16, key);
+        keyboard.device().borrow_mut().interrupt(dcpu);
+        dcpu.reg(Register::C as u16) != 0
+    }
+
+    #[test]
+    fn layouts() {
+        // On AZERTY, the key where Q is on QWERTY types 'a'.
+        let (mut keyboard, mut dcpu) = keyboard();
+        keyboard.update(&mut dcpu, &[Pressed(Q), Typed('a')]);
+        assert_eq!(typed(&keyboard, &mut dcpu), vec!['a' as u16]);
+        assert!(held(&keyboard, &mut dcpu, 'a' as u16));
+        assert!(!held(&keyboard, &mut dcpu, 'q' as u16));
+        keyboard.update(&mut dcpu, &[Released(Q)]);
+        assert!(!held(&keyboard, &mut dcpu, 'a' as u16));
+    }
+
+    #[test]
+    fn shift() {
+        let (mut keyboard, mut dcpu) = keyboard();
+        keyboard.update(&mut dcpu, &[Pressed(LShift), Pressed(A), Typed('A')]);
+        // Shift is held, but never typed.
+        assert_eq!(typed(&keyboard, &mut dcpu), vec!['A' as u16]);
+        assert!(held(&keyboard, &mut dcpu, generic_keyboard::SHIFT));
+        assert!(held(&keyboard, &mut dcpu, 'A' as u16));
+        keyboard.update(&mut dcpu, &[Released(A), Released(LShift)]);
+        assert!(!held(&keyboard, &mut dcpu, generic_keyboard::SHIFT));
+        assert!(!held(&keyboard, &mut dcpu, 'A' as u16));
+    }
+
+    #[test]
+    fn repeating() {
+        // Repeats type again, but the key stays down until it's released.
+        let (mut keyboard, mut dcpu) = keyboard();
+        keyboard.update(&mut dcpu, &[Pressed(A), Typed('a'), Pressed(A), Typed('a')]);
+        keyboard.update(&mut dcpu, &[Pressed(Back), Pressed(Back)]);
+        assert_eq!(typed(&keyboard, &mut dcpu),
+                   vec!['a' as u16, 'a' as u16, generic_keyboard::BACKSPACE,
+                        generic_keyboard::BACKSPACE]);
+        assert!(held(&keyboard, &mut dcpu, 'a' as u16));
+        keyboard.update(&mut dcpu, &[Released(A), Released(Back)]);
+        assert!(!held(&keyboard, &mut dcpu, 'a' as u16));
+        assert!(!held(&keyboard, &mut dcpu, generic_keyboard::BACKSPACE));
+    }
+
+    #[test]
+    fn unfocusing() {
+        let (mut keyboard, mut dcpu) = keyboard();
+        keyboard.update(&mut dcpu, &[Pressed(LControl), Pressed(C), Typed('c')]);
+        keyboard.set_focused(false);
+        keyboard.update(&mut dcpu, &[Pressed(D), Typed('d')]);
+        assert!(!held(&keyboard, &mut dcpu, generic_keyboard::CONTROL));
+        assert!(!held(&keyboard, &mut dcpu, 'c' as u16));
+        assert_eq!(typed(&keyboard, &mut dcpu), vec!['c' as u16]);
+    }
 }
@@ -0,0 +1,704 @@
+//! The LEM1802, a monitor that shows 32x12 cells of text, with 16 colors and a border.
+//!
+//! It shows nothing until a program maps the screen to memory, and it reads everything it
+//! shows out of the DCPU's memory as it goes, so programs draw by writing to memory.
+//! Fonts and palettes can be mapped to memory too, or left as the defaults.
+//!
+//! Interrupts, picked by `A`:
+//!
+//! | `A` | Name               | Does                                            |
+//! |-----|--------------------|-------------------------------------------------|
+//! | 0   | `MEM_MAP_SCREEN`   | Maps the screen to `B`, or turns it off if 0    |
+//! | 1   | `MEM_MAP_FONT`     | Maps the font to `B`, or the default if 0       |
+//! | 2   | `MEM_MAP_PALETTE`  | Maps the palette to `B`, or the default if 0    |
+//! | 3   | `SET_BORDER_COLOR` | Sets the border to the color at index `B & 0xf` |
+//! | 4   | `MEM_DUMP_FONT`    | Copies the default font to `B`                  |
+//! | 5   | `MEM_DUMP_PALETTE` | Copies the default palette to `B`               |
+//!
+//! Each cell is `ffff bbbb Bccc cccc`: the foreground and background color indices, a
+//! blink bit and a character.  Each character's glyph is two words, holding its four
+//! columns of eight pixels, with the top pixel of each column in the low bit and the
+//! first column in the high octet of the first word.  Colors are `0x0rgb`.
+
+use hardware::dcpu::Dcpu;
+use hardware::dcpu::device::Device;
+use hardware::dcpu::register::Register;
+
+pub const SCREEN_SIZE_IN_CELLS: (u16, u16) = (32, 12);
+pub const CELL_SIZE_IN_PIXELS: (u16, u16) = (4, 8);
+pub const NUM_COLORS: u16 = 16;
+/// Words in a font, which has two for each of 128 characters.
+pub const FONT_SIZE: u16 = 256;
+
+const ID: u32 = 0x7349f615;
+const VERSION: u16 = 0x1802;
+const MANUFACTURER: u32 = 0x1c6c8b36;
+
+const MEM_MAP_SCREEN: u16 = 0;
+const MEM_MAP_FONT: u16 = 1;
+const MEM_MAP_PALETTE: u16 = 2;
+const SET_BORDER_COLOR: u16 = 3;
+const MEM_DUMP_FONT: u16 = 4;
+const MEM_DUMP_PALETTE: u16 = 5;
+
+/// The DawnBringer 16 palette, rounded to 12 bits.
+const DEFAULT_PALETTE: [u16; 16] = [
+    0x112, 0x423, 0x336, 0x545, 0x843, 0x362, 0xc44, 0x776,
+    0x57c, 0xc73, 0x899, 0x6a3, 0xca9, 0x6bc, 0xdc6, 0xded,
+];
+
+/// Glyphs for the printable characters, from `' '` to `'~'`.
+const PRINTABLE_GLYPHS: [[u16; 2]; 95] = [
+    [
+        // " "
+        0b0000000000000000,
+        0b0000000000000000,
+    ],
+    [
+        // "!"
+        0b0000000001011111,
+        0b0000000000000000,
+    ],
+    [
+        // '"'
+        0b0000001100000000,
+        0b0000001100000000,
+    ],
+    [
+        // "#"
+        0b0111111100010010,
+        0b0111111100000000,
+    ],
+    [
+        // "$"
+        0b0010010001101011,
+        0b0001001000000000,
+    ],
+    [
+        // "%"
+        0b0110000100011100,
+        0b0100001100000000,
+    ],
+    [
+        // "&"
+        0b0011011001001001,
+        0b0111010000000000,
+    ],
+    [
+        // "'"
+        0b0000000000000011,
+        0b0000000000000000,
+    ],
+    [
+        // "("
+        0b0000000000111110,
+        0b0100000100000000,
+    ],
+    [
+        // ")"
+        0b0100000100111110,
+        0b0000000000000000,
+    ],
+    [
+        // "*"
+        0b0010101000011100,
+        0b0010101000000000,
+    ],
+    [
+        // "+"
+        0b0000100000011100,
+        0b0000100000000000,
+    ],
+    [
+        // ","
+        0b0100000000100000,
+        0b0000000000000000,
+    ],
+    [
+        // "-"
+        0b0000100000001000,
+        0b0000100000000000,
+    ],
+    [
+        // "."
+        0b0000000001000000,
+        0b0000000000000000,
+    ],
+    [
+        // "/"
+        0b0110000000011100,
+        0b0000001100000000,
+    ],
+    [
+        // "0"
+        0b0011111001001001,
+        0b0011111000000000,
+    ],
+    [
+        // "1"
+        0b0100001001111111,
+        0b0100000000000000,
+    ],
+    [
+        // "2"
+        0b0110001001011001,
+        0b0100011000000000,
+    ],
+    [
+        // "3"
+        0b0010001001001001,
+        0b0011011000000000,
+    ],
+    [
+        // "4"
+        0b0000111100001000,
+        0b0111111100000000,
+    ],
+    [
+        // "5"
+        0b0100111101001001,
+        0b0011000100000000,
+    ],
+    [
+        // "6"
+        0b0011111001001001,
+        0b0011000100000000,
+    ],
+    [
+        // "7"
+        0b0110000100011001,
+        0b0000011100000000,
+    ],
+    [
+        // "8"
+        0b0011101001000101,
+        0b0011101000000000,
+    ],
+    [
+        // "9"
+        0b0010011001001001,
+        0b0011111000000000,
+    ],
+    [
+        // ":"
+        0b0000000000100100,
+        0b0000000000000000,
+    ],
+    [
+        // ";"
+        0b0100000000100100,
+        0b0000000000000000,
+    ],
+    [
+        // "<"
+        0b0000100000010100,
+        0b0010001000000000,
+    ],
+    [
+        // "="
+        0b0001010000010100,
+        0b0001010000000000,
+    ],
+    [
+        // ">"
+        0b0010001000010100,
+        0b0000100000000000,
+    ],
+    [
+        // "?"
+        0b0000001001011001,
+        0b0000011000000000,
+    ],
+    [
+        // "@"
+        0b0011111001001101,
+        0b0010111000000000,
+    ],
+    [
+        // "A"
+        0b0111111000001001,
+        0b0111111000000000,
+    ],
+    [
+        // "B"
+        0b0111111101001001,
+        0b0011011000000000,
+    ],
+    [
+        // "C"
+        0b0011111001000001,
+        0b0010001000000000,
+    ],
+    [
+        // "D"
+        0b0111111101000001,
+        0b0011111000000000,
+    ],
+    [
+        // "E"
+        0b0111111101001001,
+        0b0100000100000000,
+    ],
+    [
+        // "F"
+        0b0111111100001001,
+        0b0000000100000000,
+    ],
+    [
+        // "G"
+        0b0011111001000001,
+        0b0111001000000000,
+    ],
+    [
+        // "H"
+        0b0111111100001000,
+        0b0111111100000000,
+    ],
+    [
+        // "I"
+        0b0100000101111111,
+        0b0100000100000000,
+    ],
+    [
+        // "J"
+        0b0011000001000000,
+        0b0011111100000000,
+    ],
+    [
+        // "K"
+        0b0111111100001000,
+        0b0111011100000000,
+    ],
+    [
+        // "L"
+        0b0111111101000000,
+        0b0100000000000000,
+    ],
+    [
+        // "M"
+        0b0111111100000110,
+        0b0111111100000000,
+    ],
+    [
+        // "N"
+        0b0111111100000001,
+        0b0111111000000000,
+    ],
+    [
+        // "O"
+        0b0011111001000001,
+        0b0011111000000000,
+    ],
+    [
+        // "P"
+        0b0111111100001001,
+        0b0000011000000000,
+    ],
+    [
+        // "Q"
+        0b0011111001110001,
+        0b0111111000000000,
+    ],
+    [
+        // "R"
+        0b0111111100001001,
+        0b0111011000000000,
+    ],
+    [
+        // "S"
+        0b0010011001001001,
+        0b0011001000000000,
+    ],
+    [
+        // "T"
+        0b0000000101111111,
+        0b0000000100000000,
+    ],
+    [
+        // "U"
+        0b0111111101000000,
+        0b0111111100000000,
+    ],
+    [
+        // "V"
+        0b0001111101100000,
+        0b0001111100000000,
+    ],
+    [
+        // "W"
+        0b0111111100110000,
+        0b0111111100000000,
+    ],
+    [
+        // "X"
+        0b0111011100001000,
+        0b0111011100000000,
+    ],
+    [
+        // "Y"
+        0b0000011101111000,
+        0b0000011100000000,
+    ],
+    [
+        // "Z"
+        0b0111000101001001,
+        0b0100011100000000,
+    ],
+    [
+        // "["
+        0b0111111101000001,
+        0b0000000000000000,
+    ],
+    [
+        // "\"
+        0b0000001100011100,
+        0b0110000000000000,
+    ],
+    [
+        // "]"
+        0b0000000001000001,
+        0b0111111100000000,
+    ],
+    [
+        // "^"
+        0b0000001000000001,
+        0b0000001000000000,
+    ],
+    [
+        // "_"
+        0b0100000001000000,
+        0b0100000000000000,
+    ],
+    [
+        // "`"
+        0b0000000000000001,
+        0b0000001000000000,
+    ],
+    [
+        // "a"
+        0b0010010001010100,
+        0b0111100000000000,
+    ],
+    [
+        // "b"
+        0b0111111101001000,
+        0b0011000000000000,
+    ],
+    [
+        // "c"
+        0b0011100001000100,
+        0b0010100000000000,
+    ],
+    [
+        // "d"
+        0b0011000001001000,
+        0b0111111000000000,
+    ],
+    [
+        // "e"
+        0b0011100001010100,
+        0b0101100000000000,
+    ],
+    [
+        // "f"
+        0b0000100001111110,
+        0b0000100100000000,
+    ],
+    [
+        // "g"
+        0b0100100001010100,
+        0b0011110000000000,
+    ],
+    [
+        // "h"
+        0b0111111000001000,
+        0b0111100000000000,
+    ],
+    [
+        // "i"
+        0b0100010001111101,
+        0b0100000000000000,
+    ],
+    [
+        // "j"
+        0b0010000001000000,
+        0b0011110100000000,
+    ],
+    [
+        // "k"
+        0b0111111100010000,
+        0b0110110000000000,
+    ],
+    [
+        // "l"
+        0b0100000101111111,
+        0b0100000000000000,
+    ],
+    [
+        // "m"
+        0b0111110000011000,
+        0b0111110000000000,
+    ],
+    [
+        // "n"
+        0b0111110000000100,
+        0b0111100000000000,
+    ],
+    [
+        // "o"
+        0b0011100001000100,
+        0b0011100000000000,
+    ],
+    [
+        // "p"
+        0b0111110000010100,
+        0b0000100000000000,
+    ],
+    [
+        // "q"
+        0b0000100000010100,
+        0b0111110000000000,
+    ],
+    [
+        // "r"
+        0b0111110000000100,
+        0b0000100000000000,
+    ],
+    [
+        // "s"
+        0b0100100001010100,
+        0b0010010000000000,
+    ],
+    [
+        // "t"
+        0b0000010000111110,
+        0b0100010000000000,
+    ],
+    [
+        // "u"
+        0b0011110001000000,
+        0b0111110000000000,
+    ],
+    [
+        // "v"
+        0b0001110001100000,
+        0b0001110000000000,
+    ],
+    [
+        // "w"
+        0b0111110000110000,
+        0b0111110000000000,
+    ],
+    [
+        // "x"
+        0b0110110000010000,
+        0b0110110000000000,
+    ],
+    [
+        // "y"
+        0b0100110001010000,
+        0b0011110000000000,
+    ],
+    [
+        // "z"
+        0b0110010001010100,
+        0b0100110000000000,
+    ],
+    [
+        // "{"
+        0b0000100000110110,
+        0b0100000100000000,
+    ],
+    [
+        // "|"
+        0b0000000001111111,
+        0b0000000000000000,
+    ],
+    [
+        // "}"
+        0b0100000100110110,
+        0b0000100000000000,
+    ],
+    [
+        // "~"
+        0b0000001000000011,
+        0b0000000100000000,
+    ],
+];
+
+
+pub struct Lem1802 {
+    /// Where each of these is mapped, or 0 if it isn't.
+    screen_addr: u16,
+    font_addr: u16,
+    palette_addr: u16,
+    border_color_idx: u16,
+}
+
+impl Lem1802 {
+    pub fn new() -> Lem1802 {
+        Lem1802 {
+            screen_addr: 0,
+            font_addr: 0,
+            palette_addr: 0,
+            border_color_idx: 0,
+        }
+    }
+
+    /// Whether the screen is mapped to memory.  It shows nothing until it is.
+    pub fn screen_mapped(&self) -> bool {
+        self.screen_addr != 0
+    }
+
+    /// Returns the word for the cell at `i`, counting across each row, then down.
+    pub fn cell(&self, dcpu: &Dcpu, i: u16) -> u16 {
+        assert!(i < SCREEN_SIZE_IN_CELLS.0 * SCREEN_SIZE_IN_CELLS.1);
+        dcpu.mem(self.screen_addr.wrapping_add(i))
+    }
+
+    /// Returns the two words of the glyph for `char_idx` in the current font.
+    pub fn glyph(&self, dcpu: &Dcpu, char_idx: u16) -> [u16; 2] {
+        assert!(char_idx < FONT_SIZE / 2);
+        let i = char_idx * 2;
+        if self.font_addr == 0 {
+            [default_font_word(i), default_font_word(i + 1)]
+        } else {
+            [dcpu.mem(self.font_addr.wrapping_add(i)),
+             dcpu.mem(self.font_addr.wrapping_add(i + 1))]
+        }
+    }
+
+    /// Returns the color at `color_idx` in the current palette as 8-bit RGB.
+    pub fn color(&self, dcpu: &Dcpu, color_idx: u16) -> [u8; 3] {
+        assert!(color_idx < NUM_COLORS);
+        let color = if self.palette_addr == 0 {
+            DEFAULT_PALETTE[color_idx as usize]
+        } else {
+            dcpu.mem(self.palette_addr.wrapping_add(color_idx))
+        };
+        // Scaling each channel by 17 takes 0xf to 0xff.
+        [((color >> 8) & 0xf) as u8 * 17,
+         ((color >> 4) & 0xf) as u8 * 17,
+         (color & 0xf) as u8 * 17]
+    }
+
+    pub fn border_color(&self, dcpu: &Dcpu) -> [u8; 3] {
+        self.color(dcpu, self.border_color_idx)
+    }
+}
+
+impl Device for Lem1802 {
+    fn id(&self) -> u32 {
+        ID
+    }
+
+    fn version(&self) -> u16 {
+        VERSION
+    }
+
+    fn manufacturer(&self) -> u32 {
+        MANUFACTURER
+    }
+
+    fn interrupt(&mut self, dcpu: &mut Dcpu) {
+        let b = dcpu.reg(Register::B as u16);
+        match dcpu.reg(Register::A as u16) {
+            MEM_MAP_SCREEN => self.screen_addr = b,
+            MEM_MAP_FONT => self.font_addr = b,
+            MEM_MAP_PALETTE => self.palette_addr = b,
+            SET_BORDER_COLOR => self.border_color_idx = b & 0xf,
+            MEM_DUMP_FONT => {
+                for i in 0..FONT_SIZE {
+                    dcpu.set_mem(b.wrapping_add(i), default_font_word(i));
+                }
+            },
+            MEM_DUMP_PALETTE => {
+                for i in 0..NUM_COLORS {
+                    dcpu.set_mem(b.wrapping_add(i), DEFAULT_PALETTE[i as usize]);
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Returns word `i` of the default font.  Characters outside of `PRINTABLE_GLYPHS` are
+/// blank.
+fn default_font_word(i: u16) -> u16 {
+    let char_idx = i / 2;
+    if char_idx < 0x20 || char_idx > 0x7e {
+        return 0;
+    }
+    PRINTABLE_GLYPHS[(char_idx - 0x20) as usize][(i % 2) as usize]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use hardware::dcpu::{assemble_for_test, with_device};
+
+    #[test]
+    fn interrupts() {
+        let lem = Rc::new(RefCell::new(Lem1802::new()));
+        let mut dcpu = with_device(lem.clone(), "\
+hwn Z
+hwq 0
+set PUSH, A
+set PUSH, B
+set A, 3
+set B, 0x1f
+hwi 0
+set A, 5
+set B, 0x2000
+hwi 0
+set [0x2003], 0x0f80
+set A, 2
+hwi 0
+set A, 4
+set B, 0x3000
+hwi 0
+set [0x3082], 0xffff
+set A, 1
+hwi 0
+:finish set PC, finish
+");
+
+        // Nothing is mapped until the program runs.
+        assert!(!lem.borrow().screen_mapped());
+        assert_eq!(lem.borrow().color(&dcpu, 3), [0x55, 0x44, 0x55]);
+        assert_eq!(lem.borrow().glyph(&dcpu, 'A' as u16),
+                   PRINTABLE_GLYPHS[('A' as u8 - 0x20) as usize]);
+        while !dcpu.finished() {
+            dcpu.tick();
+        }
+
+        assert_eq!(dcpu.reg(Register::Z as u16), 1);
+        assert_eq!((dcpu.mem(0xfffe), dcpu.mem(0xfffd)), (0xf615, 0x7349));
+        assert_eq!(dcpu.reg(Register::C as u16), VERSION);
+        let lem = lem.borrow();
+        assert!(!lem.screen_mapped());
+        assert_eq!(lem.border_color(&dcpu), [0xdd, 0xee, 0xdd]);
+        assert_eq!(lem.color(&dcpu, 3), [0xff, 0x88, 0x00]);
+        assert_eq!(lem.glyph(&dcpu, 'A' as u16), [0xffff, PRINTABLE_GLYPHS[33][1]]);
+        assert_eq!(lem.glyph(&dcpu, 'B' as u16), PRINTABLE_GLYPHS[34]);
+        assert_eq!(lem.glyph(&dcpu, 0), [0, 0]);
+    }
+
+    #[test]
+    fn assembled_text() {
+        // `.lem` strings are drawn as the characters they were written as.
+        let text = "Hi, LEM!";
+        let words = assemble_for_test(&format!(".lem 0xf000, \"{}\"\n", text));
+        assert_eq!(words.len(), text.len());
+        let lem = Lem1802::new();
+        let dcpu = Dcpu::new();
+        for (&word, c) in words.iter().zip(text.bytes()) {
+            assert_eq!(word & 0xff80, 0xf000);
+            assert_eq!(lem.glyph(&dcpu, word & 0x7f), PRINTABLE_GLYPHS[(c - 0x20) as usize]);
+        }
+    }
+}
@@ -0,0 +1,513 @@
+//! The Mackapar M35FD, a 3.5" floppy drive.
+//!
+//! Disks hold 1440 sectors of 512 words, in 80 tracks of 18 sectors.  Reading and writing
+//! happen in the background, by copying sectors straight to and from the DCPU's memory,
+//! and take as long as they would on a real drive: 2.4ms to move the head each track, and
+//! 30700 words a second once it's there.
+//!
+//! Interrupts, picked by `A`:
+//!
+//! * 0 - Sets `B` to the drive's state and `C` to the last error since the last time it
+//!   was asked, then clears the error.
+//! * 1 - Turns on interrupts with message `X`, which are sent whenever the state or error
+//!   changes, or turns them off if `X` is 0.
+//! * 2 - Starts reading sector `X` into memory at `Y`.  Sets `B` to 1 if it started, or 0
+//!   if it couldn't, in which case the error says why.
+//! * 3 - Starts writing sector `X` from memory at `Y`, and sets `B` like reading does.
+//!
+//! Disks are stored on the host as image files of little-endian words, like the ones
+//! `dasm` writes.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use hardware::dcpu::{self, Dcpu};
+use hardware::dcpu::device::Device;
+use hardware::dcpu::register::Register;
+
+pub const WORDS_PER_SECTOR: u16 = 512;
+pub const SECTORS_PER_TRACK: u16 = 18;
+pub const NUM_TRACKS: u16 = 80;
+pub const NUM_SECTORS: u16 = SECTORS_PER_TRACK * NUM_TRACKS;
+/// How many words a disk holds.
+pub const DISK_SIZE: usize = NUM_SECTORS as usize * WORDS_PER_SECTOR as usize;
+
+const ID: u32 = 0x4fd524c5;
+const VERSION: u16 = 0x000b;
+const MANUFACTURER: u32 = 0x1eb37e91;
+
+const POLL: u16 = 0;
+const SET_INTERRUPT_MESSAGE: u16 = 1;
+const READ_SECTOR: u16 = 2;
+const WRITE_SECTOR: u16 = 3;
+
+const STATE_NO_MEDIA: u16 = 0;
+const STATE_READY: u16 = 1;
+const STATE_READY_WP: u16 = 2;
+const STATE_BUSY: u16 = 3;
+
+const ERROR_NONE: u16 = 0;
+const ERROR_BUSY: u16 = 1;
+const ERROR_NO_MEDIA: u16 = 2;
+const ERROR_PROTECTED: u16 = 3;
+const ERROR_EJECT: u16 = 4;
+const ERROR_BAD_SECTOR: u16 = 5;
+const ERROR_BROKEN: u16 = 0xffff;
+
+/// 2.4ms, in DCPU cycles.
+const SEEK_CYCLES_PER_TRACK: u64 = dcpu::CLOCK_SPEED * 24 / 10_000;
+const WORDS_PER_SECOND: u64 = 30_700;
+/// How long reading or writing a sector takes once the head is over it, in DCPU cycles.
+const TRANSFER_CYCLES: u64 =
+    WORDS_PER_SECTOR as u64 * dcpu::CLOCK_SPEED / WORDS_PER_SECOND;
+
+
+/// A floppy disk, which can be backed by an image file on the host.
+pub struct Disk {
+    words: Vec<u16>,
+    /// The image file that writes go to, if there is one.
+    path: Option<PathBuf>,
+    write_protected: bool,
+}
+
+impl Disk {
+    /// Returns an empty disk that isn't saved anywhere.
+    pub fn blank() -> Disk {
+        Disk {
+            words: vec![0; DISK_SIZE],
+            path: None,
+            write_protected: false,
+        }
+    }
+
+    /// Opens the image at `path`, which every write to the disk goes to.
+    ///
+    /// Images shorter than a full disk are treated as if the rest of the disk is zeroes,
+    /// so programs can be put on disks as they come out of the assembler.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Disk> {
+        let path = path.as_ref();
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        if bytes.len() > DISK_SIZE * 2 || bytes.len() % 2 != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "{} isn't a disk image; it should be at most {} bytes, in words",
+                path.display(), DISK_SIZE * 2)));
+        }
+
+        let mut words: Vec<u16> = bytes.chunks(2)
+            .map(|pair| pair[0] as u16 | (pair[1] as u16) << 8)
+            .collect();
+        words.resize(DISK_SIZE, 0);
+        Ok(Disk {
+            words,
+            path: Some(path.to_path_buf()),
+            write_protected: false,
+        })
+    }
+
+    pub fn write_protected(&self) -> bool {
+        self.write_protected
+    }
+
+    pub fn set_write_protected(&mut self, write_protected: bool) {
+        self.write_protected = write_protected;
+    }
+
+    pub fn words(&self) -> &[u16] {
+        &self.words
+    }
+
+    fn sector(&self, sector: u16) -> &[u16] {
+        let start = sector as usize * WORDS_PER_SECTOR as usize;
+        &self.words[start..start + WORDS_PER_SECTOR as usize]
+    }
+
+    /// Overwrites `sector`, and the same sector of the image file, if there is one.
+    fn write_sector(&mut self, sector: u16, words: &[u16]) -> io::Result<()> {
+        let start = sector as usize * WORDS_PER_SECTOR as usize;
+        self.words[start..start + WORDS_PER_SECTOR as usize].copy_from_slice(words);
+
+        if let Some(ref path) = self.path {
+            let mut bytes = Vec::with_capacity(words.len() * 2);
+            for &word in words {
+                bytes.push(word as u8);
+                bytes.push((word >> 8) as u8);
+            }
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            file.seek(SeekFrom::Start(start as u64 * 2))?;
+            file.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum OperationKind {
+    Read,
+    Write,
+}
+
+/// A read or write that's under way.
+struct Operation {
+    kind: OperationKind,
+    sector: u16,
+    /// Where the sector goes in memory, or comes from.
+    addr: u16,
+    /// When it finishes, in DCPU cycles.
+    done_cycle: u64,
+}
+
+
+pub struct M35fd {
+    disk: Option<Disk>,
+    operation: Option<Operation>,
+    /// The track the head is over.
+    track: u16,
+    /// The last error since the drive was polled.
+    error: u16,
+    /// Sent whenever the state or error changes, or 0 if interrupts are off.
+    interrupt_message: u16,
+}
+
+impl M35fd {
+    pub fn new() -> M35fd {
+        M35fd {
+            disk: None,
+            operation: None,
+            track: 0,
+            error: ERROR_NONE,
+            interrupt_message: 0,
+        }
+    }
+
+    /// Puts `disk` in the drive, giving back the disk that was already in it, if there
+    /// was one.
+    pub fn insert(&mut self, disk: Disk, dcpu: &mut Dcpu) -> Option<Disk> {
+        let ejected = self.eject(dcpu);
+        self.disk = Some(disk);
+        self.send_interrupt(dcpu);
+        ejected
+    }
+
+    /// Takes the disk out of the drive, stopping whatever it was doing.
+    pub fn eject(&mut self, dcpu: &mut Dcpu) -> Option<Disk> {
+        let disk = self.disk.take();
+        if disk.is_some() {
+            if self.operation.take().is_some() {
+                self.error = ERROR_EJECT;
+            }
+            self.send_interrupt(dcpu);
+        }
+        disk
+    }
+
+    pub fn disk(&self) -> Option<&Disk> {
+        self.disk.as_ref()
+    }
+
+    fn state(&self) -> u16 {
+        match self.disk {
+            None => STATE_NO_MEDIA,
+            Some(_) if self.operation.is_some() => STATE_BUSY,
+            Some(ref disk) if disk.write_protected() => STATE_READY_WP,
+            Some(_) => STATE_READY,
+        }
+    }
+
+    /// Starts reading or writing, returning whether it could, and setting the error if
+    /// it couldn't.
+    fn start(&mut self, kind: OperationKind, sector: u16, addr: u16, dcpu: &mut Dcpu)
+             -> bool {
+        let error = match self.disk {
+            None => ERROR_NO_MEDIA,
+            Some(_) if self.operation.is_some() => ERROR_BUSY,
+            Some(_) if sector >= NUM_SECTORS => ERROR_BAD_SECTOR,
+            Some(ref disk) if kind == OperationKind::Write && disk.write_protected() => {
+                ERROR_PROTECTED
+            },
+            Some(_) => ERROR_NONE,
+        };
+        if error != ERROR_NONE {
+            self.error = error;
+            self.send_interrupt(dcpu);
+            return false;
+        }
+
+        let track = sector / SECTORS_PER_TRACK;
+        let tracks_moved = (track as i32 - self.track as i32).abs() as u64;
+        self.track = track;
+        let seek_cycles = tracks_moved * SEEK_CYCLES_PER_TRACK;
+        self.operation = Some(Operation {
+            kind,
+            sector,
+            addr,
+            done_cycle: dcpu.cycles() + seek_cycles + TRANSFER_CYCLES,
+        });
+        self.send_interrupt(dcpu);
+        true
+    }
+
+    /// Copies the sector for the finished `operation`.
+    fn finish(&mut self, operation: Operation, dcpu: &mut Dcpu) {
+        let disk = match self.disk {
+            Some(ref mut disk) => disk,
+            None => return,
+        };
+        match operation.kind {
+            OperationKind::Read => {
+                let words = disk.sector(operation.sector);
+                for (i, &word) in words.iter().enumerate() {
+                    dcpu.set_mem(operation.addr.wrapping_add(i as u16), word);
+                }
+            },
+            OperationKind::Write => {
+                let words: Vec<u16> = (0..WORDS_PER_SECTOR)
+                    .map(|i| dcpu.mem(operation.addr.wrapping_add(i)))
+                    .collect();
+                if let Err(e) = disk.write_sector(operation.sector, &words) {
+                    eprintln!("Couldn't save sector {} of a disk: {}", operation.sector,
+                              e);
+                    self.error = ERROR_BROKEN;
+                }
+            },
+        }
+    }
+
+    fn send_interrupt(&self, dcpu: &mut Dcpu) {
+        if self.interrupt_message != 0 {
+            dcpu.interrupt(self.interrupt_message);
+        }
+    }
+}
+
+impl Device for M35fd {
+    fn id(&self) -> u32 {
+        ID
+    }
+
+    fn version(&self) -> u16 {
+        VERSION
+    }
+
+    fn manufacturer(&self) -> u32 {
+        MANUFACTURER
+    }
+
+    fn interrupt(&mut self, dcpu: &mut Dcpu) {
+        let x = dcpu.reg(Register::X as u16);
+        let y = dcpu.reg(Register::Y as u16);
+        match dcpu.reg(Register::A as u16) {
+            POLL => {
+                let state = self.state();
+                dcpu.set_reg(Register::B as u16, state);
+                dcpu.set_reg(Register::C as u16, self.error);
+                self.error = ERROR_NONE;
+            },
+            SET_INTERRUPT_MESSAGE => self.interrupt_message = x,
+            READ_SECTOR => {
+                let started = self.start(OperationKind::Read, x, y, dcpu);
+                dcpu.set_reg(Register::B as u16, started as u16);
+            },
+            WRITE_SECTOR => {
+                let started = self.start(OperationKind::Write, x, y, dcpu);
+                dcpu.set_reg(Register::B as u16, started as u16);
+            },
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, dcpu: &mut Dcpu) {
+        let done = match self.operation {
+            Some(ref operation) => dcpu.cycles() >= operation.done_cycle,
+            None => false,
+        };
+        if done {
+            let operation = self.operation.take().unwrap();
+            self.finish(operation, dcpu);
+            self.send_interrupt(dcpu);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::rc::Rc;
+
+    use super::*;
+    use hardware::dcpu::with_device;
+
+    /// Runs `program_src` with a drive holding `disk`, until it finishes.
+    fn run(program_src: &str, disk: Disk) -> (Dcpu, Rc<RefCell<M35fd>>) {
+        let drive = Rc::new(RefCell::new(M35fd::new()));
+        let mut dcpu = with_device(drive.clone(), program_src);
+        drive.borrow_mut().insert(disk, &mut dcpu);
+        while !dcpu.finished() {
+            dcpu.tick();
+        }
+        (dcpu, drive)
+    }
+
+    #[test]
+    fn reading_and_writing() {
+        let path = env::temp_dir().join(format!("m35fd-test-{}.bin", process::id()));
+        // Short images are padded out.
+        File::create(&path).unwrap().write_all(&[0x34, 0x12, 0x78, 0x56]).unwrap();
+        let disk = Disk::open(&path).unwrap();
+
+        // Counts interrupts in Z, copies sector 0 to sector 1000, and waits for both.
+        let (dcpu, drive) = run("\
+ias on_change
+set A, 1
+set X, 0x1234
+hwi 0
+set A, 2
+set X, 0
+set Y, 0x4000
+hwi 0
+set [0x1000], B
+:read_wait
+set A, 0
+hwi 0
+ife B, 3
+  set PC, read_wait
+set [0x1001], C
+set A, 3
+set X, 1000
+set Y, 0x4000
+hwi 0
+set A, 2 ; The drive is busy.
+hwi 0
+set [0x1002], B
+set A, 0
+hwi 0
+set [0x1003], C
+:write_wait
+set A, 0
+hwi 0
+ife B, 3
+  set PC, write_wait
+:finish set PC, finish
+
+:on_change
+add Z, 1
+rfi 0
+", disk);
+        assert_eq!((dcpu.mem(0x4000), dcpu.mem(0x4001), dcpu.mem(0x4002)),
+                   (0x1234, 0x5678, 0));
+        assert_eq!((dcpu.mem(0x1000), dcpu.mem(0x1001)), (1, ERROR_NONE));
+        assert_eq!((dcpu.mem(0x1002), dcpu.mem(0x1003)), (0, ERROR_BUSY));
+        // Read started and finished, write started, failed read, write finished.
+        assert_eq!(dcpu.reg(Register::Z as u16), 5);
+        // Seeking 55 tracks makes writing take longer than reading.
+        assert!(dcpu.cycles() > 2 * TRANSFER_CYCLES + 55 * SEEK_CYCLES_PER_TRACK);
+
+        let drive = drive.borrow();
+        let copy = &drive.disk().unwrap().words()[1000 * 512..1000 * 512 + 3];
+        assert_eq!(copy, &[0x1234, 0x5678, 0][..]);
+        let saved = Disk::open(&path).unwrap();
+        assert_eq!(&saved.words()[1000 * 512..1000 * 512 + 2], &[0x1234, 0x5678][..]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        let mut disk = Disk::blank();
+        disk.set_write_protected(true);
+        let (dcpu, _) = run("\
+set A, 0
+hwi 0
+set [0x1000], B
+set A, 3
+hwi 0
+set A, 0
+hwi 0
+set [0x1001], C
+set A, 2
+set X, 1440
+hwi 0
+set A, 0
+hwi 0
+set [0x1002], C
+hwi 0
+set [0x1003], C
+:finish set PC, finish
+", disk);
+        assert_eq!(dcpu.mem(0x1000), STATE_READY_WP);
+        assert_eq!(dcpu.mem(0x1001), ERROR_PROTECTED);
+        assert_eq!(dcpu.mem(0x1002), ERROR_BAD_SECTOR);
+        // Polling clears the error.
+        assert_eq!(dcpu.mem(0x1003), ERROR_NONE);
+    }
+
+    #[test]
+    fn swapping_disks() {
+        // Records the state and error at every interrupt, counting them in Z, and reads
+        // sector 0 whenever [0x2000] is set.
+        let drive = Rc::new(RefCell::new(M35fd::new()));
+        let mut dcpu = with_device(drive.clone(), "\
+ias on_change
+set A, 1
+set X, 1
+hwi 0
+:idle
+ife [0x2000], 0
+  set PC, idle
+set [0x2000], 0
+set A, 2
+set X, 0
+set Y, 0x4000
+hwi 0
+set PC, idle
+
+:on_change
+set PUSH, A
+set A, 0
+hwi 0
+set [0x1000+Z], B
+set [0x1010+Z], C
+add Z, 1
+set A, POP
+rfi 0
+");
+        let run_for = |dcpu: &mut Dcpu, cycles: u64| {
+            let end = dcpu.cycles() + cycles;
+            while dcpu.cycles() < end {
+                dcpu.tick();
+            }
+        };
+
+        run_for(&mut dcpu, 100);
+        assert!(drive.borrow_mut().eject(&mut dcpu).is_none());
+        let mut disk = Disk::blank();
+        disk.set_write_protected(true);
+        assert!(drive.borrow_mut().insert(disk, &mut dcpu).is_none());
+        run_for(&mut dcpu, 100);
+        let disk = drive.borrow_mut().eject(&mut dcpu).unwrap();
+        assert!(disk.write_protected());
+        run_for(&mut dcpu, 100);
+        drive.borrow_mut().insert(disk, &mut dcpu);
+        run_for(&mut dcpu, 100);
+        // Taking the disk out in the middle of a read stops it.
+        dcpu.set_mem(0x2000, 1);
+        run_for(&mut dcpu, 100);
+        assert!(drive.borrow_mut().eject(&mut dcpu).is_some());
+        run_for(&mut dcpu, 100);
+
+        assert_eq!(dcpu.reg(Register::Z as u16), 5);
+        let states: Vec<u16> = (0..5).map(|i| dcpu.mem(0x1000 + i)).collect();
+        assert_eq!(states, vec![STATE_READY_WP, STATE_NO_MEDIA, STATE_READY_WP, STATE_BUSY,
+                                STATE_NO_MEDIA]);
+        let errors: Vec<u16> = (0..5).map(|i| dcpu.mem(0x1010 + i)).collect();
+        assert_eq!(errors, vec![ERROR_NONE, ERROR_NONE, ERROR_NONE, ERROR_NONE,
+                                ERROR_EJECT]);
+        // The read never got to memory.
+        assert_eq!(dcpu.mem(0x4000), 0);
+    }
+}
@@ -1,3 +1,11 @@
+pub mod clock;
 pub mod dcpu;
+pub mod firmware;
 pub mod keyboard;
+pub mod m35fd;
+pub mod network;
+pub mod rng;
+pub mod serial;
+pub mod speaker;
+pub mod sped3;
 pub mod lem;
@@ -0,0 +1,239 @@
+//! A network card, which sends packets of words to the other computers in the world, by
+//! way of a `Switch`.
+//!
+//! The switch gives each card an address when it's plugged in, counting up from 1.
+//! Packets can be lost or take a while to arrive, depending on the switch, and packets
+//! for cards that aren't there, or whose receive queue is full, are dropped.
+//!
+//! Interrupts, picked by `A`:
+//!
+//! * 0 - Sets `C` to the card's address, or 0 if it isn't plugged into a switch.
+//! * 1 - Sends the `Y` words at `X` to the card with address `B`, or to every other card
+//!   if `B` is 0xffff.  Sets `C` to 1 if the packet was sent, or 0 if it's longer than
+//!   `MAX_PACKET_SIZE` words or the send queue is full.
+//! * 2 - Takes the next packet out of the receive queue and copies up to `Y` words of it
+//!   to `X`, setting `B` to the address it came from and `C` to its length, or sets both
+//!   to 0 if the queue is empty.
+//! * 3 - Sets `B` to the number of packets in the receive queue.
+//! * 4 - Turns on interrupts with message `B`, which are sent whenever a packet arrives,
+//!   or turns them off if `B` is 0.
+
+pub mod switch;
+
+use std::collections::VecDeque;
+
+use hardware::dcpu::Dcpu;
+use hardware::dcpu::device::Device;
+use hardware::dcpu::register::Register;
+
+pub use self::switch::Switch;
+
+/// Packets sent here go to every card but the one that sent them.
+pub const BROADCAST_ADDRESS: u16 = 0xffff;
+/// The most words a packet can hold.
+pub const MAX_PACKET_SIZE: u16 = 64;
+
+const ID: u32 = 0x4e1c0001;
+const VERSION: u16 = 1;
+const MANUFACTURER: u32 = 0;
+
+const GET_ADDRESS: u16 = 0;
+const SEND: u16 = 1;
+const RECEIVE: u16 = 2;
+const GET_STATUS: u16 = 3;
+const SET_INTERRUPT_MESSAGE: u16 = 4;
+
+/// The most packets each queue holds.
+const QUEUE_CAPACITY: usize = 16;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packet {
+    pub from: u16,
+    pub to: u16,
+    pub words: Vec<u16>,
+}
+
+
+pub struct NetworkCard {
+    /// Given by the switch, or 0 until the card's plugged into one.
+    address: u16,
+    /// Packets waiting for the switch to pick them up, oldest first.
+    sent: VecDeque<Packet>,
+    /// Packets waiting for the DCPU to take them, oldest first.
+    received: VecDeque<Packet>,
+    /// Packets that have arrived since the DCPU was last told about them.
+    arrivals: usize,
+    /// Sent whenever a packet arrives, or 0 if interrupts are off.
+    interrupt_message: u16,
+}
+
+impl NetworkCard {
+    pub fn new() -> NetworkCard {
+        NetworkCard {
+            address: 0,
+            sent: VecDeque::new(),
+            received: VecDeque::new(),
+            arrivals: 0,
+            interrupt_message: 0,
+        }
+    }
+
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    pub fn set_address(&mut self, address: u16) {
+        self.address = address;
+    }
+
+    /// Takes the oldest packet that's been sent but hasn't gone out yet.
+    pub fn take_sent(&mut self) -> Option<Packet> {
+        self.sent.pop_front()
+    }
+
+    /// Adds `packet` to the receive queue, unless it's full.
+    pub fn deliver(&mut self, packet: Packet) {
+        if self.received.len() < QUEUE_CAPACITY {
+            self.received.push_back(packet);
+            self.arrivals += 1;
+        }
+    }
+
+    fn send(&mut self, dcpu: &Dcpu) -> bool {
+        let to = dcpu.reg(Register::B as u16);
+        let addr = dcpu.reg(Register::X as u16);
+        let len = dcpu.reg(Register::Y as u16);
+        let full = self.sent.len() == QUEUE_CAPACITY;
+        if self.address == 0 || len > MAX_PACKET_SIZE || full {
+            return false;
+        }
+        self.sent.push_back(Packet {
+            from: self.address,
+            to,
+            words: (0..len).map(|i| dcpu.mem(addr.wrapping_add(i))).collect(),
+        });
+        true
+    }
+
+    fn receive(&mut self, dcpu: &mut Dcpu) {
+        let packet = match self.received.pop_front() {
+            Some(p) => p,
+            None => {
+                dcpu.set_reg(Register::B as u16, 0);
+                dcpu.set_reg(Register::C as u16, 0);
+                return;
+            },
+        };
+        let addr = dcpu.reg(Register::X as u16);
+        let max_len = dcpu.reg(Register::Y as u16) as usize;
+        for (i, &word) in packet.words.iter().take(max_len).enumerate() {
+            dcpu.set_mem(addr.wrapping_add(i as u16), word);
+        }
+        dcpu.set_reg(Register::B as u16, packet.from);
+        dcpu.set_reg(Register::C as u16, packet.words.len() as u16);
+    }
+}
+
+impl Device for NetworkCard {
+    fn id(&self) -> u32 {
+        ID
+    }
+
+    fn version(&self) -> u16 {
+        VERSION
+    }
+
+    fn manufacturer(&self) -> u32 {
+        MANUFACTURER
+    }
+
+    fn interrupt(&mut self, dcpu: &mut Dcpu) {
+        match dcpu.reg(Register::A as u16) {
+            GET_ADDRESS => dcpu.set_reg(Register::C as u16, self.address),
+            SEND => {
+                let sent = self.send(dcpu);
+                dcpu.set_reg(Register::C as u16, sent as u16);
+            },
+            RECEIVE => self.receive(dcpu),
+            GET_STATUS => dcpu.set_reg(Register::B as u16, self.received.len() as u16),
+            SET_INTERRUPT_MESSAGE => {
+                self.interrupt_message = dcpu.reg(Register::B as u16);
+            },
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, dcpu: &mut Dcpu) {
+        // Packets are delivered by the switch, which can't get at the DCPU, so we tell
+        // it about them here.
+        while self.arrivals > 0 {
+            self.arrivals -= 1;
+            if self.interrupt_message != 0 {
+                dcpu.interrupt(self.interrupt_message);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use hardware::dcpu::with_device;
+
+    #[test]
+    fn sending_and_receiving() {
+        let card = Rc::new(RefCell::new(NetworkCard::new()));
+        card.borrow_mut().set_address(2);
+        // Sends "hi" to address 7, and copies the first two words of each packet that
+        // arrives to [0x1000], with who sent it in [0x1002] and its length in [0x1003].
+        let mut dcpu = with_device(card.clone(), "\
+ias on_packet
+set A, 4
+set B, 0x42
+hwi 0
+set A, 1
+set B, 7
+set X, message
+set Y, 2
+hwi 0
+:wait set PC, wait
+
+:on_packet
+set A, 2
+set X, 0x1000
+set Y, 2
+hwi 0
+set [0x1002], B
+set [0x1003], C
+rfi 0
+
+:message dat \"hi\"
+");
+        for _ in 0..20 {
+            dcpu.tick();
+        }
+
+        assert_eq!(card.borrow_mut().take_sent(), Some(Packet {
+            from: 2,
+            to: 7,
+            words: vec!['h' as u16, 'i' as u16],
+        }));
+        assert_eq!(card.borrow_mut().take_sent(), None);
+
+        card.borrow_mut().deliver(Packet {
+            from: 7,
+            to: 2,
+            words: vec![1, 2, 3],
+        });
+        for _ in 0..20 {
+            dcpu.tick();
+        }
+        assert_eq!((dcpu.mem(0x1000), dcpu.mem(0x1001)), (1, 2));
+        assert_eq!((dcpu.mem(0x1002), dcpu.mem(0x1003)), (7, 3));
+    }
+}
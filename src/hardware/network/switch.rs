@@ -0,0 +1,207 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use rand::Rng;
+
+use super::{NetworkCard, Packet, BROADCAST_ADDRESS};
+
+
+/// Passes packets between the network cards plugged into it, once a tick.
+pub struct Switch {
+    cards: Vec<Rc<RefCell<NetworkCard>>>,
+    /// Packets on their way, with the tick they arrive on.
+    in_flight: Vec<(u64, Packet)>,
+    ticks: u64,
+    /// How many ticks packets take to arrive.
+    latency: u64,
+    /// The chance of each packet being lost, from 0 to 1.
+    loss: f32,
+    /// Decides which packets are lost.
+    rng: Rc<RefCell<Rng>>,
+}
+
+impl Switch {
+    /// Returns a new `Switch`, which passes on every packet right away.  Any randomness
+    /// comes from `rng`.
+    pub fn new(rng: Rc<RefCell<Rng>>) -> Switch {
+        Switch {
+            cards: Vec::new(),
+            in_flight: Vec::new(),
+            ticks: 0,
+            latency: 0,
+            loss: 0.0,
+            rng,
+        }
+    }
+
+    pub fn set_latency(&mut self, ticks: u64) {
+        self.latency = ticks;
+    }
+
+    pub fn set_loss(&mut self, loss: f32) {
+        assert!(loss >= 0.0 && loss <= 1.0);
+        self.loss = loss;
+    }
+
+    /// Plugs in `card`, and returns the address it's been given.
+    pub fn connect(&mut self, card: Rc<RefCell<NetworkCard>>) -> u16 {
+        let address = self.cards.len() as u16 + 1;
+        assert!(address != BROADCAST_ADDRESS);
+        card.borrow_mut().set_address(address);
+        self.cards.push(card);
+        address
+    }
+
+    /// Picks up the packets that have been sent since the last tick, and delivers the
+    /// ones that have arrived.
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+
+        for card in &self.cards {
+            while let Some(packet) = card.borrow_mut().take_sent() {
+                if self.loss > 0.0 && self.rng.borrow_mut().next_f32() < self.loss {
+                    continue;
+                }
+                self.in_flight.push((self.ticks + self.latency, packet));
+            }
+        }
+
+        let ticks = self.ticks;
+        let (arrived, in_flight) = self.in_flight.drain(..)
+            .partition(|&(arrival, _)| arrival <= ticks);
+        self.in_flight = in_flight;
+        for (_, packet) in arrived {
+            for card in &self.cards {
+                let address = card.borrow().address();
+                let broadcast = packet.to == BROADCAST_ADDRESS && address != packet.from;
+                if address == packet.to || broadcast {
+                    card.borrow_mut().deliver(packet.clone());
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::IsaacRng;
+
+    use super::*;
+    use hardware::dcpu::{with_device, Dcpu};
+    use hardware::dcpu::register::Register;
+
+    fn send(card: &Rc<RefCell<NetworkCard>>, to: u16) {
+        let from = card.borrow().address();
+        card.borrow_mut().sent.push_back(Packet {
+            from,
+            to,
+            words: vec![from],
+        });
+    }
+
+    fn received(card: &Rc<RefCell<NetworkCard>>) -> Vec<u16> {
+        card.borrow_mut().received.drain(..).map(|p| p.from).collect()
+    }
+
+    #[test]
+    fn routing() {
+        let cards: Vec<_> = (0..3).map(|_| Rc::new(RefCell::new(NetworkCard::new())))
+            .collect();
+        let mut switch = Switch::new(Rc::new(RefCell::new(IsaacRng::new_unseeded())));
+        for card in &cards {
+            switch.connect(card.clone());
+        }
+        switch.set_latency(2);
+
+        send(&cards[0], 2);
+        send(&cards[1], BROADCAST_ADDRESS);
+        send(&cards[2], 9);
+        switch.tick();
+        assert_eq!(received(&cards[1]), vec![]);
+        switch.tick();
+        switch.tick();
+        assert_eq!(received(&cards[0]), vec![2]);
+        assert_eq!(received(&cards[1]), vec![1]);
+        assert_eq!(received(&cards[2]), vec![2]);
+
+        switch.set_loss(1.0);
+        send(&cards[0], 2);
+        for _ in 0..3 {
+            switch.tick();
+        }
+        assert_eq!(received(&cards[1]), vec![]);
+    }
+
+    /// Returns a computer running `program_src`, with a network card plugged into it.
+    fn computer(program_src: &str) -> (Dcpu, Rc<RefCell<NetworkCard>>) {
+        let card = Rc::new(RefCell::new(NetworkCard::new()));
+        (with_device(card.clone(), program_src), card)
+    }
+
+    #[test]
+    fn two_computers() {
+        // Sends 16 packets to address 2, holding 0 to 15.
+        let (mut sender, sender_card) = computer("\
+:send
+set [0x1000], I
+set A, 1
+set B, 2
+set X, 0x1000
+set Y, 1
+hwi 0
+add I, 1
+ifl I, 16
+  set PC, send
+:done set PC, done
+");
+        // Copies each packet that arrives to [0x1000 + Z], counting them in Z.
+        let (mut receiver, receiver_card) = computer("\
+ias on_packet
+set A, 4
+set B, 1
+hwi 0
+:idle set PC, idle
+
+:on_packet
+set A, 2
+set X, 0x1000
+add X, Z
+set Y, 1
+hwi 0
+add Z, 1
+rfi 0
+");
+        let loss = 0.5;
+        let mut switch = Switch::new(Rc::new(RefCell::new(IsaacRng::new_unseeded())));
+        assert_eq!(switch.connect(sender_card), 1);
+        assert_eq!(switch.connect(receiver_card.clone()), 2);
+        switch.set_latency(3);
+        switch.set_loss(loss);
+        let mut run_frame = |sender: &mut Dcpu, receiver: &mut Dcpu| {
+            for _ in 0..1000 {
+                sender.tick();
+                receiver.tick();
+            }
+            switch.tick();
+        };
+
+        // Everything is sent in the first frame, and picked up when it ends, so it
+        // arrives three ticks later.
+        for _ in 0..3 {
+            run_frame(&mut sender, &mut receiver);
+        }
+        assert!(receiver_card.borrow().received.is_empty());
+        run_frame(&mut sender, &mut receiver);
+        assert!(!receiver_card.borrow().received.is_empty());
+        run_frame(&mut sender, &mut receiver);
+
+        // The same generator decides which packets are lost.
+        let mut rng = IsaacRng::new_unseeded();
+        let expected: Vec<u16> = (0..16).filter(|_| rng.next_f32() >= loss).collect();
+        assert!(!expected.is_empty() && expected.len() < 16);
+        let received = receiver.reg(Register::Z as u16);
+        let words: Vec<u16> = (0..received).map(|i| receiver.mem(0x1000 + i)).collect();
+        assert_eq!(words, expected);
+    }
+}
@@ -0,0 +1,130 @@
+//! A random number generator, which gives programs random words.
+//!
+//! Its words come from a generator it's given, which can be shared with the rest of the
+//! world, so a world that's seeded plays out the same way every time.  Programs can seed
+//! it too, after which it has a generator of its own.
+//!
+//! Interrupts, picked by `A`:
+//!
+//! * 0 - Sets `C` to a random word.
+//! * 1 - Fills the `Y` words at `X` with random words.
+//! * 2 - Seeds the generator with `B` and `C`, so it gives the same words every time it's
+//!   given the same seed.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use rand::{IsaacRng, Rng, SeedableRng};
+
+use hardware::dcpu::Dcpu;
+use hardware::dcpu::device::Device;
+use hardware::dcpu::register::Register;
+
+const ID: u32 = 0x3b2c7d0e;
+const VERSION: u16 = 1;
+const MANUFACTURER: u32 = 0;
+
+const NEXT_WORD: u16 = 0;
+const FILL: u16 = 1;
+const SEED: u16 = 2;
+
+
+pub struct HardwareRng {
+    source: Rc<RefCell<Rng>>,
+}
+
+impl HardwareRng {
+    /// Returns a generator that takes its words from `source`.
+    pub fn new(source: Rc<RefCell<Rng>>) -> HardwareRng {
+        HardwareRng {
+            source,
+        }
+    }
+
+    fn next_word(&mut self) -> u16 {
+        self.source.borrow_mut().next_u32() as u16
+    }
+}
+
+impl Device for HardwareRng {
+    fn id(&self) -> u32 {
+        ID
+    }
+
+    fn version(&self) -> u16 {
+        VERSION
+    }
+
+    fn manufacturer(&self) -> u32 {
+        MANUFACTURER
+    }
+
+    fn interrupt(&mut self, dcpu: &mut Dcpu) {
+        match dcpu.reg(Register::A as u16) {
+            NEXT_WORD => {
+                let word = self.next_word();
+                dcpu.set_reg(Register::C as u16, word);
+            },
+            FILL => {
+                let addr = dcpu.reg(Register::X as u16);
+                for i in 0..dcpu.reg(Register::Y as u16) {
+                    let word = self.next_word();
+                    dcpu.set_mem(addr.wrapping_add(i), word);
+                }
+            },
+            SEED => {
+                let seed = [dcpu.reg(Register::B as u16) as u32,
+                            dcpu.reg(Register::C as u16) as u32];
+                // ISAAC gives the same words on every platform, unlike `StdRng`.
+                self.source = Rc::new(RefCell::new(IsaacRng::from_seed(&seed[..])));
+            },
+            _ => (),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hardware::dcpu::with_device;
+
+    /// Runs a program that seeds the generator with 0x1234 and 0x5678 after taking two
+    /// words, and takes two more, leaving them in [0x1000..0x1004].
+    fn run(source: Rc<RefCell<Rng>>) -> Vec<u16> {
+        let mut dcpu = with_device(Rc::new(RefCell::new(HardwareRng::new(source))), "\
+set A, 1
+set X, 0x1000
+set Y, 2
+hwi 0
+set A, 2
+set B, 0x1234
+set C, 0x5678
+hwi 0
+set A, 0
+hwi 0
+set [0x1002], C
+hwi 0
+set [0x1003], C
+:wait set PC, wait
+");
+        for _ in 0..20 {
+            dcpu.tick();
+        }
+        (0x1000..0x1004).map(|i| dcpu.mem(i)).collect()
+    }
+
+    #[test]
+    fn seeding() {
+        let seeded = |seed| -> Rc<RefCell<Rng>> {
+            Rc::new(RefCell::new(IsaacRng::from_seed(&[seed][..])))
+        };
+        let words = run(seeded(1));
+        assert_eq!(run(seeded(1)), words);
+
+        // The program's seed decides everything after it's set.
+        let other_words = run(seeded(2));
+        assert!(other_words[..2] != words[..2]);
+        assert_eq!(other_words[2..], words[2..]);
+    }
+}
@@ -0,0 +1,332 @@
+//! The host's end of a serial port's cable, which carries bytes to and from a stream on
+//! the host.
+//!
+//! Streams are read and written on their own threads, so the DCPU never waits on them.
+
+#[cfg(unix)]
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often a TCP link checks for a connection while nothing's connected.
+const ACCEPT_INTERVAL_MS: u64 = 50;
+
+
+pub struct Link {
+    /// Bytes from the host.
+    incoming: Receiver<u8>,
+    /// Bytes for the host.
+    outgoing: Sender<u8>,
+    /// Writes bytes to the host's stream, if the link has one.
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Link {
+    /// Returns a link that carries bytes over channels, to and from whatever's on their
+    /// other ends.
+    pub fn new(incoming: Receiver<u8>, outgoing: Sender<u8>) -> Link {
+        Link {
+            incoming,
+            outgoing,
+            writer: None,
+        }
+    }
+
+    /// Returns a link that reads from `reader` and writes to `writer`.
+    pub fn streams<R, W>(reader: R, writer: W) -> Link
+        where R: Read + Send + 'static, W: Write + Send + 'static
+    {
+        let (incoming_sender, incoming) = mpsc::channel();
+        let (outgoing, outgoing_receiver) = mpsc::channel();
+        thread::spawn(move || read_into(reader, &incoming_sender));
+        let writer = thread::spawn(move || {
+            let mut writer = writer;
+            write_from(&outgoing_receiver, |bytes| {
+                writer.write_all(bytes).and_then(|_| writer.flush())
+            });
+        });
+        Link {
+            writer: Some(writer),
+            ..Link::new(incoming, outgoing)
+        }
+    }
+
+    pub fn stdio() -> Link {
+        Link::streams(io::stdin(), io::stdout())
+    }
+
+    /// Returns a link that listens on `addr` for TCP connections, and talks to one at a
+    /// time.  Bytes sent while nothing's connected are dropped.
+    ///
+    /// Once the link is dropped, it hangs up and stops listening, which frees `addr`.
+    pub fn tcp<A: ToSocketAddrs>(addr: A) -> io::Result<Link> {
+        let listener = TcpListener::bind(addr)?;
+        // Accepting can't be interrupted, so the listener polls, to notice the link going.
+        listener.set_nonblocking(true)?;
+        let (incoming_sender, incoming) = mpsc::channel();
+        let (outgoing, outgoing_receiver) = mpsc::channel();
+        let connection: Arc<Mutex<Option<TcpStream>>> = Arc::new(Mutex::new(None));
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let writer_connection = connection.clone();
+        let writer_closed = closed.clone();
+        let writer = thread::spawn(move || {
+            write_from(&outgoing_receiver, |bytes| {
+                if let Some(ref mut stream) = *writer_connection.lock().unwrap() {
+                    // A failed write means the connection's gone, which the reader
+                    // notices.
+                    let _ = stream.write_all(bytes);
+                }
+                Ok(())
+            });
+            // Writes never fail, so the link's been dropped.  Hanging up wakes the reader.
+            writer_closed.store(true, Ordering::SeqCst);
+            if let Some(ref stream) = *writer_connection.lock().unwrap() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        });
+        thread::spawn(move || {
+            while !closed.load(Ordering::SeqCst) {
+                let stream = match listener.accept() {
+                    Ok((s, _)) => s,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(ACCEPT_INTERVAL_MS));
+                        continue;
+                    },
+                    Err(_) => continue,
+                };
+                // Some platforms pass the listener's non-blocking mode on to connections.
+                if stream.set_nonblocking(false).is_err() {
+                    continue;
+                }
+                match stream.try_clone() {
+                    Ok(s) => {
+                        let mut connection = connection.lock().unwrap();
+                        // The writer may have just hung up, without seeing this one.
+                        if closed.load(Ordering::SeqCst) {
+                            return;
+                        }
+                        *connection = Some(s);
+                    },
+                    Err(_) => continue,
+                }
+                if !read_into(stream, &incoming_sender) {
+                    return;
+                }
+                *connection.lock().unwrap() = None;
+            }
+        });
+
+        Ok(Link {
+            writer: Some(writer),
+            ..Link::new(incoming, outgoing)
+        })
+    }
+
+    /// Returns a link to a new pseudo-terminal, along with the path of the terminal device
+    /// that tools on the host can open, like `/dev/pts/3`.
+    ///
+    /// The terminal is raw, so bytes go through it untouched.  Bytes sent while nothing
+    /// has it open wait in the terminal until something does.
+    #[cfg(unix)]
+    pub fn pty() -> io::Result<(Link, PathBuf)> {
+        use std::ffi::CStr;
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+        use libc;
+
+        let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = unsafe { File::from_raw_fd(fd) };
+        if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let name = unsafe { libc::ptsname(fd) };
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let name = unsafe { CStr::from_ptr(name) };
+        let path = PathBuf::from(name.to_string_lossy().into_owned());
+
+        // We hold the terminal open ourselves, so reading doesn't fail while nothing else
+        // has it open.
+        let terminal = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+        unsafe {
+            let mut attributes = ::std::mem::zeroed();
+            if libc::tcgetattr(terminal.as_raw_fd(), &mut attributes) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut attributes);
+            if libc::tcsetattr(terminal.as_raw_fd(), libc::TCSANOW, &attributes) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        // The writer lets go of the terminal once the link is dropped, which ends the
+        // reader too, unless something else still has it open.
+        let reader = master.try_clone()?;
+        let writer = PtyWriter {
+            master,
+            _terminal: terminal,
+        };
+        Ok((Link::streams(reader, writer), path))
+    }
+
+    /// Returns the next byte from the host, if one's come in.
+    pub fn receive(&self) -> Option<u8> {
+        self.incoming.try_recv().ok()
+    }
+
+    pub fn send(&self, byte: u8) {
+        // The host hanging up is just like the cable being pulled out, so there's nothing
+        // to do about it.
+        let _ = self.outgoing.send(byte);
+    }
+
+    /// Closes the link, waiting for the bytes that have been sent to be written.
+    pub fn close(self) {
+        let Link { outgoing, writer, .. } = self;
+        drop(outgoing);
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Writes to a pseudo-terminal, while holding its terminal end open.
+#[cfg(unix)]
+struct PtyWriter {
+    master: File,
+    _terminal: File,
+}
+
+#[cfg(unix)]
+impl Write for PtyWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.master.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.master.flush()
+    }
+}
+
+/// Passes bytes from `reader` on to `sender` until the stream ends.  Returns false if the
+/// link has been dropped.
+fn read_into<R: Read>(mut reader: R, sender: &Sender<u8>) -> bool {
+    let mut buffer = [0; 256];
+    loop {
+        let len = match reader.read(&mut buffer) {
+            Ok(0) | Err(_) => return true,
+            Ok(len) => len,
+        };
+        for &byte in &buffer[..len] {
+            if sender.send(byte).is_err() {
+                return false;
+            }
+        }
+    }
+}
+
+/// Writes bytes from `receiver` with `write`, a batch at a time, until the link is
+/// dropped or a write fails.
+fn write_from<F: FnMut(&[u8]) -> io::Result<()>>(receiver: &Receiver<u8>, mut write: F) {
+    while let Ok(byte) = receiver.recv() {
+        let mut bytes = vec![byte];
+        bytes.extend(receiver.try_iter());
+        if write(&bytes).is_err() {
+            return;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    /// Returns an address on the loopback interface that nothing's listening on.
+    fn free_address() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    /// Calls `f` until it returns something, giving up after a few seconds.
+    fn wait_for<T, F: FnMut() -> Option<T>>(mut f: F) -> T {
+        let start = Instant::now();
+        loop {
+            if let Some(value) = f() {
+                return value;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "Timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn tcp() {
+        let addr = free_address();
+        let link = Link::tcp(addr).unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"hi").unwrap();
+        assert_eq!(wait_for(|| link.receive()), b'h');
+        assert_eq!(wait_for(|| link.receive()), b'i');
+        // The link only knows about the connection once it's been accepted, which it has
+        // by the time bytes come in.
+        link.send(b'!');
+        let mut buffer = [0; 1];
+        client.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"!");
+
+        // Dropping the link hangs up, and frees the address.
+        drop(link);
+        assert_eq!(client.read(&mut buffer).unwrap(), 0);
+        wait_for(|| TcpListener::bind(addr).ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pty() {
+        use std::fs::OpenOptions;
+
+        let (link, path) = Link::pty().unwrap();
+        let mut terminal = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        terminal.write_all(b"a\n").unwrap();
+        assert_eq!(wait_for(|| link.receive()), b'a');
+        // Newlines aren't turned into anything else.
+        assert_eq!(wait_for(|| link.receive()), b'\n');
+
+        link.send(b'\n');
+        let mut buffer = [0; 1];
+        terminal.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"\n");
+        // Nothing was echoed back.
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(link.receive(), None);
+    }
+
+    #[test]
+    fn tcp_without_connection() {
+        let addr = free_address();
+        let link = Link::tcp(addr).unwrap();
+        assert!(TcpListener::bind(addr).is_err());
+        drop(link);
+        wait_for(|| TcpListener::bind(addr).ok());
+    }
+}
@@ -0,0 +1,240 @@
+//! A serial port, which sends and receives bytes over a link to a stream on the host,
+//! like a terminal, a pipe or a socket.
+//!
+//! Like a real UART, it moves a byte at a time: only the low byte of each word sent goes
+//! out, and each byte received comes in as a word from 0 to 255.  It moves one byte each
+//! way every `CYCLES_PER_BYTE` cycles, and buffers up to `BUFFER_CAPACITY` bytes each way.
+//!
+//! Interrupts, picked by `A`:
+//!
+//! * 0 - Sends `B`, setting `C` to 1 if it was sent, or 0 if the send buffer is full.
+//! * 1 - Takes the next received byte out of the buffer and puts it in `C`, setting `B`
+//!   to 1, or sets both to 0 if there isn't one.
+//! * 2 - Sets `B` to the number of received bytes waiting, and `C` to how many more bytes
+//!   the send buffer has room for.
+//! * 3 - Turns on interrupts with message `B`, which are sent whenever a byte comes in,
+//!   or turns them off if `B` is 0.
+//! * 4 - Turns on interrupts with message `B`, which are sent whenever a send fills the
+//!   send buffer, or turns them off if `B` is 0.
+
+pub mod link;
+
+use std::collections::VecDeque;
+
+use hardware::dcpu::Dcpu;
+use hardware::dcpu::device::Device;
+use hardware::dcpu::register::Register;
+
+use self::link::Link;
+
+const ID: u32 = 0xe57d9027;
+const VERSION: u16 = 1;
+const MANUFACTURER: u32 = 0;
+
+const SEND: u16 = 0;
+const RECEIVE: u16 = 1;
+const GET_STATUS: u16 = 2;
+const SET_RECEIVE_MESSAGE: u16 = 3;
+const SET_FULL_MESSAGE: u16 = 4;
+
+/// The most bytes each buffer holds.
+const BUFFER_CAPACITY: usize = 64;
+/// Makes for 1000 bytes a second.
+const CYCLES_PER_BYTE: u64 = 100;
+
+
+pub struct Serial {
+    /// Where bytes go, or nowhere if nothing's plugged in.
+    link: Option<Link>,
+    /// Bytes from the link, oldest first.
+    received: VecDeque<u16>,
+    /// Bytes for the link, oldest first.
+    to_send: VecDeque<u16>,
+    /// When the next byte can move each way, in DCPU cycles.
+    next_transfer_cycle: u64,
+    /// Sent whenever a byte comes in, or 0 if those interrupts are off.
+    receive_message: u16,
+    /// Sent whenever the send buffer fills up, or 0 if those interrupts are off.
+    full_message: u16,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            link: None,
+            received: VecDeque::new(),
+            to_send: VecDeque::new(),
+            next_transfer_cycle: 0,
+            receive_message: 0,
+            full_message: 0,
+        }
+    }
+
+    /// Plugs the port into `link`, giving back the link it was plugged into, if there was
+    /// one.
+    pub fn connect(&mut self, link: Link) -> Option<Link> {
+        self.link.replace(link)
+    }
+
+    pub fn disconnect(&mut self) -> Option<Link> {
+        self.link.take()
+    }
+
+    fn send(&mut self, byte: u16, dcpu: &mut Dcpu) -> bool {
+        if self.to_send.len() == BUFFER_CAPACITY {
+            return false;
+        }
+        self.to_send.push_back(byte & 0xff);
+        if self.to_send.len() == BUFFER_CAPACITY && self.full_message != 0 {
+            dcpu.interrupt(self.full_message);
+        }
+        true
+    }
+}
+
+impl Device for Serial {
+    fn id(&self) -> u32 {
+        ID
+    }
+
+    fn version(&self) -> u16 {
+        VERSION
+    }
+
+    fn manufacturer(&self) -> u32 {
+        MANUFACTURER
+    }
+
+    fn interrupt(&mut self, dcpu: &mut Dcpu) {
+        let b = dcpu.reg(Register::B as u16);
+        match dcpu.reg(Register::A as u16) {
+            SEND => {
+                let sent = self.send(b, dcpu);
+                dcpu.set_reg(Register::C as u16, sent as u16);
+            },
+            RECEIVE => {
+                let byte = self.received.pop_front();
+                dcpu.set_reg(Register::B as u16, byte.is_some() as u16);
+                dcpu.set_reg(Register::C as u16, byte.unwrap_or(0));
+            },
+            GET_STATUS => {
+                dcpu.set_reg(Register::B as u16, self.received.len() as u16);
+                let room = BUFFER_CAPACITY - self.to_send.len();
+                dcpu.set_reg(Register::C as u16, room as u16);
+            },
+            SET_RECEIVE_MESSAGE => self.receive_message = b,
+            SET_FULL_MESSAGE => self.full_message = b,
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, dcpu: &mut Dcpu) {
+        if dcpu.cycles() < self.next_transfer_cycle {
+            return;
+        }
+        self.next_transfer_cycle = dcpu.cycles() + CYCLES_PER_BYTE;
+
+        // Bytes sent while nothing's plugged in are lost, like they would be down a loose
+        // cable.
+        if let Some(byte) = self.to_send.pop_front() {
+            if let Some(ref link) = self.link {
+                link.send(byte as u8);
+            }
+        }
+        if self.received.len() == BUFFER_CAPACITY {
+            return;
+        }
+        let byte = match self.link {
+            Some(ref link) => link.receive(),
+            None => None,
+        };
+        if let Some(byte) = byte {
+            self.received.push_back(byte as u16);
+            if self.receive_message != 0 {
+                dcpu.interrupt(self.receive_message);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+
+    use super::*;
+    use hardware::dcpu::with_device;
+
+    #[test]
+    fn echo() {
+        let (host_sender, incoming) = mpsc::channel();
+        let (outgoing, host_receiver) = mpsc::channel();
+        let serial = Rc::new(RefCell::new(Serial::new()));
+        serial.borrow_mut().connect(Link::new(incoming, outgoing));
+        // Sends back each byte it receives, in upper case, including any that came in
+        // before interrupts were turned on.
+        let mut dcpu = with_device(serial.clone(), "\
+ias on_receive
+set A, 3
+set B, 0x42
+hwi 0
+:wait set PC, wait
+
+:on_receive
+set A, 1
+hwi 0
+ife B, 0
+  rfi 0
+ifg C, 0x60
+  sub C, 0x20
+set B, C
+set A, 0
+hwi 0
+set PC, on_receive
+");
+
+        for &byte in b"hi!" {
+            host_sender.send(byte).unwrap();
+        }
+        while dcpu.cycles() < 1000 {
+            dcpu.tick();
+        }
+        let echoed: Vec<u8> = host_receiver.try_iter().collect();
+        assert_eq!(echoed, b"HI!");
+    }
+
+    #[test]
+    fn full_buffer() {
+        // Sends as fast as it can, counting bytes sent in X, until the buffer fills up
+        // and sets Y.
+        let mut dcpu = with_device(Rc::new(RefCell::new(Serial::new())), "\
+ias on_full
+set A, 4
+set B, 0x42
+hwi 0
+:send
+ifn Y, 0
+  set PC, wait
+set A, 0
+set B, 0x1234
+hwi 0
+add X, C
+set PC, send
+:wait set PC, wait
+
+:on_full
+set Y, 1
+rfi 0
+");
+
+        while dcpu.cycles() < 1000 {
+            dcpu.tick();
+        }
+        // A few bytes went out while the buffer was filling up.
+        let sent = dcpu.reg(Register::X as u16) as usize;
+        assert!(sent > BUFFER_CAPACITY && sent < BUFFER_CAPACITY + 10);
+        assert_eq!(dcpu.reg(Register::Y as u16), 1);
+    }
+}
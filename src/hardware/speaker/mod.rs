@@ -0,0 +1,254 @@
+//! A speaker with three channels, a square wave, a triangle wave and noise, which plays
+//! them mixed together as 16-bit mono PCM.
+//!
+//! Samples are made as the DCPU runs, `SAMPLE_RATE` for every second's worth of cycles,
+//! so sound lines up with the program that's playing it however fast the game runs.
+//! Whatever owns the speaker takes the samples to play them, or to write them to a WAV
+//! file with `write_wav`.
+//!
+//! Interrupts, picked by `A`:
+//!
+//! * 0 - Sets the frequency of channel `B` to `C` Hz, or silences it if `C` is 0.
+//! * 1 - Sets the volume of channel `B` to `C`, from 0 to 255.
+//! * 2 - Silences every channel.
+//!
+//! The noise channel changes to a new random level `C` times a second.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use hardware::dcpu::{self, Dcpu};
+use hardware::dcpu::device::Device;
+use hardware::dcpu::register::Register;
+
+pub const SAMPLE_RATE: u32 = 22050;
+
+pub const SQUARE: u16 = 0;
+pub const TRIANGLE: u16 = 1;
+pub const NOISE: u16 = 2;
+
+const ID: u32 = 0x02060001;
+const VERSION: u16 = 1;
+const MANUFACTURER: u32 = 0;
+
+const SET_FREQUENCY: u16 = 0;
+const SET_VOLUME: u16 = 1;
+const SILENCE: u16 = 2;
+
+const NUM_CHANNELS: usize = 3;
+/// The most samples that are kept for whatever owns the speaker, which is a second's
+/// worth.  Older ones are dropped if they aren't taken in time.
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize;
+
+
+#[derive(Clone, Copy)]
+struct Channel {
+    frequency: u16,
+    volume: u16,
+    /// How far through a cycle of the wave the channel is, out of 2^32.
+    phase: u32,
+}
+
+impl Channel {
+    /// Moves on by a sample, returning whether the wave started a new cycle.
+    fn advance(&mut self) -> bool {
+        let step = ((self.frequency as u64) << 32) / SAMPLE_RATE as u64;
+        let (phase, wrapped) = self.phase.overflowing_add(step as u32);
+        self.phase = phase;
+        wrapped
+    }
+}
+
+
+pub struct Speaker {
+    channels: [Channel; NUM_CHANNELS],
+    /// The noise channel's linear feedback shift register, like the NES's.
+    noise_register: u16,
+    /// Samples made so far, counting from when the DCPU started.
+    samples_made: u64,
+    /// Samples waiting to be taken, oldest first.
+    samples: VecDeque<i16>,
+}
+
+impl Speaker {
+    pub fn new() -> Speaker {
+        let channel = Channel {
+            frequency: 0,
+            volume: 0,
+            phase: 0,
+        };
+        Speaker {
+            channels: [channel; NUM_CHANNELS],
+            noise_register: 1,
+            samples_made: 0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Takes the samples that have been made since this was last called.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.samples.drain(..).collect()
+    }
+
+    fn next_sample(&mut self) -> i16 {
+        let mut mixed = 0.0;
+        for i in 0..NUM_CHANNELS {
+            let channel = self.channels[i];
+            if channel.frequency == 0 || channel.volume == 0 {
+                continue;
+            }
+            let level = match i as u16 {
+                SQUARE => if channel.phase < 1 << 31 { 1.0 } else { -1.0 },
+                TRIANGLE => {
+                    let phase = channel.phase as f32 / 4294967296.0;
+                    1.0 - 4.0 * (phase - 0.5).abs()
+                },
+                _ => if self.noise_register & 0x1 == 1 { 1.0 } else { -1.0 },
+            };
+            mixed += level * channel.volume as f32 / 255.0;
+
+            if self.channels[i].advance() && i as u16 == NOISE {
+                let feedback = (self.noise_register ^ (self.noise_register >> 1)) & 0x1;
+                self.noise_register = (self.noise_register >> 1) | (feedback << 14);
+            }
+        }
+        // Every channel can be at full volume at once without clipping.
+        (mixed / NUM_CHANNELS as f32 * i16::max_value() as f32) as i16
+    }
+}
+
+impl Device for Speaker {
+    fn id(&self) -> u32 {
+        ID
+    }
+
+    fn version(&self) -> u16 {
+        VERSION
+    }
+
+    fn manufacturer(&self) -> u32 {
+        MANUFACTURER
+    }
+
+    fn interrupt(&mut self, dcpu: &mut Dcpu) {
+        let b = dcpu.reg(Register::B as u16) as usize;
+        let c = dcpu.reg(Register::C as u16);
+        match dcpu.reg(Register::A as u16) {
+            SET_FREQUENCY if b < NUM_CHANNELS => self.channels[b].frequency = c,
+            SET_VOLUME if b < NUM_CHANNELS => self.channels[b].volume = c.min(255),
+            SILENCE => {
+                for channel in self.channels.iter_mut() {
+                    channel.volume = 0;
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, dcpu: &mut Dcpu) {
+        let end = dcpu.cycles() * SAMPLE_RATE as u64 / dcpu::CLOCK_SPEED;
+        while self.samples_made < end {
+            let sample = self.next_sample();
+            if self.samples.len() == MAX_BUFFERED_SAMPLES {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(sample);
+            self.samples_made += 1;
+        }
+    }
+}
+
+/// Writes `samples` as a mono WAV file at `SAMPLE_RATE`.
+pub fn write_wav<W: Write>(writer: &mut W, samples: &[i16]) -> io::Result<()> {
+    let data_size = 2 * samples.len() as u32;
+    let mut bytes = Vec::with_capacity(44 + data_size as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&le_u32(36 + data_size));
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&le_u32(16));
+    // PCM, with one channel.
+    bytes.extend_from_slice(&le_u16(1));
+    bytes.extend_from_slice(&le_u16(1));
+    bytes.extend_from_slice(&le_u32(SAMPLE_RATE));
+    // Bytes a second, bytes a sample and bits a sample.
+    bytes.extend_from_slice(&le_u32(2 * SAMPLE_RATE));
+    bytes.extend_from_slice(&le_u16(2));
+    bytes.extend_from_slice(&le_u16(16));
+
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&le_u32(data_size));
+    for &sample in samples {
+        bytes.extend_from_slice(&le_u16(sample as u16));
+    }
+    writer.write_all(&bytes)
+}
+
+fn le_u16(n: u16) -> [u8; 2] {
+    [n as u8, (n >> 8) as u8]
+}
+
+fn le_u32(n: u32) -> [u8; 4] {
+    [n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs::File;
+    use std::rc::Rc;
+
+    use super::*;
+    use hardware::dcpu::with_device;
+
+    /// What `chord` should sound like.  If the synthesis changes on purpose, the test
+    /// writes what it got to the temp directory, to be listened to and copied over this.
+    const CHORD_WAV: &[u8] = include_bytes!("chord.wav");
+
+    #[test]
+    fn chord() {
+        let speaker = Rc::new(RefCell::new(Speaker::new()));
+        // Plays an A and the E above it on the square and triangle channels, with quieter
+        // noise, for a twentieth of a second.
+        let mut dcpu = with_device(speaker.clone(), "\
+set A, 0
+set B, 0
+set C, 440
+hwi 0
+set B, 1
+set C, 660
+hwi 0
+set B, 2
+set C, 4000
+hwi 0
+set A, 1
+set B, 0
+set C, 255
+hwi 0
+set B, 1
+hwi 0
+set B, 2
+set C, 64
+hwi 0
+:wait set PC, wait
+");
+        while dcpu.cycles() < dcpu::CLOCK_SPEED / 20 {
+            dcpu.tick();
+        }
+
+        let samples = speaker.borrow_mut().take_samples();
+        assert_eq!(samples.len(), SAMPLE_RATE as usize / 20);
+        let mut wav = Vec::new();
+        write_wav(&mut wav, &samples).unwrap();
+        if &wav[..] != CHORD_WAV {
+            let path = env::temp_dir().join("chord.wav");
+            File::create(&path).and_then(|mut f| f.write_all(&wav)).unwrap();
+            panic!("The chord doesn't sound right; it's been written to {}",
+                   path.display());
+        }
+        assert_eq!(speaker.borrow_mut().take_samples(), vec![]);
+    }
+}
@@ -0,0 +1,240 @@
+//! The SPED-3, a projector that draws a model made of lines in the air above it, and
+//! turns it to face whichever way programs ask.
+//!
+//! Interrupts, picked by `A`:
+//!
+//! * 0 - Sets `B` to the state (0 if there's nothing to show, 1 if it's showing a model
+//!   and 2 if it's turning) and `C` to the last error, which is always 0.
+//! * 1 - Shows the `Y` vertices at `X`, up to 128, or nothing if `Y` is 0.
+//! * 2 - Turns the model until it's `X` degrees round, at 50 degrees a second.
+//!
+//! Each vertex is two words, `yyyy yyyy xxxx xxxx` and `0000 0icc zzzz zzzz`, where the
+//! color `c` is black, red, green or blue, and `i` makes it brighter.  A line is drawn
+//! from each vertex to the next in the color of the one it goes to, so black vertices
+//! move the beam without drawing anything.
+//!
+//! Turning goes by the DCPU's cycle count, like the Generic Clock does.
+
+use hardware::dcpu::{self, Dcpu};
+use hardware::dcpu::device::Device;
+use hardware::dcpu::register::Register;
+
+pub const STATE_NO_DATA: u16 = 0;
+pub const STATE_RUNNING: u16 = 1;
+pub const STATE_TURNING: u16 = 2;
+
+const ID: u32 = 0x42babf3c;
+const VERSION: u16 = 0x0003;
+const MANUFACTURER: u32 = 0x1eb37e91;
+
+const POLL: u16 = 0;
+const MAP_REGION: u16 = 1;
+const ROTATE: u16 = 2;
+
+const ERROR_NONE: u16 = 0;
+
+/// The most vertices the projector can show.
+const MAX_VERTICES: u16 = 128;
+const DEGREES_PER_SECOND: f32 = 50.0;
+
+/// What the intensity bit picks between for each color channel.
+const DIM: u8 = 0x80;
+const BRIGHT: u8 = 0xff;
+
+
+/// A line in the model, with each end's X, Y and Z from 0 to 255.
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    pub from: [u8; 3],
+    pub to: [u8; 3],
+    pub color: [u8; 3],
+}
+
+
+pub struct Sped3 {
+    vertex_addr: u16,
+    num_vertices: u16,
+    /// Where the model was facing when it last started turning, in degrees.
+    start_angle: f32,
+    /// When the model last started turning, in DCPU cycles.
+    start_cycle: u64,
+    /// Where the model is turning to, in degrees.
+    target_angle: u16,
+}
+
+impl Sped3 {
+    pub fn new() -> Sped3 {
+        Sped3 {
+            vertex_addr: 0,
+            num_vertices: 0,
+            start_angle: 0.0,
+            start_cycle: 0,
+            target_angle: 0,
+        }
+    }
+
+    /// Returns which way the model is facing, in degrees from 0 to 360.
+    pub fn angle(&self, dcpu: &Dcpu) -> f32 {
+        let to_turn = self.to_turn();
+        let turned = self.turned(dcpu);
+        if turned >= to_turn.abs() {
+            self.target_angle as f32
+        } else {
+            (self.start_angle + turned * to_turn.signum() + 360.0) % 360.0
+        }
+    }
+
+    /// Returns the lines in the model, leaving out black ones.
+    pub fn lines(&self, dcpu: &Dcpu) -> Vec<Line> {
+        let vertices: Vec<([u8; 3], [u8; 3])> = (0..self.num_vertices)
+            .map(|i| {
+                let addr = self.vertex_addr.wrapping_add(2 * i);
+                Self::decode_vertex(dcpu.mem(addr), dcpu.mem(addr.wrapping_add(1)))
+            })
+            .collect();
+        vertices.windows(2)
+            .filter(|pair| pair[1].1 != [0, 0, 0])
+            .map(|pair| Line {
+                from: pair[0].0,
+                to: pair[1].0,
+                color: pair[1].1,
+            })
+            .collect()
+    }
+
+    fn state(&self, dcpu: &Dcpu) -> u16 {
+        if self.num_vertices == 0 {
+            STATE_NO_DATA
+        } else if self.turned(dcpu) < self.to_turn().abs() {
+            STATE_TURNING
+        } else {
+            STATE_RUNNING
+        }
+    }
+
+    /// Returns how far the model has to turn from where it started, going whichever way
+    /// is shortest.
+    fn to_turn(&self) -> f32 {
+        let to_turn = (self.target_angle as f32 - self.start_angle) % 360.0;
+        if to_turn > 180.0 {
+            to_turn - 360.0
+        } else if to_turn <= -180.0 {
+            to_turn + 360.0
+        } else {
+            to_turn
+        }
+    }
+
+    /// Returns how many degrees the model could have turned since it started turning.
+    fn turned(&self, dcpu: &Dcpu) -> f32 {
+        let elapsed = dcpu.cycles() - self.start_cycle;
+        elapsed as f32 * DEGREES_PER_SECOND / dcpu::CLOCK_SPEED as f32
+    }
+
+    /// Returns the position and color of a vertex.
+    fn decode_vertex(first: u16, second: u16) -> ([u8; 3], [u8; 3]) {
+        let position = [first as u8, (first >> 8) as u8, second as u8];
+        let intensity = if (second >> 10) & 0b1 == 1 { BRIGHT } else { DIM };
+        let color = match (second >> 8) & 0b11 {
+            0 => [0, 0, 0],
+            1 => [intensity, 0, 0],
+            2 => [0, intensity, 0],
+            _ => [0, 0, intensity],
+        };
+        (position, color)
+    }
+}
+
+impl Device for Sped3 {
+    fn id(&self) -> u32 {
+        ID
+    }
+
+    fn version(&self) -> u16 {
+        VERSION
+    }
+
+    fn manufacturer(&self) -> u32 {
+        MANUFACTURER
+    }
+
+    fn interrupt(&mut self, dcpu: &mut Dcpu) {
+        let x = dcpu.reg(Register::X as u16);
+        match dcpu.reg(Register::A as u16) {
+            POLL => {
+                let state = self.state(dcpu);
+                dcpu.set_reg(Register::B as u16, state);
+                dcpu.set_reg(Register::C as u16, ERROR_NONE);
+            },
+            MAP_REGION => {
+                self.vertex_addr = x;
+                self.num_vertices = dcpu.reg(Register::Y as u16).min(MAX_VERTICES);
+            },
+            ROTATE => {
+                self.start_angle = self.angle(dcpu);
+                self.start_cycle = dcpu.cycles();
+                self.target_angle = x % 360;
+            },
+            _ => (),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use hardware::dcpu::with_device;
+
+    #[test]
+    fn turning() {
+        let sped = Rc::new(RefCell::new(Sped3::new()));
+        // Shows a red line and a bright blue one, with a black one between them, then
+        // turns to 350 degrees and polls until it's done, counting polls in I.
+        let mut dcpu = with_device(sped.clone(), "\
+set A, 1
+set X, model
+set Y, 4
+hwi 0
+set A, 2
+set X, 710
+hwi 0
+:poll
+add I, 1
+set A, 0
+hwi 0
+ife B, 2
+  set PC, poll
+:wait set PC, wait
+
+:model
+dat 0x0000, 0x0000
+dat 0xff00, 0x0140
+dat 0xffff, 0x00ff
+dat 0x00ff, 0x0700
+");
+
+        for _ in 0..20 {
+            dcpu.tick();
+        }
+        assert_eq!(dcpu.reg(Register::B as u16), STATE_TURNING);
+        let lines = sped.borrow().lines(&dcpu);
+        assert_eq!(lines, vec![
+            Line { from: [0, 0, 0], to: [0, 0xff, 0x40], color: [DIM, 0, 0] },
+            Line { from: [0xff, 0xff, 0xff], to: [0xff, 0, 0], color: [0, 0, BRIGHT] },
+        ]);
+
+        // It goes the short way round, taking a fifth of a second to turn 10 degrees.
+        let angle = sped.borrow().angle(&dcpu);
+        assert!(angle > 359.0 && angle < 360.0);
+        while dcpu.cycles() < dcpu::CLOCK_SPEED / 5 + 100 {
+            dcpu.tick();
+        }
+        assert_eq!(dcpu.reg(Register::B as u16), STATE_RUNNING);
+        assert_eq!(sped.borrow().angle(&dcpu), 350.0);
+        assert!(dcpu.reg(Register::I as u16) > 1);
+    }
+}
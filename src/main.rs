@@ -2,11 +2,16 @@ extern crate cgmath;
 extern crate gl;
 extern crate glutin;
 extern crate image;
+#[cfg(unix)]
+extern crate libc;
 extern crate rand;
+extern crate rodio;
 extern crate tobj;
 
+use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::process;
 
 pub mod entity;
 pub mod game;
@@ -15,8 +20,67 @@ pub mod hardware;
 pub mod util;
 pub mod world;
 
+const USAGE: &str = "usage: trillek [--seed N] [--disk PATH] [--serial ADDR|pty]";
+
 fn main() {
-    let mut game = game::Game::new();
+    let mut options = world::WorldOptions::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            // Everything random in the world comes from a generator seeded with `N`, so
+            // runs can be reproduced.
+            match args.next().and_then(|value| value.parse().ok()) {
+                Some(value) => options.seed = Some(value),
+                None => {
+                    eprintln!("error: --seed needs a number");
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                },
+            }
+        } else if arg == "--disk" {
+            // The first terminal starts with the image at `PATH` in its floppy drive,
+            // and writes go back to the file.
+            let path = match args.next() {
+                Some(path) => path,
+                None => {
+                    eprintln!("error: --disk needs a path");
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                },
+            };
+            match hardware::m35fd::Disk::open(&path) {
+                Ok(disk) => options.disk = Some(disk),
+                Err(e) => {
+                    eprintln!("error: Couldn't open {}: {}", path, e);
+                    process::exit(1);
+                },
+            }
+        } else if arg == "--serial" {
+            // Tools on the host can connect to the terminals' serial ports over TCP, the
+            // first at `ADDR`, like 127.0.0.1:6502, and the rest on the ports after it.
+            // With `pty`, each one gets a pseudo-terminal instead.
+            let ports = match args.next() {
+                #[cfg(unix)]
+                Some(ref value) if value == "pty" => Some(world::SerialPorts::Pty),
+                Some(value) => value.parse().ok().map(world::SerialPorts::Tcp),
+                None => None,
+            };
+            match ports {
+                Some(ports) => options.serial_ports = Some(ports),
+                None => {
+                    eprintln!("error: --serial needs an address, like 127.0.0.1:6502");
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                },
+            }
+        } else {
+            eprintln!("error: Unknown option \"{}\"", arg);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+
+    let mut game = game::Game::new(options);
     game.run();
 }
 
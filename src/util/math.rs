@@ -96,6 +96,13 @@ impl Transformation {
         self.cached = None;
     }
 
+    /// Rotates to `axes`, with the i'th component being the angle about the i'th axis.
+    /// Like `move_to`, this is absolute and not relative to the mesh's current rotation.
+    pub fn rotate_to(&mut self, axes: Vector3) {
+        self.rotation = axes;
+        self.cached = None;
+    }
+
     /// Rotates the i'th axis by a magnitude equal to the i'th component of `axes`.
     pub fn rotate(&mut self, axes: Vector3) {
         self.rotation += axes;
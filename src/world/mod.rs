@@ -7,7 +7,12 @@ pub use self::tick::*;
 mod render;
 pub use self::render::*;
 
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+
 use glutin::VirtualKeyCode;
+use rand::{self, IsaacRng, Rng, SeedableRng};
 
 use entity::player::Player;
 use entity::Entity;
@@ -16,23 +21,90 @@ use graphics::Display;
 use graphics::renderer::Renderer;
 use game::camera::Camera;
 use hardware::lem::Lem;
+use hardware::m35fd::Disk;
+use hardware::network::Switch;
+use hardware::serial::link::Link;
 use util::collide::Collide;
 use util::math::Point3;
 use util::debug::DebugState;
 
 use self::collidable::{cube, obj, rect};
 
+// How many ticks packets take to get between computers, and the chance of each one being
+// lost.
+const NETWORK_LATENCY: u64 = 3;
+const NETWORK_LOSS: f32 = 0.0;
+
+
+/// How to set up a new world.
+#[derive(Default)]
+pub struct WorldOptions {
+    /// Seeds everything random in the world, so it plays out the same way every time.
+    pub seed: Option<u32>,
+    /// Starts out in the first terminal's floppy drive.
+    pub disk: Option<Disk>,
+    /// Where terminals' serial ports connect to on the host.  They aren't connected to
+    /// anything without it.
+    pub serial_ports: Option<SerialPorts>,
+}
+
+/// How tools on the host can get at terminals' serial ports.
+pub enum SerialPorts {
+    /// Over TCP, with the first terminal listening at the address, and each terminal
+    /// after it on the next port up.
+    Tcp(SocketAddr),
+    /// Through a new pseudo-terminal for each terminal.
+    #[cfg(unix)]
+    Pty,
+}
+
+impl WorldOptions {
+    /// Connects the `n`th terminal's serial port to the host, if it connects anywhere.
+    fn serial_link(&self, n: u16) -> Option<Link> {
+        match self.serial_ports {
+            None => None,
+            Some(SerialPorts::Tcp(addr)) => {
+                let addr = SocketAddr::new(addr.ip(), addr.port().checked_add(n)?);
+                match Link::tcp(addr) {
+                    Ok(link) => {
+                        println!("Terminal {}'s serial port is listening on {}", n + 1, addr);
+                        Some(link)
+                    },
+                    Err(e) => {
+                        eprintln!("Couldn't listen for serial connections on {}: {}", addr, e);
+                        None
+                    },
+                }
+            },
+            #[cfg(unix)]
+            Some(SerialPorts::Pty) => match Link::pty() {
+                Ok((link, path)) => {
+                    println!("Terminal {}'s serial port is at {}", n + 1, path.display());
+                    Some(link)
+                },
+                Err(e) => {
+                    eprintln!("Couldn't make a pseudo-terminal for a serial port: {}", e);
+                    None
+                },
+            },
+        }
+    }
+}
 
 pub struct World {
     player: Player,
     collidables: Vec<Box<Collide>>,
     entities: Vec<Box<Entity>>,
+    /// Connects every computer in the world.
+    switch: Switch,
     renderer: Renderer,
     debug_state: DebugState,
 }
 
 impl World {
-    pub fn new(player: Player, display: Display) -> World {
+    /// Returns a new world.  Everything random in it comes from one generator, which is
+    /// seeded with `options.seed` if it's given.
+    pub fn new(player: Player, display: Display, mut options: WorldOptions) -> World {
         let mut collidables: Vec<Box<Collide>> = Vec::new();
 
         collidables.push(Box::new(cube::new(2.0, Point3 {
@@ -104,18 +176,45 @@ impl World {
             z: 17.0,
         })));
 
+        let rng: Rc<RefCell<Rng>> = match options.seed {
+            Some(seed) => Rc::new(RefCell::new(IsaacRng::from_seed(&[seed][..]))),
+            None => Rc::new(RefCell::new(rand::thread_rng())),
+        };
+
+        let mut switch = Switch::new(rng.clone());
+        switch.set_latency(NETWORK_LATENCY);
+        switch.set_loss(NETWORK_LOSS);
+
         let mut entities: Vec<Box<Entity>> = Vec::new();
-        let monitor = Lem::new(Point3 {
-            x: 0.0,
-            y: 0.0,
-            z: -3.0,
-        });
-        entities.push(Box::new(monitor));
+        // Two terminals, side by side, so there's someone to talk to over the network.
+        let positions = [
+            Point3 {
+                x: 0.0,
+                y: 0.0,
+                z: -3.0,
+            },
+            Point3 {
+                x: -4.0,
+                y: 0.0,
+                z: -3.0,
+            },
+        ];
+        for (i, &position) in positions.iter().enumerate() {
+            let serial_link = options.serial_link(i as u16);
+            let mut monitor = Lem::new(position, rng.clone(), serial_link);
+            // The disk goes in the first terminal.
+            if let Some(disk) = options.disk.take() {
+                monitor.insert_disk(disk);
+            }
+            switch.connect(monitor.network_card());
+            entities.push(Box::new(monitor));
+        }
 
         World {
             player,
             collidables,
             entities,
+            switch,
             renderer: Renderer::new(display),
             debug_state: DebugState::None,
         }
@@ -143,6 +242,8 @@ impl World {
                                         EntitySlice::new(lsplit, rsplit),
                                         &self.debug_state));
         }
+
+        self.switch.tick();
     }
 
     pub fn render(&mut self, camera: &Camera) {